@@ -0,0 +1,643 @@
+use crate::error::RaucError;
+use crate::jobs::unix_now;
+use crate::tls::ClientCertConfig;
+use crate::tokens::{Scope, TokenStore};
+use crate::users::{self, NewUser, Role, UserInfo, UserStore, UserUpdate};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use rocket::http::{Cookie, CookieJar, SameSite, Status};
+use rocket::mtls::Certificate;
+use rocket::request::{FromRequest, Outcome, Request};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Mutex;
+
+/// Name of the cookie holding the session token
+pub const SESSION_COOKIE: &str = "rauc_session";
+
+/// Login settings, authentication is disabled without them
+pub struct AuthConfig {
+    /// Administrator, who can add further users through the API
+    pub username: String,
+    /// Argon2 hash of the password in PHC string format, see `hash_password`
+    pub password_hash: String,
+    /// Seconds of inactivity after which a session expires
+    pub session_timeout: u64,
+    /// Only send the session cookie over HTTPS
+    pub secure_cookie: bool,
+}
+
+struct Session {
+    username: String,
+    /// Unix timestamp in seconds, pushed back on every request
+    expires_at: u64,
+}
+
+/// Logged in user as returned by the API
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SessionInfo {
+    /// Not set if authentication is disabled
+    pub username: Option<String>,
+    /// Unix timestamp in seconds
+    pub expires_at: Option<u64>,
+    /// Subject of the client certificate identifying the user, who cannot log out
+    pub client_certificate: Option<String>,
+}
+
+/// What the requester may do, as returned by the API
+#[derive(Debug, Clone, Serialize)]
+pub struct Permissions {
+    pub name: String,
+    /// Not set for API tokens and if authentication is disabled
+    pub role: Option<Role>,
+    pub permissions: Vec<Scope>,
+}
+
+/// Password check and the sessions of logged in users
+pub struct Auth {
+    config: Option<AuthConfig>,
+    users: UserStore,
+    client_certs: Option<ClientCertConfig>,
+    /// Keyed by session token
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+/// Hash a password for use in `AUTH_PASSWORD_HASH`
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| format!("Failed to hash password: {}", e))
+}
+
+/// Hash a password without blocking the async workers, as hashing is slow by design
+pub async fn hash_password_async(password: String) -> Result<String, RaucError> {
+    tokio::task::spawn_blocking(move || hash_password(&password))
+        .await
+        .map_err(|e| RaucError::Internal(format!("Failed to hash password: {}", e)))?
+        .map_err(RaucError::Internal)
+}
+
+async fn verify_password(password_hash: String, password: String) -> Result<bool, RaucError> {
+    tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&password_hash).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    })
+    .await
+    .map_err(|e| RaucError::Internal(format!("Failed to verify password: {}", e)))
+}
+
+fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl Auth {
+    pub fn new(
+        config: Option<AuthConfig>,
+        users: UserStore,
+        client_certs: Option<ClientCertConfig>,
+    ) -> Result<Self, String> {
+        if let Some(config) = &config {
+            PasswordHash::new(&config.password_hash)
+                .map_err(|e| format!("Invalid AUTH_PASSWORD_HASH: {}", e))?;
+        }
+
+        Ok(Self {
+            config,
+            users,
+            client_certs,
+            sessions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.is_some()
+    }
+
+    /// Whether requesters are identified at all, otherwise everyone has all permissions
+    pub fn identifies_requesters(&self) -> bool {
+        self.enabled() || self.client_certs.is_some()
+    }
+
+    /// The configured administrator followed by the users added through the API
+    pub fn list_users(&self) -> Vec<UserInfo> {
+        let configured = self.config.as_ref().map(|config| UserInfo {
+            username: config.username.clone(),
+            role: Role::Admin,
+            created_by: None,
+            created_at: None,
+            configured: true,
+        });
+        configured.into_iter().chain(self.users.list()).collect()
+    }
+
+    fn check_not_configured(&self, username: &str) -> Result<(), RaucError> {
+        if self
+            .config
+            .as_ref()
+            .is_some_and(|config| config.username == username)
+        {
+            return Err(RaucError::BadRequest(format!(
+                "User {} is configured through AUTH_USERNAME and cannot be changed",
+                username
+            )));
+        }
+        Ok(())
+    }
+
+    pub async fn create_user(
+        &self,
+        user: NewUser,
+        created_by: &str,
+    ) -> Result<UserInfo, RaucError> {
+        // Users added without a password login can still be identified by client certificates
+        if !self.identifies_requesters() {
+            return Err(RaucError::BadRequest(
+                "Authentication is not enabled".to_string(),
+            ));
+        }
+        let username = user.username.trim();
+        if username.is_empty() || username.chars().any(char::is_whitespace) {
+            return Err(RaucError::BadRequest(
+                "Username must not be empty or contain whitespace".to_string(),
+            ));
+        }
+        self.check_not_configured(username)?;
+        users::validate_password(&user.password)?;
+
+        let password_hash = hash_password_async(user.password).await?;
+        self.users
+            .create(username, user.role, password_hash, created_by)
+    }
+
+    /// Change a user's role or password, a new password ends the user's sessions
+    pub async fn update_user(
+        &self,
+        username: &str,
+        update: UserUpdate,
+    ) -> Result<UserInfo, RaucError> {
+        self.check_not_configured(username)?;
+        let password_hash = match update.password {
+            Some(password) => {
+                users::validate_password(&password)?;
+                Some(hash_password_async(password).await?)
+            }
+            None => None,
+        };
+
+        let ends_sessions = password_hash.is_some();
+        let user = self.users.update(username, update.role, password_hash)?;
+        if ends_sessions {
+            self.end_sessions(username);
+        }
+        Ok(user)
+    }
+
+    pub fn delete_user(&self, username: &str) -> Result<UserInfo, RaucError> {
+        self.check_not_configured(username)?;
+        let user = self.users.delete(username)?;
+        self.end_sessions(username);
+        Ok(user)
+    }
+
+    /// Role of a user, who may only log in with a client certificate
+    fn role(&self, username: &str) -> Option<Role> {
+        if self
+            .config
+            .as_ref()
+            .is_some_and(|config| config.username == username)
+        {
+            Some(Role::Admin)
+        } else {
+            self.users.credentials(username).map(|(role, _)| role)
+        }
+    }
+
+    /// Role and password hash of a user
+    fn credentials(&self, username: &str) -> Option<(Role, String)> {
+        let config = self.config.as_ref()?;
+        if username == config.username {
+            Some((Role::Admin, config.password_hash.clone()))
+        } else {
+            self.users.credentials(username)
+        }
+    }
+
+    /// Check the credentials and start a session, setting its cookie
+    pub async fn login(
+        &self,
+        username: &str,
+        password: &str,
+        cookies: &CookieJar<'_>,
+    ) -> Result<SessionInfo, RaucError> {
+        let Some(config) = &self.config else {
+            return Err(RaucError::BadRequest(
+                "Password login is not enabled".to_string(),
+            ));
+        };
+
+        let valid = match self.credentials(username) {
+            Some((_, password_hash)) => {
+                verify_password(password_hash, password.to_string()).await?
+            }
+            None => false,
+        };
+        if !valid {
+            return Err(RaucError::Unauthorized(
+                "Invalid username or password".to_string(),
+            ));
+        }
+
+        let token = new_token();
+        let expires_at = unix_now() + config.session_timeout;
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = unix_now();
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(
+            token.clone(),
+            Session {
+                username: username.to_string(),
+                expires_at,
+            },
+        );
+
+        cookies.add(
+            Cookie::build((SESSION_COOKIE, token))
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Strict)
+                .secure(config.secure_cookie),
+        );
+
+        Ok(SessionInfo {
+            username: Some(username.to_string()),
+            expires_at: Some(expires_at),
+            client_certificate: None,
+        })
+    }
+
+    /// End the session of the cookie, if any, and remove the cookie
+    pub fn logout(&self, cookies: &CookieJar<'_>) {
+        if let Some(cookie) = cookies.get(SESSION_COOKIE) {
+            self.sessions.lock().unwrap().remove(cookie.value());
+        }
+        cookies.remove(Cookie::build(SESSION_COOKIE).path("/"));
+    }
+
+    /// End all sessions of a user, e.g. after it was deleted or its password changed
+    fn end_sessions(&self, username: &str) {
+        self.sessions
+            .lock()
+            .unwrap()
+            .retain(|_, session| session.username != username);
+    }
+
+    /// Look up a session and extend it, returning the logged in user and its current role
+    fn resume(&self, token: &str) -> Option<(SessionInfo, Role)> {
+        let config = self.config.as_ref()?;
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = unix_now();
+
+        let session = sessions.get_mut(token)?;
+        // The user may have been deleted in the meantime
+        let role = self.credentials(&session.username).map(|(role, _)| role);
+        match role {
+            Some(role) if session.expires_at > now => {
+                session.expires_at = now + config.session_timeout;
+                let info = SessionInfo {
+                    username: Some(session.username.clone()),
+                    expires_at: Some(session.expires_at),
+                    client_certificate: None,
+                };
+                Some((info, role))
+            }
+            _ => {
+                sessions.remove(token);
+                None
+            }
+        }
+    }
+
+    /// Identify the user a client certificate names, if it is mapped to a role
+    fn certificate_user(&self, certificate: &Certificate<'_>) -> Option<User> {
+        let client_certs = self.client_certs.as_ref()?;
+        let name = client_certs.identity(certificate)?;
+        let role = self.role(&name).or(client_certs.default_role)?;
+        Some(User {
+            session: Some(SessionInfo {
+                username: Some(name.clone()),
+                expires_at: None,
+                client_certificate: Some(certificate.subject().to_string()),
+            }),
+            name,
+            role: Some(role),
+            permissions: role.permissions(),
+        })
+    }
+
+    /// Identify the requester from an API token, session cookie or client certificate
+    fn authenticate(
+        &self,
+        tokens: &TokenStore,
+        request: &Request<'_>,
+        certificate: Option<&Certificate<'_>>,
+    ) -> Result<User, RaucError> {
+        if !self.identifies_requesters() {
+            let client = request
+                .client_ip()
+                .map(|ip| ip.to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            return Ok(User {
+                name: client,
+                session: None,
+                role: None,
+                permissions: Scope::ALL.to_vec(),
+            });
+        }
+
+        let bearer = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let user = match bearer {
+            Some(token) => tokens.authenticate(token.trim()).map(|token| User {
+                name: format!("token {}", token.name),
+                session: None,
+                role: None,
+                permissions: token.scopes,
+            }),
+            None => request
+                .cookies()
+                .get(SESSION_COOKIE)
+                .and_then(|cookie| self.resume(cookie.value()))
+                .map(|(session, role)| User {
+                    name: session.username.clone().unwrap_or_default(),
+                    session: Some(session),
+                    role: Some(role),
+                    permissions: role.permissions(),
+                })
+                .or_else(|| certificate.and_then(|certificate| self.certificate_user(certificate))),
+        };
+
+        user.ok_or_else(
+            || match certificate.filter(|_| self.client_certs.is_some()) {
+                Some(certificate) => RaucError::Unauthorized(format!(
+                    "Client certificate {} is not mapped to a role",
+                    certificate.subject()
+                )),
+                None => RaucError::Unauthorized("Authentication required".to_string()),
+            },
+        )
+    }
+}
+
+/// Request guard for API routes, failing with 401 unless the request carries a valid session,
+/// API token or client certificate
+///
+/// Always succeeds if authentication is disabled. Routes needing more than read access use the
+/// guards checking a permission instead, e.g. `CanInstall`.
+#[derive(Debug, Clone)]
+pub struct User {
+    /// Logged in user or API token, or the client's IP address if authentication is disabled,
+    /// recorded as the initiator of operations
+    pub name: String,
+    /// Login session the request was made with
+    pub session: Option<SessionInfo>,
+    pub role: Option<Role>,
+    /// Granted by the role or the API token's scopes
+    pub permissions: Vec<Scope>,
+}
+
+impl User {
+    /// Fail with `Forbidden` if the requester lacks `scope`
+    pub fn require(&self, scope: Scope) -> Result<(), RaucError> {
+        if self.permissions.contains(&scope) {
+            Ok(())
+        } else if self.role.is_some() {
+            Err(RaucError::Forbidden(format!(
+                "The {} permission is required",
+                scope
+            )))
+        } else {
+            Err(RaucError::Forbidden(format!(
+                "API token lacks the {} scope",
+                scope
+            )))
+        }
+    }
+
+    pub fn permissions(&self) -> Permissions {
+        Permissions {
+            name: self.name.clone(),
+            role: self.role,
+            permissions: self.permissions.clone(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for User {
+    type Error = RaucError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, RaucError> {
+        // Several guards of a route may need the user, only look it up once
+        let user = request
+            .local_cache_async(async {
+                let auth = request
+                    .rocket()
+                    .state::<Auth>()
+                    .expect("Auth is managed state");
+                let tokens = request
+                    .rocket()
+                    .state::<TokenStore>()
+                    .expect("TokenStore is managed state");
+                let certificate = request.guard::<Certificate<'_>>().await.succeeded();
+                auth.authenticate(tokens, request, certificate.as_ref())
+            })
+            .await;
+
+        match user {
+            Ok(user) => Outcome::Success(user.clone()),
+            Err(error) => {
+                request.local_cache(|| Denied(Some(error.clone())));
+                Outcome::Error((Status::Unauthorized, error.clone()))
+            }
+        }
+    }
+}
+
+/// Error of a failed authentication or permission guard, reported by the 401 and 403 catchers
+pub struct Denied(pub Option<RaucError>);
+
+/// Define a request guard that succeeds if the requester has a permission
+macro_rules! permission_guard {
+    ($(#[$doc:meta])* $name:ident, $scope:expr) => {
+        $(#[$doc])*
+        pub struct $name(pub User);
+
+        impl Deref for $name {
+            type Target = User;
+
+            fn deref(&self) -> &User {
+                &self.0
+            }
+        }
+
+        #[rocket::async_trait]
+        impl<'r> FromRequest<'r> for $name {
+            type Error = RaucError;
+
+            async fn from_request(request: &'r Request<'_>) -> Outcome<Self, RaucError> {
+                let user = rocket::outcome::try_outcome!(request.guard::<User>().await);
+                match user.require($scope) {
+                    Ok(()) => Outcome::Success($name(user)),
+                    Err(error) => {
+                        request.local_cache(|| Denied(Some(error.clone())));
+                        Outcome::Error((Status::Forbidden, error))
+                    }
+                }
+            }
+        }
+    };
+}
+
+permission_guard!(
+    /// Requester allowed to read the status, bundles and history
+    CanRead,
+    Scope::Read
+);
+permission_guard!(
+    /// Requester allowed to upload and delete bundles
+    CanUpload,
+    Scope::Upload
+);
+permission_guard!(
+    /// Requester allowed to install bundles
+    CanInstall,
+    Scope::Install
+);
+permission_guard!(
+    /// Requester allowed to reboot the system
+    CanReboot,
+    Scope::Reboot
+);
+permission_guard!(
+    /// Requester allowed to mark slots and roll back
+    CanManageSlots,
+    Scope::Slots
+);
+permission_guard!(
+    /// Requester allowed to manage users
+    CanManageUsers,
+    Scope::Users
+);
+permission_guard!(
+    /// Requester allowed to manage API tokens
+    CanManageTokens,
+    Scope::Tokens
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const TIMEOUT: u64 = 60;
+
+    fn auth(dir: &TempDir) -> Auth {
+        let config = AuthConfig {
+            username: "admin".to_string(),
+            password_hash: hash_password("administrator").unwrap(),
+            session_timeout: TIMEOUT,
+            secure_cookie: false,
+        };
+        let users = UserStore::open(dir.path().join("users.json"));
+        Auth::new(Some(config), users, None).unwrap()
+    }
+
+    /// Add a session like `login` does, without the cookie
+    fn start_session(auth: &Auth, username: &str, expires_at: u64) -> String {
+        let token = new_token();
+        let session = Session {
+            username: username.to_string(),
+            expires_at,
+        };
+        auth.sessions.lock().unwrap().insert(token.clone(), session);
+        token
+    }
+
+    #[test]
+    fn rejects_invalid_password_hashes() {
+        let dir = TempDir::new().unwrap();
+        let config = AuthConfig {
+            username: "admin".to_string(),
+            password_hash: "administrator".to_string(),
+            session_timeout: TIMEOUT,
+            secure_cookie: false,
+        };
+        let users = UserStore::open(dir.path().join("users.json"));
+        assert!(Auth::new(Some(config), users, None).is_err());
+    }
+
+    #[test]
+    fn resuming_extends_the_session() {
+        let dir = TempDir::new().unwrap();
+        let auth = auth(&dir);
+        let token = start_session(&auth, "admin", unix_now() + 5);
+
+        let (session, role) = auth.resume(&token).unwrap();
+        assert_eq!(session.username.as_deref(), Some("admin"));
+        assert!(session.expires_at.unwrap() >= unix_now() + TIMEOUT - 1);
+        assert_eq!(role, Role::Admin);
+        assert!(auth.resume("unknown").is_none());
+    }
+
+    #[test]
+    fn expired_sessions_are_ended() {
+        let dir = TempDir::new().unwrap();
+        let auth = auth(&dir);
+        let token = start_session(&auth, "admin", unix_now().saturating_sub(1));
+
+        assert!(auth.resume(&token).is_none());
+        assert!(auth.sessions.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn sessions_end_when_the_user_is_deleted() {
+        let dir = TempDir::new().unwrap();
+        let auth = auth(&dir);
+        let user = NewUser {
+            username: "operator".to_string(),
+            password: "operator password".to_string(),
+            role: Role::Operator,
+        };
+        auth.create_user(user, "admin").await.unwrap();
+        let token = start_session(&auth, "operator", unix_now() + TIMEOUT);
+        assert_eq!(auth.resume(&token).unwrap().1, Role::Operator);
+
+        auth.delete_user("operator").unwrap();
+        assert!(auth.resume(&token).is_none());
+
+        // Sessions of users that vanished otherwise are not resumed either
+        let token = start_session(&auth, "ghost", unix_now() + TIMEOUT);
+        assert!(auth.resume(&token).is_none());
+        assert!(auth.sessions.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn sessions_are_not_resumed_without_password_login() {
+        let dir = TempDir::new().unwrap();
+        let users = UserStore::open(dir.path().join("users.json"));
+        let auth = Auth::new(None, users, None).unwrap();
+        let token = start_session(&auth, "admin", unix_now() + TIMEOUT);
+        assert!(auth.resume(&token).is_none());
+    }
+}
@@ -0,0 +1,157 @@
+use super::{InstallOutput, InstallStream, StreamingOptions};
+use crate::error::RaucError;
+use crate::rauc::{RaucBundleInfo, RaucStatus, SlotMark};
+use std::process::Output;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Child;
+
+/// Extract stdout from a finished rauc command, failing on a non-zero exit status
+pub fn command_stdout(output: Output) -> Result<String, RaucError> {
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(RaucError::from_rauc_stderr(&stderr));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+pub fn parse_status(stdout: &str) -> Result<RaucStatus, RaucError> {
+    serde_json::from_str(stdout)
+        .map_err(|e| RaucError::InvalidOutput(format!("Failed to parse rauc status JSON: {}", e)))
+}
+
+pub fn parse_bundle_info(stdout: &str) -> Result<RaucBundleInfo, RaucError> {
+    serde_json::from_str(stdout).map_err(|e| {
+        RaucError::InvalidOutput(format!("Failed to parse rauc bundle info JSON: {}", e))
+    })
+}
+
+/// Command line options of `rauc info` and `rauc install` for streaming a bundle
+pub fn streaming_args(options: &StreamingOptions) -> Vec<String> {
+    let mut args: Vec<String> = options
+        .http_headers
+        .iter()
+        .map(|header| format!("--http-header={}", header))
+        .collect();
+
+    if let Some(ca) = &options.tls_ca {
+        args.push(format!("--tls-ca={}", ca));
+    }
+    if let Some(cert) = &options.tls_cert {
+        args.push(format!("--tls-cert={}", cert));
+    }
+    if let Some(key) = &options.tls_key {
+        args.push(format!("--tls-key={}", key));
+    }
+    if options.tls_no_verify {
+        args.push("--tls-no-verify".to_string());
+    }
+
+    args
+}
+
+/// Command line of `rauc status mark-good|mark-bad|mark-active`
+pub fn mark_args(slot: &str, mark: SlotMark) -> Vec<String> {
+    vec![
+        "status".to_string(),
+        format!("mark-{}", mark.as_str()),
+        slot.to_string(),
+    ]
+}
+
+/// Message for a successful mark, rauc only prints one to stderr
+pub fn mark_message(slot: &str, mark: SlotMark) -> String {
+    format!("Marked slot {} as {}", slot, mark.as_str())
+}
+
+fn read_error(error: std::io::Error) -> InstallOutput {
+    InstallOutput::Stderr(format!("Failed to read output: {}", error))
+}
+
+/// Forward the output of a spawned `rauc install` process line by line
+pub fn install_output_stream(mut child: Child) -> Result<InstallStream, RaucError> {
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| RaucError::Internal("Failed to capture stdout".to_string()))?;
+    let stderr = child
+        .stderr
+        .take()
+        .ok_or_else(|| RaucError::Internal("Failed to capture stderr".to_string()))?;
+
+    let stream = async_stream::stream! {
+        let mut stdout_reader = BufReader::new(stdout).lines();
+        let mut stderr_reader = BufReader::new(stderr).lines();
+
+        loop {
+            tokio::select! {
+                result = stdout_reader.next_line() => {
+                    match result {
+                        Ok(Some(line)) => yield InstallOutput::Stdout(line),
+                        Ok(None) => break,
+                        Err(e) => yield read_error(e),
+                    }
+                }
+                result = stderr_reader.next_line() => {
+                    match result {
+                        Ok(Some(line)) => yield InstallOutput::Stderr(line),
+                        Ok(None) => {},
+                        Err(e) => yield read_error(e),
+                    }
+                }
+            }
+        }
+
+        // Wait for process to complete
+        match child.wait().await {
+            Ok(status) => {
+                let result = if status.success() {
+                    Ok(())
+                } else {
+                    Err(format!("Installation failed with status: {}", status))
+                };
+                yield InstallOutput::Finished { result, exit_status: status.code() };
+            }
+            Err(e) => {
+                yield InstallOutput::Finished {
+                    result: Err(format!("Failed to wait for process: {}", e)),
+                    exit_status: None,
+                };
+            }
+        }
+    };
+
+    Ok(Box::pin(stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streaming_args_match_rauc_options() {
+        assert!(streaming_args(&StreamingOptions::default()).is_empty());
+
+        let options = StreamingOptions {
+            http_headers: vec![
+                "Authorization: Bearer secret".to_string(),
+                "X-Device: 42".to_string(),
+            ],
+            tls_ca: Some("/etc/rauc/ca.pem".to_string()),
+            tls_cert: Some("/etc/rauc/client.pem".to_string()),
+            tls_key: Some("/etc/rauc/client.key".to_string()),
+            tls_no_verify: true,
+        };
+        assert_eq!(
+            streaming_args(&options),
+            [
+                "--http-header=Authorization: Bearer secret",
+                "--http-header=X-Device: 42",
+                "--tls-ca=/etc/rauc/ca.pem",
+                "--tls-cert=/etc/rauc/client.pem",
+                "--tls-key=/etc/rauc/client.key",
+                "--tls-no-verify",
+            ]
+        );
+    }
+}
@@ -0,0 +1,929 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde_json::{Map, Value as JsonValue};
+use tokio::sync::OnceCell;
+use tokio_stream::StreamExt;
+use zbus::zvariant::{OwnedValue, Value};
+use zbus::{proxy, Connection};
+
+use super::{local, InstallOutput, InstallStream, RaucBackend, StreamingOptions};
+use crate::error::RaucError;
+use crate::rauc::{RaucBundleInfo, RaucSlot, RaucStatus, SlotMark};
+
+/// Proxy for the `de.pengutronix.rauc.Installer` interface exported by the RAUC service
+#[proxy(
+    interface = "de.pengutronix.rauc.Installer",
+    default_service = "de.pengutronix.rauc",
+    default_path = "/"
+)]
+pub trait Installer {
+    fn install_bundle(&self, source: &str, args: HashMap<&str, Value<'_>>) -> zbus::Result<()>;
+
+    fn inspect_bundle(
+        &self,
+        source: &str,
+        args: HashMap<&str, Value<'_>>,
+    ) -> zbus::Result<HashMap<String, OwnedValue>>;
+
+    fn info(&self, bundle: &str) -> zbus::Result<(String, String)>;
+
+    fn get_slot_status(&self) -> zbus::Result<Vec<(String, HashMap<String, OwnedValue>)>>;
+
+    fn get_primary(&self) -> zbus::Result<String>;
+
+    fn mark(&self, state: &str, slot_identifier: &str) -> zbus::Result<(String, String)>;
+
+    #[zbus(signal)]
+    fn completed(&self, result: i32) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn operation(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn last_error(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn progress(&self) -> zbus::Result<(i32, String, i32)>;
+
+    #[zbus(property)]
+    fn compatible(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn variant(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn boot_slot(&self) -> zbus::Result<String>;
+}
+
+/// Message bus the RAUC service is reached on
+#[derive(Clone, Copy, Debug)]
+pub enum DBusBus {
+    /// The system bus, where the real RAUC service lives
+    System,
+    /// The session bus, used for testing against a stand-in service
+    Session,
+}
+
+impl DBusBus {
+    async fn connect(&self) -> Result<Connection, RaucError> {
+        let connection = match self {
+            DBusBus::System => Connection::system().await,
+            DBusBus::Session => Connection::session().await,
+        };
+
+        connection
+            .map_err(|e| RaucError::TargetUnreachable(format!("Failed to connect to D-Bus: {}", e)))
+    }
+}
+
+/// Backend talking to the RAUC service via its D-Bus API
+#[derive(Clone, Debug)]
+pub struct DBusBackend {
+    bus: DBusBus,
+    /// Connection to `bus`, opened on first use and shared by all requests
+    connection: Arc<OnceCell<Connection>>,
+}
+
+impl DBusBackend {
+    pub fn new(bus: DBusBus) -> Self {
+        Self {
+            bus,
+            connection: Arc::new(OnceCell::new()),
+        }
+    }
+
+    /// Backend using an established connection, e.g. to a stand-in service in tests
+    #[cfg(test)]
+    fn with_connection(connection: Connection) -> Self {
+        Self {
+            bus: DBusBus::Session,
+            connection: Arc::new(OnceCell::new_with(Some(connection))),
+        }
+    }
+
+    async fn proxy(&self) -> Result<InstallerProxy<'static>, RaucError> {
+        let connection = self
+            .connection
+            .get_or_try_init(|| self.bus.connect())
+            .await?;
+        InstallerProxy::new(connection)
+            .await
+            .map_err(|e| dbus_error("Failed to create RAUC D-Bus proxy", e))
+    }
+}
+
+#[async_trait]
+impl RaucBackend for DBusBackend {
+    async fn get_status(&self) -> Result<RaucStatus, RaucError> {
+        let proxy = self.proxy().await?;
+
+        let compatible = proxy
+            .compatible()
+            .await
+            .map_err(|e| dbus_error("Failed to read Compatible property", e))?;
+        let variant = proxy
+            .variant()
+            .await
+            .map_err(|e| dbus_error("Failed to read Variant property", e))?;
+        let booted = proxy
+            .boot_slot()
+            .await
+            .map_err(|e| dbus_error("Failed to read BootSlot property", e))?;
+        // GetPrimary fails on bootloaders that cannot report the primary slot
+        let boot_primary = proxy.get_primary().await.ok();
+
+        let slots = proxy
+            .get_slot_status()
+            .await
+            .map_err(|e| dbus_error("Failed to call GetSlotStatus", e))?
+            .into_iter()
+            .map(|(name, properties)| {
+                let slot: RaucSlot =
+                    serde_json::from_value(slot_to_json(&properties)).map_err(|e| {
+                        RaucError::InvalidOutput(format!(
+                            "Failed to parse status of slot {}: {}",
+                            name, e
+                        ))
+                    })?;
+                Ok(BTreeMap::from([(name, slot)]))
+            })
+            .collect::<Result<_, RaucError>>()?;
+
+        Ok(RaucStatus {
+            compatible,
+            variant,
+            booted,
+            boot_primary,
+            slots,
+            artifact_repositories: Vec::new(),
+            extra: Default::default(),
+        })
+    }
+
+    async fn get_bundle_info(
+        &self,
+        bundle: &str,
+        streaming: &StreamingOptions,
+    ) -> Result<RaucBundleInfo, RaucError> {
+        let proxy = self.proxy().await?;
+
+        let info = match proxy
+            .inspect_bundle(bundle, streaming_args(streaming))
+            .await
+        {
+            Ok(info) => info,
+            // RAUC before 1.8 only has Info, which takes no streaming arguments
+            Err(zbus::Error::MethodError(name, _, _))
+                if name.as_str() == "org.freedesktop.DBus.Error.UnknownMethod" =>
+            {
+                return legacy_bundle_info(&proxy, bundle, streaming).await;
+            }
+            Err(e) => return Err(dbus_error("Failed to call InspectBundle", e)),
+        };
+
+        serde_json::from_value(bundle_to_json(&info)).map_err(|e| {
+            RaucError::InvalidOutput(format!("Failed to parse bundle info of {}: {}", bundle, e))
+        })
+    }
+
+    async fn install_bundle(
+        &self,
+        bundle: &str,
+        streaming: &StreamingOptions,
+    ) -> Result<InstallStream, RaucError> {
+        let proxy = self.proxy().await?;
+
+        let operation = proxy
+            .operation()
+            .await
+            .map_err(|e| dbus_error("Failed to read Operation property", e))?;
+        if operation != "idle" {
+            return Err(RaucError::Busy(format!(
+                "RAUC is busy (current operation: {})",
+                operation
+            )));
+        }
+
+        // Subscribe before starting the installation so no update is missed
+        let mut progress = proxy.receive_progress_changed().await;
+        let mut completed = proxy
+            .receive_completed()
+            .await
+            .map_err(|e| dbus_error("Failed to subscribe to Completed signal", e))?;
+
+        proxy
+            .install_bundle(bundle, streaming_args(streaming))
+            .await
+            .map_err(|e| dbus_error("Failed to call InstallBundle", e))?;
+
+        let bundle = bundle.to_string();
+        let stream = async_stream::stream! {
+            yield InstallOutput::Stdout(format!("installing {}", bundle));
+
+            loop {
+                tokio::select! {
+                    Some(change) = progress.next() => {
+                        if let Ok((percentage, message, _depth)) = change.get().await {
+                            yield InstallOutput::Stdout(format!("{:>3}% {}", percentage, message));
+                        }
+                    }
+                    signal = completed.next() => {
+                        let result = signal
+                            .and_then(|signal| signal.args().ok().map(|args| args.result))
+                            .unwrap_or(-1);
+
+                        let outcome = if result == 0 {
+                            Ok(())
+                        } else {
+                            let last_error = proxy.last_error().await.unwrap_or_default();
+                            Err(format!("Installation failed: {}", last_error))
+                        };
+                        yield InstallOutput::Finished { result: outcome, exit_status: Some(result) };
+                        break;
+                    }
+                }
+            }
+        };
+
+        Ok(Box::pin(stream))
+    }
+
+    async fn mark_slot(&self, slot: &str, mark: SlotMark) -> Result<String, RaucError> {
+        let (_slot_name, message) = self
+            .proxy()
+            .await?
+            .mark(mark.as_str(), slot)
+            .await
+            .map_err(|e| dbus_error("Failed to call Mark", e))?;
+        Ok(message)
+    }
+
+    async fn reboot(&self) -> Result<String, RaucError> {
+        // RAUC has no reboot method, the service runs on the target itself
+        local::reboot_local().await
+    }
+}
+
+/// Bundle info via Info, which only reports compatible and version
+async fn legacy_bundle_info(
+    proxy: &InstallerProxy<'_>,
+    bundle: &str,
+    streaming: &StreamingOptions,
+) -> Result<RaucBundleInfo, RaucError> {
+    if !streaming.is_empty() {
+        return Err(RaucError::BadRequest(
+            "The RAUC service is too old to inspect bundles with streaming headers or TLS options"
+                .to_string(),
+        ));
+    }
+
+    let (compatible, version) = proxy
+        .info(bundle)
+        .await
+        .map_err(|e| dbus_error("Failed to call Info", e))?;
+
+    Ok(RaucBundleInfo {
+        compatible,
+        version,
+        description: None,
+        build: None,
+        format: None,
+        hooks: Vec::new(),
+        hash: None,
+        images: Vec::new(),
+        meta: BTreeMap::new(),
+        extra: Default::default(),
+    })
+}
+
+/// InspectBundle and InstallBundle arguments for streaming a bundle, named like rauc's command
+/// line options
+fn streaming_args(options: &StreamingOptions) -> HashMap<&'static str, Value<'static>> {
+    let mut args = HashMap::new();
+
+    if !options.http_headers.is_empty() {
+        args.insert("http-headers", Value::from(options.http_headers.clone()));
+    }
+    if let Some(ca) = &options.tls_ca {
+        args.insert("tls-ca", Value::from(ca.clone()));
+    }
+    if let Some(cert) = &options.tls_cert {
+        args.insert("tls-cert", Value::from(cert.clone()));
+    }
+    if let Some(key) = &options.tls_key {
+        args.insert("tls-key", Value::from(key.clone()));
+    }
+    if options.tls_no_verify {
+        args.insert("tls-no-verify", Value::from(true));
+    }
+
+    args
+}
+
+/// Classify a failed D-Bus call, using the error message RAUC attached to it
+fn dbus_error(context: &str, error: zbus::Error) -> RaucError {
+    match &error {
+        zbus::Error::MethodError(name, _, _)
+            if name.as_str() == "org.freedesktop.DBus.Error.ServiceUnknown"
+                || name.as_str() == "org.freedesktop.DBus.Error.NameHasNoOwner" =>
+        {
+            RaucError::TargetUnreachable(format!("{}: RAUC service is not running", context))
+        }
+        zbus::Error::MethodError(_, Some(message), _) => RaucError::from_rauc_stderr(message),
+        zbus::Error::InputOutput(_) | zbus::Error::Address(_) | zbus::Error::Handshake(_) => {
+            RaucError::TargetUnreachable(format!("{}: {}", context, error))
+        }
+        _ => RaucError::CommandFailed {
+            message: context.to_string(),
+            stderr: error.to_string(),
+        },
+    }
+}
+
+/// Convert a GetSlotStatus dictionary into the layout used by `rauc status --output-format=json`
+fn slot_to_json(properties: &HashMap<String, OwnedValue>) -> JsonValue {
+    let mut slot = Map::new();
+    let mut slot_status = Map::new();
+
+    for (key, value) in properties {
+        let value = value_to_json(value);
+        match key.as_str() {
+            "class" | "device" | "type" | "bootname" | "state" | "parent" | "mountpoint" => {
+                slot.insert(key.clone(), value);
+            }
+            "boot-status" => {
+                slot.insert("boot_status".to_string(), value);
+            }
+            "sha256" | "size" => {
+                insert_nested(&mut slot_status, &["checksum", key], value);
+            }
+            _ => {
+                let path: Vec<&str> = key.split('.').collect();
+                insert_nested(&mut slot_status, &path, value);
+            }
+        }
+    }
+
+    if !slot_status.is_empty() {
+        slot.insert("slot_status".to_string(), JsonValue::Object(slot_status));
+    }
+
+    JsonValue::Object(slot)
+}
+
+/// Convert an InspectBundle dictionary into the layout used by `rauc info --output-format=json`
+fn bundle_to_json(info: &HashMap<String, OwnedValue>) -> JsonValue {
+    let field = |key: &str| info.get(key).map(|value| value_to_json(value));
+    let mut bundle = Map::new();
+
+    // compatible, version, description and build
+    if let Some(JsonValue::Object(update)) = field("update") {
+        bundle.extend(update);
+    }
+    if let Some(format) = field("bundle").and_then(|details| details.get("format").cloned()) {
+        bundle.insert("format".to_string(), format);
+    }
+    if let Some(hash) = field("manifest-hash") {
+        bundle.insert("hash".to_string(), hash);
+    }
+    if let Some(hooks) = field("hooks") {
+        bundle.insert("hooks".to_string(), hook_names(hooks));
+    }
+    if let Some(JsonValue::Array(images)) = field("images") {
+        let images = images
+            .into_iter()
+            .filter_map(|image| {
+                let JsonValue::Object(mut image) = image else {
+                    return None;
+                };
+                let class = image.remove("slot-class")?.as_str()?.to_string();
+                if let Some(hooks) = image.remove("hooks") {
+                    image.insert("hooks".to_string(), hook_names(hooks));
+                }
+                Some(JsonValue::Object(Map::from_iter([(
+                    class,
+                    JsonValue::Object(image),
+                )])))
+            })
+            .collect();
+        bundle.insert("images".to_string(), JsonValue::Array(images));
+    }
+    if let Some(meta) = field("meta") {
+        bundle.insert("meta".to_string(), meta);
+    }
+
+    JsonValue::Object(bundle)
+}
+
+/// Hooks as a list of names, RAUC reports them as a list or as a dictionary of flags
+fn hook_names(hooks: JsonValue) -> JsonValue {
+    match hooks {
+        JsonValue::Object(hooks) => hooks
+            .into_iter()
+            .filter(|(_, enabled)| enabled.as_bool() != Some(false))
+            .map(|(name, _)| JsonValue::String(name))
+            .collect(),
+        other => other,
+    }
+}
+
+fn insert_nested(map: &mut Map<String, JsonValue>, path: &[&str], value: JsonValue) {
+    match path {
+        [] => {}
+        [key] => {
+            map.insert(key.to_string(), value);
+        }
+        [key, rest @ ..] => {
+            let entry = map
+                .entry(key.to_string())
+                .or_insert_with(|| JsonValue::Object(Map::new()));
+            if let JsonValue::Object(child) = entry {
+                insert_nested(child, rest, value);
+            }
+        }
+    }
+}
+
+fn value_to_json(value: &Value<'_>) -> JsonValue {
+    match value {
+        Value::Bool(v) => JsonValue::from(*v),
+        Value::U8(v) => JsonValue::from(*v),
+        Value::I16(v) => JsonValue::from(*v),
+        Value::U16(v) => JsonValue::from(*v),
+        Value::I32(v) => JsonValue::from(*v),
+        Value::U32(v) => JsonValue::from(*v),
+        Value::I64(v) => JsonValue::from(*v),
+        Value::U64(v) => JsonValue::from(*v),
+        Value::F64(v) => JsonValue::from(*v),
+        Value::Str(v) => JsonValue::from(v.as_str()),
+        Value::ObjectPath(v) => JsonValue::from(v.as_str()),
+        Value::Signature(v) => JsonValue::from(v.to_string()),
+        Value::Value(v) => value_to_json(v),
+        Value::Array(v) => v.inner().iter().map(value_to_json).collect(),
+        Value::Structure(v) => v.fields().iter().map(value_to_json).collect(),
+        Value::Dict(v) => JsonValue::Object(
+            v.iter()
+                .map(|(key, value)| {
+                    let key = match key {
+                        Value::Str(key) => key.to_string(),
+                        other => other.to_string(),
+                    };
+                    (key, value_to_json(value))
+                })
+                .collect(),
+        ),
+        other => JsonValue::from(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rauc::{BootStatus, SlotState};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use zbus::object_server::SignalEmitter;
+    use zbus::{connection, fdo, interface, Guid};
+
+    fn owned<'a>(value: impl Into<Value<'a>>) -> OwnedValue {
+        value.into().try_to_owned().unwrap()
+    }
+
+    /// Stand-in for the RAUC service, with a booted slot `rootfs.0` and an inactive `rootfs.1`
+    #[derive(Default)]
+    struct StandIn {
+        last_error: String,
+        progress: (i32, String, i32),
+        /// Method, source and argument names of InspectBundle and InstallBundle calls
+        calls: Calls,
+    }
+
+    #[interface(name = "de.pengutronix.rauc.Installer")]
+    impl StandIn {
+        async fn install_bundle(
+            &mut self,
+            source: String,
+            args: HashMap<String, OwnedValue>,
+            #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        ) -> fdo::Result<()> {
+            let names = argument_names(args);
+            self.calls
+                .lock()
+                .unwrap()
+                .push(("install", source.clone(), names));
+
+            if source.contains("missing") {
+                return Err(fdo::Error::Failed(format!(
+                    "Failed to open bundle {}: No such file or directory",
+                    source
+                )));
+            }
+
+            let result = if source.contains("broken") {
+                self.last_error = "Failed to check bundle signature".to_string();
+                self.last_error_changed(&emitter).await?;
+                1
+            } else {
+                0
+            };
+            self.progress = (40, "Copying image to rootfs.1".to_string(), 2);
+            self.progress_changed(&emitter).await?;
+
+            // Completed follows the method return, like in the real service
+            let emitter = emitter.to_owned();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                let _ = StandIn::completed(&emitter, result).await;
+            });
+            Ok(())
+        }
+
+        fn inspect_bundle(
+            &self,
+            source: String,
+            args: HashMap<String, OwnedValue>,
+        ) -> fdo::Result<HashMap<String, OwnedValue>> {
+            let names = argument_names(args);
+            self.calls
+                .lock()
+                .unwrap()
+                .push(("inspect", source.clone(), names));
+
+            if source.contains("corrupt") {
+                return Err(fdo::Error::Failed(
+                    "Invalid bundle: squashfs superblock not found".to_string(),
+                ));
+            }
+            // RAUC before 1.8
+            if source.contains("legacy") {
+                return Err(fdo::Error::UnknownMethod(
+                    "No such method InspectBundle".to_string(),
+                ));
+            }
+
+            let update = HashMap::from([
+                ("compatible", Value::from("demo-board")),
+                ("version", Value::from("1.3.0")),
+                ("description", Value::from("Demo update")),
+                ("build", Value::from("20261001")),
+            ]);
+            let bundle = HashMap::from([("format", Value::from("verity"))]);
+            let image = HashMap::from([
+                ("slot-class", Value::from("rootfs")),
+                ("filename", Value::from("rootfs.ext4")),
+                ("checksum", Value::from("c0ffee")),
+                ("size", Value::from(1048576u64)),
+                (
+                    "hooks",
+                    Value::from(HashMap::from([("post-install", true)])),
+                ),
+            ]);
+            let meta = HashMap::from([(
+                "update",
+                HashMap::from([("channel", Value::from("stable"))]),
+            )]);
+            Ok(HashMap::from([
+                ("manifest-hash".to_string(), owned("5eed")),
+                ("update".to_string(), owned(update)),
+                ("bundle".to_string(), owned(bundle)),
+                ("hooks".to_string(), owned(vec!["install-check"])),
+                ("images".to_string(), owned(vec![image])),
+                ("meta".to_string(), owned(meta)),
+            ]))
+        }
+
+        fn info(&self, _bundle: &str) -> fdo::Result<(String, String)> {
+            Ok(("demo-board".to_string(), "1.2.9".to_string()))
+        }
+
+        fn get_slot_status(&self) -> Vec<(String, HashMap<String, OwnedValue>)> {
+            let booted = HashMap::from([
+                ("class".to_string(), owned("rootfs")),
+                ("device".to_string(), owned("/dev/mmcblk0p2")),
+                ("type".to_string(), owned("ext4")),
+                ("bootname".to_string(), owned("A")),
+                ("state".to_string(), owned("booted")),
+                ("boot-status".to_string(), owned("good")),
+                ("bundle.compatible".to_string(), owned("demo-board")),
+                ("bundle.version".to_string(), owned("1.2.0")),
+                (
+                    "installed.timestamp".to_string(),
+                    owned("2026-10-01T08:00:00Z"),
+                ),
+                ("installed.count".to_string(), owned(3u32)),
+                ("sha256".to_string(), owned("c0ffee")),
+                ("size".to_string(), owned(1048576u64)),
+                ("status".to_string(), owned("ok")),
+            ]);
+            let inactive = HashMap::from([
+                ("class".to_string(), owned("rootfs")),
+                ("device".to_string(), owned("/dev/mmcblk0p3")),
+                ("type".to_string(), owned("ext4")),
+                ("bootname".to_string(), owned("B")),
+                ("state".to_string(), owned("inactive")),
+                ("boot-status".to_string(), owned("bad")),
+            ]);
+            vec![
+                ("rootfs.0".to_string(), booted),
+                ("rootfs.1".to_string(), inactive),
+            ]
+        }
+
+        fn get_primary(&self) -> fdo::Result<String> {
+            Ok("rootfs.0".to_string())
+        }
+
+        fn mark(&self, state: &str, slot_identifier: &str) -> fdo::Result<(String, String)> {
+            match slot_identifier {
+                "rootfs.0" | "rootfs.1" => Ok((
+                    slot_identifier.to_string(),
+                    format!("marked slot {} as {}", slot_identifier, state),
+                )),
+                // What the bus replies when the RAUC service is not running
+                "gone" => Err(fdo::Error::ServiceUnknown(
+                    "The name de.pengutronix.rauc was not provided by any .service files"
+                        .to_string(),
+                )),
+                "locked" => Err(fdo::Error::Failed(
+                    "Operation already in progress".to_string(),
+                )),
+                other => Err(fdo::Error::Failed(format!(
+                    "No slot with name {} found",
+                    other
+                ))),
+            }
+        }
+
+        #[zbus(signal)]
+        async fn completed(emitter: &SignalEmitter<'_>, result: i32) -> zbus::Result<()>;
+
+        #[zbus(property)]
+        fn operation(&self) -> String {
+            "idle".to_string()
+        }
+
+        #[zbus(property)]
+        fn last_error(&self) -> String {
+            self.last_error.clone()
+        }
+
+        #[zbus(property)]
+        fn progress(&self) -> (i32, String, i32) {
+            self.progress.clone()
+        }
+
+        #[zbus(property)]
+        fn compatible(&self) -> String {
+            "demo-board".to_string()
+        }
+
+        #[zbus(property)]
+        fn variant(&self) -> String {
+            "dev".to_string()
+        }
+
+        #[zbus(property)]
+        fn boot_slot(&self) -> String {
+            "A".to_string()
+        }
+    }
+
+    type Calls = Arc<Mutex<Vec<(&'static str, String, Vec<String>)>>>;
+
+    fn argument_names(args: HashMap<String, OwnedValue>) -> Vec<String> {
+        let mut names: Vec<String> = args.into_keys().collect();
+        names.sort();
+        names
+    }
+
+    /// Backend connected peer-to-peer to a stand-in, which serves as long as the returned
+    /// connection is kept
+    async fn backend() -> (DBusBackend, Calls, Connection) {
+        let stand_in = StandIn::default();
+        let calls = stand_in.calls.clone();
+        let (server, client) = tokio::net::UnixStream::pair().unwrap();
+        let server = connection::Builder::unix_stream(server)
+            .server(Guid::generate())
+            .unwrap()
+            .p2p()
+            .serve_at("/", stand_in)
+            .unwrap()
+            .build();
+        let client = connection::Builder::unix_stream(client).p2p().build();
+        let (server, client) = tokio::try_join!(server, client).unwrap();
+
+        (DBusBackend::with_connection(client), calls, server)
+    }
+
+    async fn collect(stream: InstallStream) -> Vec<InstallOutput> {
+        tokio::time::timeout(Duration::from_secs(5), stream.collect())
+            .await
+            .expect("installation did not finish")
+    }
+
+    #[tokio::test]
+    async fn status_maps_slot_properties_to_rauc_json_layout() {
+        let (backend, _, _server) = backend().await;
+        let status = backend.get_status().await.unwrap();
+
+        assert_eq!(status.compatible, "demo-board");
+        assert_eq!(status.variant, "dev");
+        assert_eq!(status.booted, "A");
+        assert_eq!(status.boot_primary.as_deref(), Some("rootfs.0"));
+        let slots: Vec<_> = status.slots().map(|(name, _)| name).collect();
+        assert_eq!(slots, ["rootfs.0", "rootfs.1"]);
+
+        let (_, booted) = status.booted_slot().unwrap();
+        assert_eq!(booted.class, "rootfs");
+        assert_eq!(booted.device, "/dev/mmcblk0p2");
+        assert_eq!(booted.slot_type, "ext4");
+        assert_eq!(booted.bootname.as_deref(), Some("A"));
+        assert_eq!(booted.boot_status, Some(BootStatus::Good));
+        assert_eq!(status.booted_version(), Some("1.2.0"));
+
+        let slot_status = booted.slot_status.as_ref().unwrap();
+        let bundle = slot_status.bundle.as_ref().unwrap();
+        assert_eq!(bundle.compatible.as_deref(), Some("demo-board"));
+        let installed = slot_status.installed.as_ref().unwrap();
+        assert_eq!(installed.timestamp.as_deref(), Some("2026-10-01T08:00:00Z"));
+        assert_eq!(installed.count, Some(3));
+        let checksum = slot_status.checksum.as_ref().unwrap();
+        assert_eq!(checksum.sha256.as_deref(), Some("c0ffee"));
+        assert_eq!(checksum.size, Some(1048576));
+        assert_eq!(slot_status.status.as_deref(), Some("ok"));
+
+        let (_, inactive) = status.slots().nth(1).unwrap();
+        assert_eq!(inactive.state, SlotState::Inactive);
+        assert_eq!(inactive.boot_status, Some(BootStatus::Bad));
+        assert!(inactive.slot_status.is_none());
+    }
+
+    #[tokio::test]
+    async fn install_reports_progress_and_completion() {
+        let (backend, calls, _server) = backend().await;
+        let stream = backend
+            .install_bundle("/data/update.raucb", &StreamingOptions::default())
+            .await
+            .unwrap();
+        let output = collect(stream).await;
+
+        assert!(
+            matches!(&output[0], InstallOutput::Stdout(line) if line == "installing /data/update.raucb")
+        );
+        assert!(output.iter().any(
+            |line| matches!(line, InstallOutput::Stdout(line) if line == " 40% Copying image to rootfs.1")
+        ));
+        assert!(matches!(
+            output.last(),
+            Some(InstallOutput::Finished {
+                result: Ok(()),
+                exit_status: Some(0)
+            })
+        ));
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [("install", "/data/update.raucb".to_string(), Vec::new())]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_install_reports_last_error() {
+        let (backend, _, _server) = backend().await;
+        let stream = backend
+            .install_bundle("/data/broken.raucb", &StreamingOptions::default())
+            .await
+            .unwrap();
+
+        match collect(stream).await.last() {
+            Some(InstallOutput::Finished {
+                result: Err(message),
+                exit_status: Some(1),
+            }) => assert_eq!(
+                message,
+                "Installation failed: Failed to check bundle signature"
+            ),
+            other => panic!("unexpected end of installation: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn errors_are_classified() {
+        let (backend, _, _server) = backend().await;
+
+        let error = backend
+            .install_bundle("/data/missing.raucb", &StreamingOptions::default())
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(error, RaucError::NoBundle), "{:?}", error);
+
+        let error = backend
+            .get_bundle_info("/data/corrupt.raucb", &StreamingOptions::default())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, RaucError::InvalidBundle { .. }),
+            "{:?}",
+            error
+        );
+
+        let error = backend.mark_slot("gone", SlotMark::Good).await.unwrap_err();
+        assert!(
+            matches!(error, RaucError::TargetUnreachable(_)),
+            "{:?}",
+            error
+        );
+
+        let error = backend
+            .mark_slot("locked", SlotMark::Good)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, RaucError::Busy(_)), "{:?}", error);
+
+        let error = backend
+            .mark_slot("rootfs.7", SlotMark::Bad)
+            .await
+            .unwrap_err();
+        match error {
+            RaucError::CommandFailed { stderr, .. } => {
+                assert_eq!(stderr, "No slot with name rootfs.7 found")
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        let message = backend
+            .mark_slot("rootfs.1", SlotMark::Active)
+            .await
+            .unwrap();
+        assert_eq!(message, "marked slot rootfs.1 as active");
+    }
+
+    fn streaming() -> StreamingOptions {
+        StreamingOptions {
+            http_headers: vec!["Authorization: Bearer secret".to_string()],
+            tls_ca: Some("/etc/rauc/streaming-ca.pem".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn inspects_bundles_with_streaming_args() {
+        let (backend, calls, _server) = backend().await;
+        let url = "https://updates.example.com/update.raucb";
+        let info = backend.get_bundle_info(url, &streaming()).await.unwrap();
+
+        assert_eq!(info.compatible, "demo-board");
+        assert_eq!(info.version, "1.3.0");
+        assert_eq!(info.description.as_deref(), Some("Demo update"));
+        assert_eq!(info.build.as_deref(), Some("20261001"));
+        assert_eq!(info.format.as_deref(), Some("verity"));
+        assert_eq!(info.hash.as_deref(), Some("5eed"));
+        assert_eq!(info.hooks, ["install-check"]);
+        let image = &info.images[0]["rootfs"];
+        assert_eq!(image.filename.as_deref(), Some("rootfs.ext4"));
+        assert_eq!(image.checksum.as_deref(), Some("c0ffee"));
+        assert_eq!(image.size, Some(1048576));
+        assert_eq!(image.hooks, ["post-install"]);
+        assert_eq!(info.meta["update"]["channel"], "stable");
+
+        let names = vec!["http-headers".to_string(), "tls-ca".to_string()];
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [("inspect", url.to_string(), names)]
+        );
+    }
+
+    #[tokio::test]
+    async fn installs_with_streaming_args() {
+        let (backend, calls, _server) = backend().await;
+        let url = "https://updates.example.com/update.raucb";
+        let stream = backend.install_bundle(url, &streaming()).await.unwrap();
+        collect(stream).await;
+
+        let names = vec!["http-headers".to_string(), "tls-ca".to_string()];
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [("install", url.to_string(), names)]
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_info_without_inspect_bundle() {
+        let (backend, _, _server) = backend().await;
+        let bundle = "/data/legacy.raucb";
+
+        let info = backend
+            .get_bundle_info(bundle, &StreamingOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(info.version, "1.2.9");
+
+        let error = backend
+            .get_bundle_info(bundle, &streaming())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, RaucError::BadRequest(_)), "{:?}", error);
+    }
+}
@@ -0,0 +1,101 @@
+use super::{cli, InstallStream, RaucBackend, StreamingOptions};
+use crate::error::RaucError;
+use crate::rauc::{RaucBundleInfo, RaucStatus, SlotMark};
+use async_trait::async_trait;
+use std::process::Stdio;
+use tokio::process::Command;
+
+/// Backend running the `rauc` binary directly on the target system
+#[derive(Clone, Default)]
+pub struct LocalBackend;
+
+impl LocalBackend {
+    pub fn new() -> Self {
+        Self
+    }
+
+    async fn execute_command(&self, args: &[&str]) -> Result<String, RaucError> {
+        let output = Command::new("rauc")
+            .args(args)
+            .output()
+            .await
+            .map_err(|e| RaucError::Internal(format!("Failed to execute rauc command: {}", e)))?;
+
+        cli::command_stdout(output)
+    }
+}
+
+#[async_trait]
+impl RaucBackend for LocalBackend {
+    async fn get_status(&self) -> Result<RaucStatus, RaucError> {
+        let stdout = self
+            .execute_command(&["status", "--detailed", "--output-format=json"])
+            .await?;
+        cli::parse_status(&stdout)
+    }
+
+    async fn get_bundle_info(
+        &self,
+        bundle: &str,
+        streaming: &StreamingOptions,
+    ) -> Result<RaucBundleInfo, RaucError> {
+        let streaming_args = cli::streaming_args(streaming);
+        let mut args = vec!["info", "--output-format=json"];
+        args.extend(streaming_args.iter().map(String::as_str));
+        args.push(bundle);
+
+        let stdout = self.execute_command(&args).await?;
+        cli::parse_bundle_info(&stdout)
+    }
+
+    async fn install_bundle(
+        &self,
+        bundle: &str,
+        streaming: &StreamingOptions,
+    ) -> Result<InstallStream, RaucError> {
+        let child = Command::new("rauc")
+            .arg("install")
+            .args(cli::streaming_args(streaming))
+            .arg(bundle)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                RaucError::Internal(format!("Failed to spawn local install command: {}", e))
+            })?;
+
+        cli::install_output_stream(child)
+    }
+
+    async fn mark_slot(&self, slot: &str, mark: SlotMark) -> Result<String, RaucError> {
+        let args = cli::mark_args(slot, mark);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        self.execute_command(&args).await?;
+        Ok(cli::mark_message(slot, mark))
+    }
+
+    async fn reboot(&self) -> Result<String, RaucError> {
+        reboot_local().await
+    }
+}
+
+/// Location of the kernel's random boot ID on Linux
+pub const BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+
+/// Boot ID of the machine the server is running on
+pub async fn boot_id() -> Result<String, RaucError> {
+    tokio::fs::read_to_string(BOOT_ID_PATH)
+        .await
+        .map(|id| id.trim().to_string())
+        .map_err(|e| RaucError::Internal(format!("Failed to read boot ID: {}", e)))
+}
+
+/// Reboot the machine the server is running on
+pub async fn reboot_local() -> Result<String, RaucError> {
+    let _ = Command::new("reboot")
+        .output()
+        .await
+        .map_err(|e| RaucError::Internal(format!("Failed to execute reboot command: {}", e)))?;
+
+    Ok("Reboot initiated".to_string())
+}
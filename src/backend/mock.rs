@@ -0,0 +1,231 @@
+use super::{is_remote_bundle, InstallOutput, InstallStream, RaucBackend, StreamingOptions};
+use crate::error::RaucError;
+use crate::rauc::{RaucBundleInfo, RaucStatus, SlotMark};
+use async_trait::async_trait;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const COMPATIBLE: &str = "rauc-web-ui-mock";
+const BUNDLE_VERSION: &str = "1.0.0";
+
+/// Progress lines emitted by a simulated installation, mimicking `rauc install`
+const INSTALL_STEPS: &[(u8, &str)] = &[
+    (0, "Installing"),
+    (0, "Determining slot states"),
+    (20, "Determining slot states done."),
+    (20, "Checking bundle"),
+    (20, "Verifying signature"),
+    (40, "Verifying signature done."),
+    (40, "Checking bundle done."),
+    (40, "Checking manifest contents"),
+    (60, "Checking manifest contents done."),
+    (60, "Determining target install group"),
+    (80, "Determining target install group done."),
+    (80, "Updating slots"),
+    (80, "Checking slot rootfs.{slot}"),
+    (90, "Checking slot rootfs.{slot} done."),
+    (90, "Copying image to rootfs.{slot}"),
+    (100, "Copying image to rootfs.{slot} done."),
+    (100, "Updating slots done."),
+    (100, "Installing done."),
+];
+
+/// Fail like rauc for missing or empty bundle files, URLs are accepted without downloading them
+async fn check_bundle(bundle: &str) -> Result<(), RaucError> {
+    if is_remote_bundle(bundle) {
+        return Ok(());
+    }
+
+    let metadata = tokio::fs::metadata(bundle)
+        .await
+        .map_err(|_| RaucError::NoBundle)?;
+    if metadata.len() == 0 {
+        return Err(RaucError::InvalidBundle {
+            stderr: format!("Failed to load bundle {}: bundle is empty", bundle),
+        });
+    }
+
+    Ok(())
+}
+
+struct MockState {
+    /// Index of the slot the simulated system booted from
+    booted: usize,
+    /// Index of the slot the bootloader will boot next
+    primary: usize,
+    /// Bundle version installed in each slot
+    versions: [String; 2],
+    /// Boot status of each slot, `good` or `bad`
+    boot_status: [&'static str; 2],
+    /// Number of simulated reboots, used as boot ID
+    boots: u64,
+}
+
+impl MockState {
+    /// Index of a slot given by name, `booted` or `other`
+    fn slot_index(&self, slot: &str) -> Result<usize, RaucError> {
+        match slot {
+            "booted" => Ok(self.booted),
+            "other" => Ok(1 - self.booted),
+            "rootfs.0" => Ok(0),
+            "rootfs.1" => Ok(1),
+            _ => Err(RaucError::NotFound(format!("Slot {} not found", slot))),
+        }
+    }
+}
+
+/// In-memory simulated backend for running the web UI without a target or rauc
+#[derive(Clone)]
+pub struct MockBackend {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MockState {
+                booted: 0,
+                primary: 0,
+                versions: ["0.9.0".to_string(), "0.8.0".to_string()],
+                boot_status: ["good", "good"],
+                boots: 0,
+            })),
+        }
+    }
+}
+
+impl Default for MockBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl RaucBackend for MockBackend {
+    async fn get_status(&self) -> Result<RaucStatus, RaucError> {
+        let state = self.state.lock().unwrap();
+        let bootnames = ["A", "B"];
+
+        let slots = (0..2)
+            .map(|index| {
+                let slot = json!({
+                    "class": "rootfs",
+                    "device": format!("/dev/mmcblk0p{}", index + 2),
+                    "type": "ext4",
+                    "bootname": bootnames[index],
+                    "state": if index == state.booted { "booted" } else { "inactive" },
+                    "parent": null,
+                    "mountpoint": if index == state.booted { Some("/") } else { None },
+                    "boot_status": state.boot_status[index],
+                    "slot_status": {
+                        "bundle": {
+                            "compatible": COMPATIBLE,
+                            "version": state.versions[index],
+                        },
+                        "status": "ok",
+                    },
+                });
+                let slot = serde_json::from_value(slot).map_err(|e| {
+                    RaucError::Internal(format!("Failed to build simulated slot status: {}", e))
+                })?;
+                Ok(BTreeMap::from([(format!("rootfs.{}", index), slot)]))
+            })
+            .collect::<Result<_, RaucError>>()?;
+
+        Ok(RaucStatus {
+            compatible: COMPATIBLE.to_string(),
+            variant: String::new(),
+            booted: bootnames[state.booted].to_string(),
+            boot_primary: Some(format!("rootfs.{}", state.primary)),
+            slots,
+            artifact_repositories: Vec::new(),
+            extra: Default::default(),
+        })
+    }
+
+    async fn get_bundle_info(
+        &self,
+        bundle: &str,
+        _streaming: &StreamingOptions,
+    ) -> Result<RaucBundleInfo, RaucError> {
+        check_bundle(bundle).await?;
+
+        Ok(RaucBundleInfo {
+            compatible: COMPATIBLE.to_string(),
+            version: BUNDLE_VERSION.to_string(),
+            description: Some("Simulated bundle".to_string()),
+            build: None,
+            format: Some("verity".to_string()),
+            hooks: Vec::new(),
+            hash: None,
+            images: Vec::new(),
+            meta: BTreeMap::new(),
+            extra: Default::default(),
+        })
+    }
+
+    async fn install_bundle(
+        &self,
+        bundle: &str,
+        _streaming: &StreamingOptions,
+    ) -> Result<InstallStream, RaucError> {
+        check_bundle(bundle).await?;
+
+        let state = self.state.clone();
+        let target = 1 - state.lock().unwrap().booted;
+        let bundle = bundle.to_string();
+
+        let stream = async_stream::stream! {
+            yield InstallOutput::Stdout(format!("installing `{}`", bundle));
+
+            for (percentage, message) in INSTALL_STEPS {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                let message = message.replace("{slot}", &target.to_string());
+                yield InstallOutput::Stdout(format!("{:>3}% {}", percentage, message));
+            }
+
+            {
+                let mut state = state.lock().unwrap();
+                state.versions[target] = BUNDLE_VERSION.to_string();
+                state.boot_status[target] = "good";
+                state.primary = target;
+            }
+
+            yield InstallOutput::Stdout(format!("Installing `{}` succeeded", bundle));
+            yield InstallOutput::Finished { result: Ok(()), exit_status: Some(0) };
+        };
+
+        Ok(Box::pin(stream))
+    }
+
+    async fn boot_id(&self) -> Result<String, RaucError> {
+        Ok(format!("mock-boot-{}", self.state.lock().unwrap().boots))
+    }
+
+    async fn mark_slot(&self, slot: &str, mark: SlotMark) -> Result<String, RaucError> {
+        let mut state = self.state.lock().unwrap();
+        let index = state.slot_index(slot)?;
+
+        // Like rauc, marking a slot active also marks it good
+        match mark {
+            SlotMark::Good => state.boot_status[index] = "good",
+            SlotMark::Bad => state.boot_status[index] = "bad",
+            SlotMark::Active => {
+                state.boot_status[index] = "good";
+                state.primary = index;
+            }
+        }
+
+        Ok(format!("marked slot rootfs.{} as {}", index, mark.as_str()))
+    }
+
+    async fn reboot(&self) -> Result<String, RaucError> {
+        let mut state = self.state.lock().unwrap();
+        state.booted = state.primary;
+        state.boots += 1;
+
+        Ok("Reboot simulated".to_string())
+    }
+}
@@ -0,0 +1,109 @@
+mod cli;
+pub mod dbus;
+pub mod local;
+pub mod mock;
+#[cfg(feature = "ssh-backend")]
+pub mod ssh;
+
+use crate::error::RaucError;
+use crate::rauc::{RaucBundleInfo, RaucStatus, SlotMark};
+use async_trait::async_trait;
+use std::pin::Pin;
+use tokio_stream::Stream;
+
+pub use dbus::{DBusBackend, DBusBus};
+pub use local::LocalBackend;
+pub use mock::MockBackend;
+#[cfg(feature = "ssh-backend")]
+pub use ssh::{SshBackend, SshConfig};
+
+/// Output of a running installation
+#[derive(Debug, Clone)]
+pub enum InstallOutput {
+    /// Line printed on stdout, or a progress message
+    Stdout(String),
+    /// Line printed on stderr
+    Stderr(String),
+    /// The installation finished, with an error message on failure
+    Finished {
+        result: Result<(), String>,
+        /// Exit status of `rauc install`, or the result of RAUC's `Completed` signal
+        exit_status: Option<i32>,
+    },
+}
+
+/// Stream of installation output, ending with `InstallOutput::Finished`
+pub type InstallStream = Pin<Box<dyn Stream<Item = InstallOutput> + Send>>;
+
+/// Whether a bundle is streamed from an HTTP(S) server rather than read from a file
+pub fn is_remote_bundle(bundle: &str) -> bool {
+    bundle.starts_with("http://") || bundle.starts_with("https://")
+}
+
+/// Options passed to rauc for streaming a bundle over HTTP(S)
+#[derive(Debug, Clone, Default)]
+pub struct StreamingOptions {
+    /// Extra request headers, e.g. `Authorization: Bearer <token>`
+    pub http_headers: Vec<String>,
+    /// CA certificate to verify the server with, in PEM format
+    pub tls_ca: Option<String>,
+    /// Client certificate and key for authenticating against the server
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub tls_no_verify: bool,
+}
+
+impl StreamingOptions {
+    pub fn is_empty(&self) -> bool {
+        self.http_headers.is_empty()
+            && self.tls_ca.is_none()
+            && self.tls_cert.is_none()
+            && self.tls_key.is_none()
+            && !self.tls_no_verify
+    }
+}
+
+/// Transport used by `RaucClient` to talk to RAUC on the target system
+#[async_trait]
+pub trait RaucBackend: Send + Sync {
+    async fn get_status(&self) -> Result<RaucStatus, RaucError>;
+
+    /// Inspect a bundle file or URL, `streaming` only applies to URLs
+    async fn get_bundle_info(
+        &self,
+        bundle: &str,
+        streaming: &StreamingOptions,
+    ) -> Result<RaucBundleInfo, RaucError>;
+
+    /// Install a bundle file or URL, `streaming` only applies to URLs
+    async fn install_bundle(
+        &self,
+        bundle: &str,
+        streaming: &StreamingOptions,
+    ) -> Result<InstallStream, RaucError>;
+
+    /// Make a locally uploaded file available at `target_path` on the target system
+    async fn copy_file_to_target(
+        &self,
+        _local_path: &str,
+        _target_path: &str,
+    ) -> Result<String, RaucError> {
+        // The file is already local for backends running on the target itself
+        Ok("File already on target system".to_string())
+    }
+
+    /// Delete a file previously made available with `copy_file_to_target`
+    async fn remove_file_from_target(&self, _target_path: &str) -> Result<(), RaucError> {
+        Ok(())
+    }
+
+    /// Identifier of the current boot of the target, changing with every reboot
+    async fn boot_id(&self) -> Result<String, RaucError> {
+        local::boot_id().await
+    }
+
+    /// Mark a slot name, `booted` or `other` as good, bad or active, returning RAUC's message
+    async fn mark_slot(&self, slot: &str, mark: SlotMark) -> Result<String, RaucError>;
+
+    async fn reboot(&self) -> Result<String, RaucError>;
+}
@@ -0,0 +1,925 @@
+use super::{cli, local, InstallOutput, InstallStream, RaucBackend, StreamingOptions};
+use crate::error::RaucError;
+use crate::rauc::{RaucBundleInfo, RaucStatus, SlotMark};
+use async_trait::async_trait;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use ssh2::{Channel, CheckResult, KnownHostFileKind, Session};
+use std::borrow::Cow;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Output};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+const DEFAULT_USER: &str = "root";
+const DEFAULT_PORT: u16 = 22;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Milliseconds a blocking operation on a connection may wait for the target
+const SESSION_TIMEOUT_MS: u32 = 120_000;
+/// Idle connections kept open for later commands
+const MAX_IDLE_SESSIONS: usize = 4;
+/// Pause between polls of a running installation's output
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Exit status reported like ssh does for commands killed by a signal
+const SIGNAL_EXIT_STATUS: i32 = 255;
+
+/// How to reach and authenticate with the target
+#[derive(Debug, Clone)]
+pub struct SshConfig {
+    /// `[user@]host[:port]`, the user defaults to root
+    pub destination: String,
+    /// Private key file, tried first
+    pub key_file: Option<PathBuf>,
+    pub key_passphrase: Option<String>,
+    /// Try the keys of the running ssh-agent
+    pub agent: bool,
+    /// Tried last, if neither a key nor the agent are accepted
+    pub password: Option<String>,
+    /// OpenSSH known_hosts file the target's host key must be listed in
+    pub known_hosts: PathBuf,
+    /// Add the key of a host missing from `known_hosts` instead of refusing to connect, a
+    /// changed key is always refused
+    pub accept_new_host_keys: bool,
+}
+
+/// Backend running rauc on a remote target over SSH, used during development
+#[derive(Clone)]
+pub struct SshBackend {
+    config: SshConfig,
+    user: String,
+    host: String,
+    port: u16,
+    /// Connections of finished commands, reused by later ones except installations. A command
+    /// takes a connection out of the pool, so no lock is held while it talks to the target.
+    idle_sessions: Arc<Mutex<Vec<Session>>>,
+}
+
+/// Command line for the target's shell, with every argument quoted so that nothing derived
+/// from the configuration or a request is interpreted by the shell
+struct RemoteCommand(String);
+
+impl RemoteCommand {
+    fn new(program: &str) -> Self {
+        Self(quote(program).into_owned())
+    }
+
+    fn arg(mut self, arg: impl AsRef<str>) -> Self {
+        self.0.push(' ');
+        self.0.push_str(&quote(arg.as_ref()));
+        self
+    }
+
+    fn args(self, args: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        args.into_iter().fold(self, Self::arg)
+    }
+
+    fn rauc(args: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        Self::new("rauc").args(args)
+    }
+
+    fn rauc_info(bundle: &str, streaming: &StreamingOptions) -> Self {
+        Self::rauc(["info", "--output-format=json"])
+            .args(cli::streaming_args(streaming))
+            .arg(bundle)
+    }
+
+    fn rauc_install(bundle: &str, streaming: &StreamingOptions) -> Self {
+        Self::rauc(["install"])
+            .args(cli::streaming_args(streaming))
+            .arg(bundle)
+    }
+
+    /// Create a directory along with its parents
+    fn mkdir(dir: &str) -> Self {
+        Self::new("mkdir").args(["-p", "--"]).arg(dir)
+    }
+
+    /// Print the size of a file in bytes
+    fn file_size(path: &str) -> Self {
+        Self::new("stat").args(["-c", "%s", "--"]).arg(path)
+    }
+
+    fn remove(path: &str) -> Self {
+        Self::new("rm").args(["-f", "--"]).arg(path)
+    }
+
+    fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Quote an argument for a POSIX shell, arguments of only harmless characters are left as they
+/// are to keep commands readable in error messages
+fn quote(arg: &str) -> Cow<'_, str> {
+    let harmless = |byte: u8| byte.is_ascii_alphanumeric() || b"-_./=:,+@%".contains(&byte);
+    if !arg.is_empty() && arg.bytes().all(harmless) {
+        Cow::Borrowed(arg)
+    } else {
+        // Nothing is special within single quotes, a single quote itself ends the quoting
+        Cow::Owned(format!("'{}'", arg.replace('\'', r"'\''")))
+    }
+}
+
+fn unreachable(message: impl std::fmt::Display) -> RaucError {
+    RaucError::TargetUnreachable(format!("Failed to connect to target: {}", message))
+}
+
+/// Line of an OpenSSH known_hosts file for a host key in SSH wire format
+fn known_hosts_line(host: &str, key: &[u8]) -> Option<String> {
+    let length = u32::from_be_bytes(key.get(..4)?.try_into().ok()?) as usize;
+    let key_type = std::str::from_utf8(key.get(4..4 + length)?).ok()?;
+    Some(format!(
+        "{} {} {}\n",
+        host,
+        key_type,
+        BASE64_STANDARD.encode(key)
+    ))
+}
+
+fn exit_status(channel: &Channel) -> Result<(ExitStatus, Option<String>), ssh2::Error> {
+    let signal = channel.exit_signal()?.exit_signal;
+    let code = match signal {
+        Some(_) => SIGNAL_EXIT_STATUS,
+        None => channel.exit_status()?,
+    };
+    Ok((ExitStatus::from_raw(code << 8), signal))
+}
+
+/// Read a command's output until it exits
+fn read_output(channel: &mut Channel) -> std::io::Result<Output> {
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    channel.read_to_end(&mut stdout)?;
+    channel.stderr().read_to_end(&mut stderr)?;
+    channel.wait_close()?;
+
+    let (status, signal) = exit_status(channel)?;
+    if let Some(signal) = signal {
+        stderr.extend_from_slice(format!("Killed by signal {}\n", signal).as_bytes());
+    }
+    Ok(Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+/// Remove the complete lines from `pending`
+fn take_lines(pending: &mut Vec<u8>) -> Vec<String> {
+    let Some(end) = pending.iter().rposition(|&byte| byte == b'\n') else {
+        return Vec::new();
+    };
+    let complete: Vec<u8> = pending.drain(..=end).collect();
+    complete[..end]
+        .split(|&byte| byte == b'\n')
+        .map(|line| {
+            String::from_utf8_lossy(line)
+                .trim_end_matches('\r')
+                .to_string()
+        })
+        .collect()
+}
+
+/// Forward the output of a running `rauc install` line by line until it exits
+fn forward_install_output(
+    session: Session,
+    mut channel: Channel,
+    sender: UnboundedSender<InstallOutput>,
+) {
+    // Poll stdout and stderr in turn, a blocking read of one could stall the other
+    session.set_blocking(false);
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let mut buffer = [0u8; 4096];
+    let lost = |e: std::io::Error| InstallOutput::Finished {
+        result: Err(format!("Lost connection to target: {}", e)),
+        exit_status: None,
+    };
+
+    loop {
+        let mut idle = true;
+        match channel.read(&mut buffer) {
+            Ok(read) => {
+                idle &= read == 0;
+                stdout.extend_from_slice(&buffer[..read]);
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => {
+                let _ = sender.send(lost(e));
+                return;
+            }
+        }
+        match channel.stderr().read(&mut buffer) {
+            Ok(read) => {
+                idle &= read == 0;
+                stderr.extend_from_slice(&buffer[..read]);
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => {
+                let _ = sender.send(lost(e));
+                return;
+            }
+        }
+
+        for line in take_lines(&mut stdout) {
+            let _ = sender.send(InstallOutput::Stdout(line));
+        }
+        for line in take_lines(&mut stderr) {
+            let _ = sender.send(InstallOutput::Stderr(line));
+        }
+        if idle && channel.eof() {
+            break;
+        }
+        if idle {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    // Output not terminated by a newline
+    if !stdout.is_empty() {
+        let _ = sender.send(InstallOutput::Stdout(
+            String::from_utf8_lossy(&stdout).to_string(),
+        ));
+    }
+    if !stderr.is_empty() {
+        let _ = sender.send(InstallOutput::Stderr(
+            String::from_utf8_lossy(&stderr).to_string(),
+        ));
+    }
+
+    session.set_blocking(true);
+    let finished = match channel.wait_close().and_then(|_| exit_status(&channel)) {
+        Ok((status, _)) => InstallOutput::Finished {
+            result: if status.success() {
+                Ok(())
+            } else {
+                Err(format!("Installation failed with status: {}", status))
+            },
+            exit_status: status.code(),
+        },
+        Err(e) => InstallOutput::Finished {
+            result: Err(format!("Failed to wait for installation: {}", e)),
+            exit_status: None,
+        },
+    };
+    let _ = sender.send(finished);
+}
+
+impl SshBackend {
+    pub fn new(config: SshConfig) -> Result<Self, String> {
+        let (user, address) = match config.destination.split_once('@') {
+            Some((user, address)) => (user.to_string(), address),
+            None => (DEFAULT_USER.to_string(), config.destination.as_str()),
+        };
+        let invalid_port = || format!("Invalid port in SSH_HOST '{}'", config.destination);
+        let (host, port) = match address.strip_prefix('[') {
+            // `[address]` or `[address]:port`, as IPv6 addresses contain colons
+            Some(bracketed) => {
+                let (host, rest) = bracketed
+                    .split_once(']')
+                    .ok_or_else(|| format!("Missing ']' in SSH_HOST '{}'", config.destination))?;
+                let port = match rest {
+                    "" => DEFAULT_PORT,
+                    rest => rest
+                        .strip_prefix(':')
+                        .and_then(|port| port.parse().ok())
+                        .ok_or_else(invalid_port)?,
+                };
+                (host, port)
+            }
+            None => match address.rsplit_once(':') {
+                // A bare IPv6 address has colons but no port
+                Some((host, port)) if !host.contains(':') => {
+                    (host, port.parse().map_err(|_| invalid_port())?)
+                }
+                _ => (address, DEFAULT_PORT),
+            },
+        };
+
+        Ok(Self {
+            user,
+            host: host.to_string(),
+            port,
+            config,
+            idle_sessions: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    /// Open a new authenticated connection to the target
+    fn connect(&self) -> Result<Session, RaucError> {
+        let addresses = (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .map_err(|e| unreachable(format!("{}: {}", self.host, e)))?;
+        let mut last_error = None;
+        let tcp = addresses
+            .into_iter()
+            .find_map(
+                |address| match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+                    Ok(tcp) => Some(tcp),
+                    Err(e) => {
+                        last_error = Some(e);
+                        None
+                    }
+                },
+            )
+            .ok_or_else(|| {
+                unreachable(format!(
+                    "{}: {}",
+                    self.host,
+                    last_error.map_or_else(|| "no address".to_string(), |e| e.to_string())
+                ))
+            })?;
+
+        let mut session = Session::new()
+            .map_err(|e| RaucError::Internal(format!("Failed to create SSH session: {}", e)))?;
+        session.set_tcp_stream(tcp);
+        session.set_timeout(SESSION_TIMEOUT_MS);
+        session.handshake().map_err(unreachable)?;
+
+        self.check_host_key(&session)?;
+        self.authenticate(&session)?;
+        Ok(session)
+    }
+
+    /// Refuse to talk to a target whose host key is unknown or changed
+    fn check_host_key(&self, session: &Session) -> Result<(), RaucError> {
+        let (key, _) = session
+            .host_key()
+            .ok_or_else(|| unreachable("The target sent no host key"))?;
+        let path = &self.config.known_hosts;
+        let mut known_hosts = session
+            .known_hosts()
+            .map_err(|e| RaucError::Internal(format!("Failed to check host key: {}", e)))?;
+        if path.exists() {
+            known_hosts
+                .read_file(path, KnownHostFileKind::OpenSSH)
+                .map_err(|e| {
+                    RaucError::Internal(format!("Failed to read {}: {}", path.display(), e))
+                })?;
+        }
+
+        let host = match self.port {
+            DEFAULT_PORT => self.host.clone(),
+            port => format!("[{}]:{}", self.host, port),
+        };
+        match known_hosts.check_port(&self.host, self.port, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::NotFound if self.config.accept_new_host_keys => {
+                let line = known_hosts_line(&host, key)
+                    .ok_or_else(|| unreachable("The target sent an invalid host key"))?;
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir).map_err(|e| {
+                        RaucError::Internal(format!("Failed to create known hosts directory: {}", e))
+                    })?;
+                }
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut file| file.write_all(line.as_bytes()))
+                    .map_err(|e| {
+                        RaucError::Internal(format!("Failed to write {}: {}", path.display(), e))
+                    })?;
+                println!("Added host key of {} to {}", host, path.display());
+                Ok(())
+            }
+            CheckResult::NotFound => Err(unreachable(format!(
+                "Host key of {} is not in {}, add it with `ssh-keyscan` or set SSH_ACCEPT_NEW_HOST_KEYS=true",
+                host,
+                path.display()
+            ))),
+            CheckResult::Mismatch => Err(unreachable(format!(
+                "Host key of {} does not match the one in {}",
+                host,
+                path.display()
+            ))),
+            CheckResult::Failure => Err(unreachable(format!(
+                "Failed to check host key of {}",
+                host
+            ))),
+        }
+    }
+
+    /// Try the key file, the ssh-agent and the password in turn
+    fn authenticate(&self, session: &Session) -> Result<(), RaucError> {
+        let mut errors = Vec::new();
+
+        if let Some(key_file) = &self.config.key_file {
+            if let Err(e) = session.userauth_pubkey_file(
+                &self.user,
+                None,
+                key_file,
+                self.config.key_passphrase.as_deref(),
+            ) {
+                errors.push(format!("key {}: {}", key_file.display(), e));
+            }
+        }
+        if !session.authenticated() && self.config.agent {
+            if let Err(e) = session.userauth_agent(&self.user) {
+                errors.push(format!("ssh-agent: {}", e));
+            }
+        }
+        if !session.authenticated() {
+            if let Some(password) = &self.config.password {
+                if let Err(e) = session.userauth_password(&self.user, password) {
+                    errors.push(format!("password: {}", e));
+                }
+            }
+        }
+
+        if session.authenticated() {
+            Ok(())
+        } else if errors.is_empty() {
+            Err(unreachable(
+                "No SSH_KEY, ssh-agent or SSH_PASSWORD to authenticate with",
+            ))
+        } else {
+            Err(unreachable(format!(
+                "Authentication as {} failed ({})",
+                self.user,
+                errors.join(", ")
+            )))
+        }
+    }
+
+    /// Open a channel on an idle connection, or on a new one if there is none or it was dropped,
+    /// e.g. by a reboot of the target. The connection belongs to the caller until it is handed
+    /// back with `release`.
+    fn open_channel(
+        &self,
+        open: impl Fn(&Session) -> Result<Channel, ssh2::Error>,
+    ) -> Result<(Session, Channel), RaucError> {
+        let idle = self.idle_sessions.lock().unwrap().pop();
+        if let Some(session) = idle {
+            if let Ok(channel) = open(&session) {
+                return Ok((session, channel));
+            }
+            // The other idle connections are most likely dropped as well
+            self.idle_sessions.lock().unwrap().clear();
+        }
+
+        let session = self.connect()?;
+        let channel = open(&session).map_err(unreachable)?;
+        Ok((session, channel))
+    }
+
+    /// Keep the connection of a successful command for later ones, a failed one is dropped
+    fn release(&self, session: Session) {
+        let mut idle = self.idle_sessions.lock().unwrap();
+        if idle.len() < MAX_IDLE_SESSIONS {
+            idle.push(session);
+        }
+    }
+
+    /// Run a command on the target
+    fn execute_ssh_command(&self, command: &RemoteCommand) -> Result<Output, RaucError> {
+        let (session, mut channel) = self.open_channel(|session| {
+            let mut channel = session.channel_session()?;
+            channel.exec(command.as_str())?;
+            Ok(channel)
+        })?;
+
+        let output = read_output(&mut channel).map_err(unreachable)?;
+        self.release(session);
+        Ok(output)
+    }
+
+    /// Copy a file to the target, keeping its mode and times. libssh2 quotes the remote path
+    /// for the `scp` it starts on the target.
+    fn send_file(&self, local_path: &str, remote_path: &str) -> Result<(), RaucError> {
+        let mut file = File::open(local_path)
+            .map_err(|e| RaucError::Internal(format!("Failed to open local file: {}", e)))?;
+        let metadata = file.metadata().map_err(|e| {
+            RaucError::Internal(format!("Failed to read local file metadata: {}", e))
+        })?;
+
+        let (session, mut channel) = self.open_channel(|session| {
+            session.scp_send(
+                Path::new(remote_path),
+                (metadata.permissions().mode() & 0o777) as i32,
+                metadata.len(),
+                Some((metadata.mtime() as u64, metadata.atime() as u64)),
+            )
+        })?;
+
+        let result = std::io::copy(&mut file, &mut channel).and_then(|_| {
+            channel.send_eof()?;
+            channel.wait_eof()?;
+            channel.close()?;
+            Ok(channel.wait_close()?)
+        });
+        result.map_err(|e| RaucError::Internal(format!("scp command failed: {}", e)))?;
+        self.release(session);
+        Ok(())
+    }
+
+    /// Copy a file to the target, creating its directory, and check the copy's size
+    fn copy_file(&self, local_path: &str, target_path: &str) -> Result<String, RaucError> {
+        // Get local file size before transfer
+        let local_size = std::fs::metadata(local_path)
+            .map_err(|e| RaucError::Internal(format!("Failed to read local file metadata: {}", e)))?
+            .len();
+
+        // Ensure parent directory exists on target
+        let parent_dir = std::path::Path::new(target_path)
+            .parent()
+            .ok_or_else(|| RaucError::Internal("Invalid target path".to_string()))?;
+
+        let mkdir_output =
+            self.execute_ssh_command(&RemoteCommand::mkdir(&parent_dir.to_string_lossy()))?;
+
+        if !mkdir_output.status.success() {
+            let stderr = String::from_utf8_lossy(&mkdir_output.stderr);
+            return Err(RaucError::Internal(format!(
+                "Failed to create remote directory: {}",
+                stderr
+            )));
+        }
+
+        // Copy file to target
+        self.send_file(local_path, target_path)?;
+
+        // Verify remote file size
+        let stat_output = self.execute_ssh_command(&RemoteCommand::file_size(target_path))?;
+
+        if stat_output.status.success() {
+            let remote_size_str = String::from_utf8_lossy(&stat_output.stdout);
+            if let Ok(remote_size) = remote_size_str.trim().parse::<u64>() {
+                if remote_size != local_size {
+                    return Err(RaucError::Internal(format!(
+                        "File size mismatch after transfer: local {} bytes, remote {} bytes",
+                        local_size, remote_size
+                    )));
+                }
+                return Ok(format!(
+                    "File copied to {} successfully ({} bytes verified)",
+                    self.host, remote_size
+                ));
+            }
+        }
+
+        Ok(format!(
+            "File copied to {} successfully ({} bytes)",
+            self.host, local_size
+        ))
+    }
+
+    /// Run blocking SSH work on a thread of its own, so a slow or unreachable target does not
+    /// stall the async workers serving other requests
+    async fn blocking<T: Send + 'static>(
+        &self,
+        work: impl FnOnce(&SshBackend) -> Result<T, RaucError> + Send + 'static,
+    ) -> Result<T, RaucError> {
+        let backend = self.clone();
+        tokio::task::spawn_blocking(move || work(&backend))
+            .await
+            .map_err(|e| RaucError::Internal(format!("SSH task failed: {}", e)))?
+    }
+
+    async fn run(&self, command: RemoteCommand) -> Result<Output, RaucError> {
+        self.blocking(move |backend| backend.execute_ssh_command(&command))
+            .await
+    }
+
+    async fn execute_command(&self, command: RemoteCommand) -> Result<String, RaucError> {
+        let output = self.run(command).await?;
+
+        cli::command_stdout(output)
+    }
+}
+
+#[async_trait]
+impl RaucBackend for SshBackend {
+    async fn get_status(&self) -> Result<RaucStatus, RaucError> {
+        let stdout = self
+            .execute_command(RemoteCommand::rauc([
+                "status",
+                "--detailed",
+                "--output-format=json",
+            ]))
+            .await?;
+        cli::parse_status(&stdout)
+    }
+
+    async fn get_bundle_info(
+        &self,
+        bundle: &str,
+        streaming: &StreamingOptions,
+    ) -> Result<RaucBundleInfo, RaucError> {
+        let stdout = self
+            .execute_command(RemoteCommand::rauc_info(bundle, streaming))
+            .await?;
+        cli::parse_bundle_info(&stdout)
+    }
+
+    async fn install_bundle(
+        &self,
+        bundle: &str,
+        streaming: &StreamingOptions,
+    ) -> Result<InstallStream, RaucError> {
+        let command = RemoteCommand::rauc_install(bundle, streaming);
+
+        // A connection of its own, so other commands are not held up while installing
+        let (session, channel) = self
+            .blocking(move |backend| {
+                let session = backend.connect()?;
+                let channel = session
+                    .channel_session()
+                    .and_then(|mut channel| channel.exec(command.as_str()).map(|_| channel))
+                    .map_err(|e| {
+                        RaucError::Internal(format!("Failed to start ssh install command: {}", e))
+                    })?;
+                Ok((session, channel))
+            })
+            .await?;
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        std::thread::spawn(move || forward_install_output(session, channel, sender));
+
+        Ok(Box::pin(async_stream::stream! {
+            while let Some(output) = receiver.recv().await {
+                yield output;
+            }
+        }))
+    }
+
+    async fn copy_file_to_target(
+        &self,
+        local_path: &str,
+        target_path: &str,
+    ) -> Result<String, RaucError> {
+        let local_path = local_path.to_string();
+        let target_path = target_path.to_string();
+        self.blocking(move |backend| backend.copy_file(&local_path, &target_path))
+            .await
+    }
+
+    async fn remove_file_from_target(&self, target_path: &str) -> Result<(), RaucError> {
+        let output = self.run(RemoteCommand::remove(target_path)).await?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(RaucError::Internal(format!(
+                "Failed to delete remote file: {}",
+                stderr
+            )));
+        }
+
+        Ok(())
+    }
+
+    async fn boot_id(&self) -> Result<String, RaucError> {
+        let output = self
+            .run(RemoteCommand::new("cat").arg(local::BOOT_ID_PATH))
+            .await?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(RaucError::CommandFailed {
+                message: "Failed to read boot ID".to_string(),
+                stderr: stderr.trim().to_string(),
+            });
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    async fn mark_slot(&self, slot: &str, mark: SlotMark) -> Result<String, RaucError> {
+        self.execute_command(RemoteCommand::rauc(cli::mark_args(slot, mark)))
+            .await?;
+        Ok(cli::mark_message(slot, mark))
+    }
+
+    async fn reboot(&self) -> Result<String, RaucError> {
+        // Note: reboot might not return successfully as the connection will be dropped
+        match self.run(RemoteCommand::new("reboot")).await {
+            Ok(output) if !output.status.success() => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                // Don't treat this as a fatal error, as the connection may drop
+                eprintln!("Reboot stderr (may be expected): {}", stderr);
+            }
+            Err(RaucError::TargetUnreachable(message)) => {
+                eprintln!("Reboot connection error (may be expected): {}", message);
+            }
+            result => {
+                result?;
+            }
+        }
+
+        Ok("Reboot command sent".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssh2::ErrorCode;
+
+    fn backend(destination: &str) -> Result<SshBackend, String> {
+        SshBackend::new(SshConfig {
+            destination: destination.to_string(),
+            key_file: None,
+            key_passphrase: None,
+            agent: false,
+            password: None,
+            known_hosts: PathBuf::from("/nonexistent/known_hosts"),
+            accept_new_host_keys: false,
+        })
+    }
+
+    fn destination(destination: &str) -> (String, String, u16) {
+        let backend = backend(destination).unwrap();
+        (backend.user, backend.host, backend.port)
+    }
+
+    #[test]
+    fn parses_destinations() {
+        let expected = |user: &str, host: &str, port| (user.to_string(), host.to_string(), port);
+        assert_eq!(
+            destination("admin@172.16.220.172"),
+            expected("admin", "172.16.220.172", 22)
+        );
+        assert_eq!(destination("target"), expected("root", "target", 22));
+        assert_eq!(
+            destination("target.local:2222"),
+            expected("root", "target.local", 2222)
+        );
+        assert_eq!(destination("[::1]:2222"), expected("root", "::1", 2222));
+        assert_eq!(
+            destination("admin@[fe80::1]"),
+            expected("admin", "fe80::1", 22)
+        );
+        assert_eq!(destination("::1"), expected("root", "::1", 22));
+        assert_eq!(destination("fe80::1"), expected("root", "fe80::1", 22));
+    }
+
+    #[test]
+    fn rejects_invalid_ports() {
+        for destination in [
+            "target:ssh",
+            "target:",
+            "target:65536",
+            "[::1]:port",
+            "[::1]22",
+        ] {
+            let error = backend(destination).err().unwrap();
+            assert_eq!(error, format!("Invalid port in SSH_HOST '{}'", destination));
+        }
+    }
+
+    #[test]
+    fn formats_known_hosts_lines() {
+        // Host key in SSH wire format: the key type and the public key, each length prefixed
+        let mut key = Vec::new();
+        key.extend_from_slice(&11u32.to_be_bytes());
+        key.extend_from_slice(b"ssh-ed25519");
+        key.extend_from_slice(&32u32.to_be_bytes());
+        key.extend_from_slice(&[7; 32]);
+
+        assert_eq!(
+            known_hosts_line("[::1]:2222", &key).unwrap(),
+            format!("[::1]:2222 ssh-ed25519 {}\n", BASE64_STANDARD.encode(&key))
+        );
+        assert_eq!(known_hosts_line("target", &key[..3]), None);
+        assert_eq!(known_hosts_line("target", &key[..8]), None);
+    }
+
+    #[test]
+    fn takes_complete_lines() {
+        let mut pending = b"Installing\r\n  0% Installing\n\n 20% Check".to_vec();
+        assert_eq!(
+            take_lines(&mut pending),
+            ["Installing", "  0% Installing", ""]
+        );
+        assert_eq!(pending, b" 20% Check");
+
+        assert!(take_lines(&mut pending).is_empty());
+        pending.extend_from_slice(b"ing bundle\n");
+        assert_eq!(take_lines(&mut pending), [" 20% Checking bundle"]);
+        assert!(pending.is_empty());
+    }
+
+    /// Words `sh` splits a command line into
+    fn shell_words(command: &RemoteCommand) -> Vec<String> {
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("printf '%s\\0' {}", command.as_str()))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let mut words: Vec<String> = output
+            .stdout
+            .split(|&byte| byte == 0)
+            .map(|word| String::from_utf8_lossy(word).into_owned())
+            .collect();
+        // Every word ends with a NUL
+        words.pop();
+        words
+    }
+
+    #[test]
+    fn quotes_hostile_arguments() {
+        let cases = [
+            ("/data/bundles/update.raucb", "/data/bundles/update.raucb"),
+            ("/data/my dir", "'/data/my dir'"),
+            ("it's", r"'it'\''s'"),
+            ("a;reboot", "'a;reboot'"),
+            ("$(reboot)", "'$(reboot)'"),
+            ("`reboot`", "'`reboot`'"),
+            ("a\nreboot", "'a\nreboot'"),
+            ("", "''"),
+            ("*", "'*'"),
+            ("-rf", "-rf"),
+        ];
+        for (arg, quoted) in cases {
+            assert_eq!(quote(arg), quoted);
+
+            let command = RemoteCommand::new("printf").arg(arg);
+            assert_eq!(shell_words(&command)[1..], [arg]);
+        }
+    }
+
+    #[test]
+    fn builds_file_commands() {
+        assert_eq!(
+            RemoteCommand::mkdir("/data/my dir").as_str(),
+            "mkdir -p -- '/data/my dir'"
+        );
+        assert_eq!(
+            RemoteCommand::file_size("/data/$(reboot).raucb").as_str(),
+            "stat -c %s -- '/data/$(reboot).raucb'"
+        );
+        assert_eq!(RemoteCommand::remove("-rf").as_str(), "rm -f -- -rf");
+        assert_eq!(
+            RemoteCommand::remove("/data/a;reboot").as_str(),
+            "rm -f -- '/data/a;reboot'"
+        );
+    }
+
+    #[test]
+    fn builds_rauc_commands() {
+        assert_eq!(
+            RemoteCommand::rauc(["status", "--detailed", "--output-format=json"]).as_str(),
+            "rauc status --detailed --output-format=json"
+        );
+        assert_eq!(
+            RemoteCommand::rauc(cli::mark_args("booted", SlotMark::Good)).as_str(),
+            "rauc status mark-good booted"
+        );
+        assert_eq!(
+            RemoteCommand::rauc_info("/data/update.raucb", &StreamingOptions::default()).as_str(),
+            "rauc info --output-format=json /data/update.raucb"
+        );
+
+        let streaming = StreamingOptions {
+            http_headers: vec!["Authorization: Bearer it's".to_string()],
+            tls_ca: Some("/etc/rauc/ca.pem".to_string()),
+            ..Default::default()
+        };
+        let command = RemoteCommand::rauc_install("https://example.com/update.raucb", &streaming);
+        assert_eq!(
+            command.as_str(),
+            r"rauc install '--http-header=Authorization: Bearer it'\''s' --tls-ca=/etc/rauc/ca.pem https://example.com/update.raucb"
+        );
+        assert_eq!(
+            shell_words(&command),
+            [
+                "rauc",
+                "install",
+                "--http-header=Authorization: Bearer it's",
+                "--tls-ca=/etc/rauc/ca.pem",
+                "https://example.com/update.raucb",
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_a_limited_number_of_idle_sessions() {
+        let backend = backend("root@127.0.0.1:1").unwrap();
+        for _ in 0..MAX_IDLE_SESSIONS + 2 {
+            backend.release(Session::new().unwrap());
+        }
+        assert_eq!(
+            backend.idle_sessions.lock().unwrap().len(),
+            MAX_IDLE_SESSIONS
+        );
+
+        // A failed channel on an idle session drops the others before connecting again, which
+        // is refused
+        let error = backend
+            .open_channel(|_| {
+                Err(ssh2::Error::new(
+                    ErrorCode::Session(-7),
+                    "Socket disconnected",
+                ))
+            })
+            .err()
+            .unwrap();
+        assert!(matches!(error, RaucError::TargetUnreachable(_)));
+        assert!(backend.idle_sessions.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,285 @@
+use crate::error::RaucError;
+use crate::rauc::RaucBundleInfo;
+use crate::validation::BundleValidation;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+const INDEX_FILE: &str = "index.json";
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Bundle kept in the repository
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredBundle {
+    pub id: String,
+    pub sha256: String,
+    /// Name of the file as uploaded by the client
+    pub filename: String,
+    pub size: u64,
+    /// Unix timestamp in seconds
+    pub uploaded_at: u64,
+    /// Client that uploaded the bundle
+    pub uploaded_by: String,
+    /// Output of `rauc info` at upload time
+    pub info: RaucBundleInfo,
+    /// Checks the bundle passed when it was uploaded
+    pub validation: BundleValidation,
+}
+
+/// Repository of uploaded bundles, stored as `<id>.raucb` next to a JSON index
+pub struct BundleStore {
+    dir: PathBuf,
+    /// Most recent upload first
+    bundles: Mutex<Vec<StoredBundle>>,
+}
+
+pub async fn sha256_file(path: &Path) -> Result<String, RaucError> {
+    let mut file = File::open(path)
+        .await
+        .map_err(|e| RaucError::Internal(format!("Failed to open bundle: {}", e)))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; BUFFER_SIZE];
+
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .await
+            .map_err(|e| RaucError::Internal(format!("Failed to read bundle: {}", e)))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+impl BundleStore {
+    /// Open the repository in `dir`, loading the index left by a previous run
+    pub fn open(dir: PathBuf) -> Self {
+        let bundles = std::fs::read_to_string(dir.join(INDEX_FILE))
+            .ok()
+            .and_then(|index| match serde_json::from_str(&index) {
+                Ok(bundles) => Some(bundles),
+                Err(e) => {
+                    eprintln!("Ignoring unreadable bundle index: {}", e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        Self {
+            dir,
+            bundles: Mutex::new(bundles),
+        }
+    }
+
+    /// Reserve an ID and the path its bundle file is to be stored at
+    pub async fn allocate(&self) -> Result<(String, PathBuf), RaucError> {
+        tokio::fs::create_dir_all(&self.dir).await.map_err(|e| {
+            RaucError::Internal(format!("Failed to create bundle directory: {}", e))
+        })?;
+
+        let id = Uuid::new_v4().to_string();
+        let path = self.path(&id);
+        Ok((id, path))
+    }
+
+    pub fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.raucb", id))
+    }
+
+    fn save(&self, bundles: &[StoredBundle]) -> Result<(), RaucError> {
+        let index = serde_json::to_string_pretty(bundles)
+            .map_err(|e| RaucError::Internal(format!("Failed to serialize bundle index: {}", e)))?;
+
+        // Write to a temporary file first so a crash never leaves a truncated index
+        let temporary = self.dir.join(format!("{}.tmp", INDEX_FILE));
+        std::fs::write(&temporary, index)
+            .and_then(|_| std::fs::rename(&temporary, self.dir.join(INDEX_FILE)))
+            .map_err(|e| RaucError::Internal(format!("Failed to write bundle index: {}", e)))
+    }
+
+    /// Add a bundle whose file was stored at `path(bundle.id)`
+    pub fn insert(&self, bundle: StoredBundle) -> Result<StoredBundle, RaucError> {
+        let mut bundles = self.bundles.lock().unwrap();
+        bundles.insert(0, bundle.clone());
+        self.save(&bundles)?;
+        Ok(bundle)
+    }
+
+    pub fn find_by_sha256(&self, sha256: &str) -> Option<StoredBundle> {
+        self.bundles
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|bundle| bundle.sha256 == sha256)
+            .cloned()
+    }
+
+    pub fn list(&self) -> Vec<StoredBundle> {
+        self.bundles.lock().unwrap().clone()
+    }
+
+    pub fn get(&self, id: &str) -> Result<StoredBundle, RaucError> {
+        self.bundles
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|bundle| bundle.id == id)
+            .cloned()
+            .ok_or_else(|| RaucError::NotFound(format!("Bundle {} not found", id)))
+    }
+
+    /// Most recently uploaded bundle
+    pub fn latest(&self) -> Result<StoredBundle, RaucError> {
+        self.bundles
+            .lock()
+            .unwrap()
+            .first()
+            .cloned()
+            .ok_or(RaucError::NoBundle)
+    }
+
+    pub async fn remove(&self, id: &str) -> Result<StoredBundle, RaucError> {
+        let bundle = {
+            let mut bundles = self.bundles.lock().unwrap();
+            let index = bundles
+                .iter()
+                .position(|bundle| bundle.id == id)
+                .ok_or_else(|| RaucError::NotFound(format!("Bundle {} not found", id)))?;
+            let bundle = bundles.remove(index);
+            self.save(&bundles)?;
+            bundle
+        };
+
+        if let Err(e) = tokio::fs::remove_file(self.path(id)).await {
+            eprintln!("Failed to delete bundle file of {}: {}", id, e);
+        }
+
+        Ok(bundle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use tempfile::TempDir;
+
+    fn stored_bundle(id: &str, sha256: &str) -> StoredBundle {
+        StoredBundle {
+            id: id.to_string(),
+            sha256: sha256.to_string(),
+            filename: format!("{}.raucb", id),
+            size: 4,
+            uploaded_at: 1_700_000_000,
+            uploaded_by: "admin".to_string(),
+            info: RaucBundleInfo {
+                compatible: "rauc-web-ui-test".to_string(),
+                version: "1.0.0".to_string(),
+                description: None,
+                build: None,
+                format: Some("verity".to_string()),
+                hooks: Vec::new(),
+                hash: None,
+                images: Vec::new(),
+                meta: BTreeMap::new(),
+                extra: Default::default(),
+            },
+            validation: BundleValidation {
+                valid: true,
+                checks: Vec::new(),
+            },
+        }
+    }
+
+    fn ids(bundles: &[StoredBundle]) -> Vec<&str> {
+        bundles.iter().map(|bundle| bundle.id.as_str()).collect()
+    }
+
+    #[test]
+    fn index_persists_across_restarts() {
+        let dir = TempDir::new().unwrap();
+        {
+            let store = BundleStore::open(dir.path().to_path_buf());
+            store.insert(stored_bundle("first", "aa")).unwrap();
+            store.insert(stored_bundle("second", "bb")).unwrap();
+        }
+
+        let store = BundleStore::open(dir.path().to_path_buf());
+        assert_eq!(ids(&store.list()), ["second", "first"]);
+        assert_eq!(store.latest().unwrap().id, "second");
+        assert_eq!(store.get("first").unwrap().sha256, "aa");
+        assert!(matches!(store.get("third"), Err(RaucError::NotFound(_))));
+    }
+
+    #[test]
+    fn index_is_replaced_atomically() {
+        let dir = TempDir::new().unwrap();
+        let store = BundleStore::open(dir.path().to_path_buf());
+        store.insert(stored_bundle("first", "aa")).unwrap();
+
+        // Only the complete index is left, the temporary file was renamed over it
+        let files: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, [INDEX_FILE]);
+        let index = std::fs::read_to_string(dir.path().join(INDEX_FILE)).unwrap();
+        let bundles: Vec<StoredBundle> = serde_json::from_str(&index).unwrap();
+        assert_eq!(ids(&bundles), ["first"]);
+
+        // A corrupted index is ignored rather than failing startup
+        std::fs::write(dir.path().join(INDEX_FILE), &index[..index.len() / 2]).unwrap();
+        let store = BundleStore::open(dir.path().to_path_buf());
+        assert!(store.list().is_empty());
+        assert!(matches!(store.latest(), Err(RaucError::NoBundle)));
+    }
+
+    #[tokio::test]
+    async fn remove_deletes_the_bundle_and_its_file() {
+        let dir = TempDir::new().unwrap();
+        let store = BundleStore::open(dir.path().to_path_buf());
+        let (id, path) = store.allocate().await.unwrap();
+        assert_eq!(path, store.path(&id));
+        std::fs::write(&path, b"hsqs").unwrap();
+        store.insert(stored_bundle(&id, "aa")).unwrap();
+        store.insert(stored_bundle("other", "bb")).unwrap();
+
+        assert_eq!(store.remove(&id).await.unwrap().id, id);
+        assert!(!path.exists());
+        assert_eq!(ids(&store.list()), ["other"]);
+        assert!(matches!(
+            store.remove(&id).await,
+            Err(RaucError::NotFound(_))
+        ));
+
+        let store = BundleStore::open(dir.path().to_path_buf());
+        assert_eq!(ids(&store.list()), ["other"]);
+    }
+
+    #[tokio::test]
+    async fn finds_duplicates_by_sha256() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("update.raucb");
+        std::fs::write(&path, b"hsqs").unwrap();
+        let sha256 = sha256_file(&path).await.unwrap();
+        assert_eq!(
+            sha256,
+            "53bff6664974960b06bcb260bd4b83494c04c71a62623bcadd7986c4d4adf3a1"
+        );
+
+        let store = BundleStore::open(dir.path().join("bundles"));
+        std::fs::create_dir_all(dir.path().join("bundles")).unwrap();
+        store.insert(stored_bundle("first", &sha256)).unwrap();
+        store.insert(stored_bundle("other", "bb")).unwrap();
+
+        assert_eq!(store.find_by_sha256(&sha256).unwrap().id, "first");
+        assert!(store.find_by_sha256("cc").is_none());
+    }
+}
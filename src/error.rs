@@ -0,0 +1,309 @@
+use crate::lock::Operation;
+use crate::validation::BundleValidation;
+use rocket::http::Status;
+use rocket::response::{self, status, Responder};
+use rocket::serde::json::Json;
+use rocket::Request;
+use serde::Serialize;
+use std::fmt;
+
+/// Classified failure of a RAUC operation or API request
+#[derive(Debug, Clone)]
+pub enum RaucError {
+    /// No bundle has been uploaded yet
+    NoBundle,
+    /// A requested resource such as an install job does not exist
+    NotFound(String),
+    /// The request itself is invalid, e.g. a malformed bundle URL
+    BadRequest(String),
+    /// The request carries no valid session or the login failed
+    Unauthorized(String),
+    /// The request is authenticated but not allowed, e.g. an API token lacking a scope
+    Forbidden(String),
+    /// The bundle's compatible does not match the system
+    IncompatibleBundle { stderr: String },
+    /// The bundle signature could not be verified
+    InvalidSignature { stderr: String },
+    /// The bundle is damaged or in an unsupported format
+    InvalidBundle { stderr: String },
+    /// A bundle streamed from a URL could not be fetched, e.g. the server's TLS certificate
+    /// was not trusted or the client certificate was refused
+    StreamingFailed { stderr: String },
+    /// An uploaded bundle failed validation and was deleted
+    BundleRejected(BundleValidation),
+    /// An uploaded file does not match the SHA-256 announced by the client
+    ChecksumMismatch { expected: String, actual: String },
+    /// The version policy forbids a downgrade or reinstall that was not forced
+    VersionNotAllowed(String),
+    /// There is no other slot with a valid bundle to roll back to
+    RollbackUnavailable(String),
+    /// RAUC itself is busy with another operation
+    Busy(String),
+    /// Another operation started through this server holds the operation lock
+    OperationInProgress(Operation),
+    /// The target system or RAUC service could not be reached
+    TargetUnreachable(String),
+    /// rauc ran but reported an error that is not classified further
+    CommandFailed { message: String, stderr: String },
+    /// rauc produced output that could not be parsed
+    InvalidOutput(String),
+    /// Failure on the server itself, e.g. file system errors
+    Internal(String),
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'static str,
+    message: String,
+    stderr: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    operation: Option<&'a Operation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    validation: Option<&'a BundleValidation>,
+}
+
+impl RaucError {
+    /// Classify a failed rauc invocation based on its stderr output
+    pub fn from_rauc_stderr(stderr: &str) -> Self {
+        let lowercase = stderr.to_lowercase();
+        let stderr = stderr.trim().to_string();
+
+        if lowercase.contains("compatible") && lowercase.contains("mismatch") {
+            RaucError::IncompatibleBundle { stderr }
+        } else if lowercase.contains("signature verification failed")
+            || lowercase.contains("verification failed")
+        {
+            RaucError::InvalidSignature { stderr }
+        } else if [
+            "certificate",
+            "ssl",
+            "tls",
+            "curl",
+            "could not resolve",
+            "couldn't connect",
+        ]
+        .iter()
+        .any(|pattern| lowercase.contains(pattern))
+        {
+            // Reported by libcurl while streaming, not about the bundle itself
+            RaucError::StreamingFailed { stderr }
+        } else if lowercase.contains("no such file") || lowercase.contains("does not exist") {
+            RaucError::NoBundle
+        } else if (lowercase.contains("bundle") && lowercase.contains("invalid"))
+            || lowercase.contains("manifest")
+            || lowercase.contains("squashfs")
+        {
+            RaucError::InvalidBundle { stderr }
+        } else if lowercase.contains("already in progress") || lowercase.contains("busy") {
+            RaucError::Busy(stderr)
+        } else {
+            RaucError::CommandFailed {
+                message: "rauc command failed".to_string(),
+                stderr,
+            }
+        }
+    }
+
+    /// Machine-readable error code included in API responses
+    pub fn code(&self) -> &'static str {
+        match self {
+            RaucError::NoBundle => "no_bundle",
+            RaucError::NotFound(_) => "not_found",
+            RaucError::BadRequest(_) => "bad_request",
+            RaucError::Unauthorized(_) => "unauthorized",
+            RaucError::Forbidden(_) => "forbidden",
+            RaucError::IncompatibleBundle { .. } => "incompatible_bundle",
+            RaucError::InvalidSignature { .. } => "invalid_signature",
+            RaucError::InvalidBundle { .. } => "invalid_bundle",
+            RaucError::StreamingFailed { .. } => "streaming_failed",
+            RaucError::BundleRejected(_) => "bundle_rejected",
+            RaucError::ChecksumMismatch { .. } => "checksum_mismatch",
+            RaucError::VersionNotAllowed(_) => "version_not_allowed",
+            RaucError::RollbackUnavailable(_) => "rollback_unavailable",
+            RaucError::Busy(_) => "busy",
+            RaucError::OperationInProgress(_) => "operation_in_progress",
+            RaucError::TargetUnreachable(_) => "target_unreachable",
+            RaucError::CommandFailed { .. } => "rauc_failed",
+            RaucError::InvalidOutput(_) => "invalid_output",
+            RaucError::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            RaucError::NoBundle | RaucError::NotFound(_) => Status::NotFound,
+            RaucError::BadRequest(_) => Status::BadRequest,
+            RaucError::Unauthorized(_) => Status::Unauthorized,
+            RaucError::Forbidden(_) => Status::Forbidden,
+            RaucError::IncompatibleBundle { .. }
+            | RaucError::InvalidSignature { .. }
+            | RaucError::InvalidBundle { .. }
+            | RaucError::BundleRejected(_)
+            | RaucError::ChecksumMismatch { .. } => Status::UnprocessableEntity,
+            RaucError::VersionNotAllowed(_)
+            | RaucError::RollbackUnavailable(_)
+            | RaucError::Busy(_)
+            | RaucError::OperationInProgress(_) => Status::Conflict,
+            RaucError::TargetUnreachable(_) | RaucError::StreamingFailed { .. } => {
+                Status::BadGateway
+            }
+            RaucError::CommandFailed { .. }
+            | RaucError::InvalidOutput(_)
+            | RaucError::Internal(_) => Status::InternalServerError,
+        }
+    }
+
+    /// Raw stderr output of rauc, if the error originated from it
+    pub fn stderr(&self) -> Option<&str> {
+        match self {
+            RaucError::IncompatibleBundle { stderr }
+            | RaucError::InvalidSignature { stderr }
+            | RaucError::InvalidBundle { stderr }
+            | RaucError::StreamingFailed { stderr }
+            | RaucError::CommandFailed { stderr, .. } => Some(stderr),
+            RaucError::BundleRejected(validation) => validation
+                .failure()
+                .and_then(|check| check.stderr.as_deref()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RaucError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RaucError::NoBundle => write!(f, "No bundle has been uploaded"),
+            RaucError::IncompatibleBundle { .. } => {
+                write!(f, "Bundle is not compatible with this system")
+            }
+            RaucError::InvalidSignature { .. } => {
+                write!(f, "Bundle signature could not be verified")
+            }
+            RaucError::InvalidBundle { .. } => write!(f, "Bundle is invalid"),
+            RaucError::StreamingFailed { .. } => write!(f, "Bundle could not be fetched"),
+            RaucError::BundleRejected(validation) => match validation.failure() {
+                Some(check) => write!(f, "Bundle rejected: {}", check.message),
+                None => write!(f, "Bundle rejected"),
+            },
+            RaucError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Uploaded file has SHA-256 {} instead of {}",
+                actual, expected
+            ),
+            RaucError::OperationInProgress(operation) => write!(
+                f,
+                "Another {} started by {} is in progress",
+                operation.kind, operation.started_by
+            ),
+            RaucError::NotFound(message)
+            | RaucError::BadRequest(message)
+            | RaucError::Unauthorized(message)
+            | RaucError::Forbidden(message)
+            | RaucError::VersionNotAllowed(message)
+            | RaucError::RollbackUnavailable(message)
+            | RaucError::Busy(message)
+            | RaucError::TargetUnreachable(message)
+            | RaucError::CommandFailed { message, .. }
+            | RaucError::InvalidOutput(message)
+            | RaucError::Internal(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for RaucError {}
+
+impl<'r> Responder<'r, 'static> for RaucError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let body = ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+            stderr: self.stderr(),
+            operation: match &self {
+                RaucError::OperationInProgress(operation) => Some(operation),
+                _ => None,
+            },
+            validation: match &self {
+                RaucError::BundleRejected(validation) => Some(validation),
+                _ => None,
+            },
+        };
+
+        status::Custom(self.status(), Json(body)).respond_to(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify(stderr: &str) -> &'static str {
+        RaucError::from_rauc_stderr(stderr).code()
+    }
+
+    #[test]
+    fn classifies_rauc_errors() {
+        let cases = [
+            (
+                "Failed to check bundle: Compatible mismatch: Expected 'raspberrypi5' but bundle manifest has 'raspberrypi4'",
+                "incompatible_bundle",
+            ),
+            (
+                "Failed to check bundle: signature verification failed: Verify error:unable to get local issuer certificate",
+                "invalid_signature",
+            ),
+            (
+                "Failed to verify bundle: Verification failed: certificate has expired",
+                "invalid_signature",
+            ),
+            (
+                "Failed to start streaming: SSL certificate problem: unable to get local issuer certificate",
+                "streaming_failed",
+            ),
+            (
+                "Failed to start streaming: Problem with the local SSL certificate",
+                "streaming_failed",
+            ),
+            (
+                "Failed to start streaming: curl error: Could not resolve host: updates.example.com",
+                "streaming_failed",
+            ),
+            (
+                "Failed to open bundle: /data/update.raucb: No such file or directory",
+                "no_bundle",
+            ),
+            (
+                "Failed to load bundle: Bundle is invalid: unknown format",
+                "invalid_bundle",
+            ),
+            (
+                "Failed to check bundle: Failed to load manifest: Key file does not have key 'compatible'",
+                "invalid_bundle",
+            ),
+            (
+                "Failed to mount bundle: mount: /mnt/rauc: wrong fs type, bad option, bad superblock on /dev/loop0, missing codepage or helper program, squashfs",
+                "invalid_bundle",
+            ),
+            ("Installation already in progress", "busy"),
+            ("D-Bus service busy", "busy"),
+            ("Failed to mark slot: rootfs.7 not found", "rauc_failed"),
+        ];
+        for (stderr, code) in cases {
+            assert_eq!(classify(stderr), code, "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn keeps_stderr_of_rauc_errors() {
+        let error = RaucError::from_rauc_stderr("  Installation already in progress\n");
+        assert_eq!(error.status(), Status::Conflict);
+
+        let error = RaucError::from_rauc_stderr("SSL peer certificate was not OK\n");
+        assert_eq!(error.status(), Status::BadGateway);
+        assert_eq!(error.stderr(), Some("SSL peer certificate was not OK"));
+
+        let error = RaucError::from_rauc_stderr("Failed to mark slot\n");
+        assert_eq!(error.status(), Status::InternalServerError);
+        assert_eq!(error.stderr(), Some("Failed to mark slot"));
+        assert_eq!(error.to_string(), "rauc command failed");
+    }
+}
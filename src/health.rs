@@ -0,0 +1,419 @@
+use crate::error::RaucError;
+use crate::jobs::unix_now;
+use crate::lock::{OperationKind, OperationLock};
+use crate::rauc::{RaucClient, SlotMark};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+/// Name recorded as the initiator of slot marks and reboots done by the health checks
+const HEALTH_CHECK_REQUESTER: &str = "health check";
+
+fn default_timeout() -> u64 {
+    10
+}
+
+/// What a health check probes
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum CheckKind {
+    /// Shell command that must exit with status 0
+    Command { command: String },
+    /// URL that must answer with a 2xx status, or `status` if given
+    Http { url: String, status: Option<u16> },
+    /// systemd unit that must be active
+    Systemd { unit: String },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HealthCheck {
+    /// Defaults to the command, URL or unit
+    name: Option<String>,
+    #[serde(flatten)]
+    kind: CheckKind,
+    /// In seconds
+    #[serde(default = "default_timeout")]
+    timeout: u64,
+}
+
+impl HealthCheck {
+    fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| match &self.kind {
+            CheckKind::Command { command } => command.clone(),
+            CheckKind::Http { url, .. } => url.clone(),
+            CheckKind::Systemd { unit } => unit.clone(),
+        })
+    }
+}
+
+/// Contents of the file given by `HEALTH_CHECK_CONFIG`
+#[derive(Debug, Clone, Deserialize)]
+pub struct HealthConfig {
+    /// Seconds to wait after startup before running the checks, e.g. for services to come up
+    #[serde(default)]
+    delay: u64,
+    /// Reboot after marking the booted slot bad
+    #[serde(default)]
+    reboot_on_failure: bool,
+    checks: Vec<HealthCheck>,
+}
+
+impl HealthConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let config = std::fs::read_to_string(path).map_err(|e| {
+            format!(
+                "Failed to read health check config {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        serde_json::from_str(&config).map_err(|e| {
+            format!(
+                "Failed to parse health check config {}: {}",
+                path.display(),
+                e
+            )
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthState {
+    /// No health checks are configured
+    Disabled,
+    /// Waiting for the configured delay
+    Pending,
+    Running,
+    Passed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub passed: bool,
+    pub message: String,
+    /// Time the check took, in milliseconds
+    pub duration_ms: u64,
+}
+
+/// Outcome of the health checks run after startup, as returned by the API
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub state: HealthState,
+    /// Unix timestamps in seconds
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+    pub checks: Vec<CheckResult>,
+    /// Messages of the slot mark and reboot done in response to the checks
+    pub actions: Vec<String>,
+    /// Set if the booted slot could not be marked
+    pub error: Option<String>,
+}
+
+/// Runs the configured health checks once and marks the booted slot accordingly
+#[derive(Clone)]
+pub struct HealthMonitor {
+    config: Option<Arc<HealthConfig>>,
+    report: Arc<Mutex<HealthReport>>,
+}
+
+/// Run a command, failing if it does not exit successfully within `timeout`
+async fn run_command(command: &mut Command, timeout: Duration) -> Result<String, String> {
+    let output = command.stdin(Stdio::null()).kill_on_drop(true).output();
+    let output = tokio::time::timeout(timeout, output)
+        .await
+        .map_err(|_| format!("Timed out after {} s", timeout.as_secs()))?
+        .map_err(|e| format!("Failed to run command: {}", e))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if output.status.success() {
+        Ok(stdout)
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        let detail = if stderr.is_empty() { stdout } else { stderr };
+        Err(format!("Exited with {}: {}", output.status, detail))
+    }
+}
+
+async fn probe_url(url: &str, status: Option<u16>, timeout: Duration) -> Result<String, String> {
+    let client = reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+
+    let actual = response.status();
+    let passed = match status {
+        Some(expected) => actual.as_u16() == expected,
+        None => actual.is_success(),
+    };
+    if passed {
+        Ok(format!("Responded with {}", actual))
+    } else {
+        Err(format!("Responded with {}", actual))
+    }
+}
+
+async fn run_check(check: &HealthCheck) -> CheckResult {
+    let started = Instant::now();
+    let timeout = Duration::from_secs(check.timeout);
+
+    let result = match &check.kind {
+        CheckKind::Command { command } => {
+            run_command(Command::new("sh").arg("-c").arg(command), timeout)
+                .await
+                .map(|_| "Exited successfully".to_string())
+        }
+        CheckKind::Http { url, status } => probe_url(url, *status, timeout).await,
+        CheckKind::Systemd { unit } => run_command(
+            Command::new("systemctl").arg("is-active").arg(unit),
+            timeout,
+        )
+        .await
+        .map(|state| format!("Unit is {}", state)),
+    };
+
+    let (passed, message) = match result {
+        Ok(message) => (true, message),
+        Err(message) => (false, message),
+    };
+    CheckResult {
+        name: check.name(),
+        passed,
+        message,
+        duration_ms: started.elapsed().as_millis() as u64,
+    }
+}
+
+impl HealthMonitor {
+    pub fn new(config: Option<HealthConfig>) -> Self {
+        let state = match config {
+            Some(_) => HealthState::Pending,
+            None => HealthState::Disabled,
+        };
+
+        Self {
+            config: config.map(Arc::new),
+            report: Arc::new(Mutex::new(HealthReport {
+                state,
+                started_at: None,
+                finished_at: None,
+                checks: Vec::new(),
+                actions: Vec::new(),
+                error: None,
+            })),
+        }
+    }
+
+    pub fn report(&self) -> HealthReport {
+        self.report.lock().unwrap().clone()
+    }
+
+    fn update(&self, apply: impl FnOnce(&mut HealthReport)) {
+        apply(&mut self.report.lock().unwrap());
+    }
+
+    /// Run the checks, then mark the booted slot good or bad and reboot if configured
+    pub async fn run(self, client: RaucClient, lock: OperationLock) {
+        let Some(config) = self.config.clone() else {
+            return;
+        };
+
+        tokio::time::sleep(Duration::from_secs(config.delay)).await;
+        self.update(|report| {
+            report.state = HealthState::Running;
+            report.started_at = Some(unix_now());
+        });
+
+        for check in &config.checks {
+            let result = run_check(check).await;
+            println!(
+                "Health check {}: {} ({})",
+                result.name,
+                if result.passed { "passed" } else { "failed" },
+                result.message
+            );
+            self.update(|report| report.checks.push(result));
+        }
+
+        let passed = self.report().checks.iter().all(|check| check.passed);
+        let outcome = self
+            .respond(&client, &lock, passed, config.reboot_on_failure)
+            .await;
+
+        self.update(|report| {
+            report.state = if passed {
+                HealthState::Passed
+            } else {
+                HealthState::Failed
+            };
+            report.finished_at = Some(unix_now());
+            if let Err(error) = outcome {
+                eprintln!("Failed to act on health checks: {}", error);
+                report.error = Some(error.to_string());
+            }
+        });
+    }
+
+    /// Mark the booted slot good if all checks passed, otherwise bad
+    async fn respond(
+        &self,
+        client: &RaucClient,
+        lock: &OperationLock,
+        passed: bool,
+        reboot_on_failure: bool,
+    ) -> Result<(), RaucError> {
+        let mark = if passed {
+            SlotMark::Good
+        } else {
+            SlotMark::Bad
+        };
+        // Wait for operations started meanwhile instead of leaving the slot unmarked
+        let guard = lock
+            .acquire_waiting(OperationKind::MarkSlot, HEALTH_CHECK_REQUESTER, None)
+            .await;
+        let message = client.mark_slot("booted", mark).await?;
+        println!(
+            "Health checks {}: {}",
+            if passed { "passed" } else { "failed" },
+            message
+        );
+        self.update(|report| report.actions.push(message));
+        drop(guard);
+
+        if !passed && reboot_on_failure {
+            let _guard = lock
+                .acquire_waiting(OperationKind::Reboot, HEALTH_CHECK_REQUESTER, None)
+                .await;
+            let message = client.reboot().await?;
+            self.update(|report| report.actions.push(message));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use crate::rauc::BootStatus;
+
+    fn command(command: &str, timeout: u64) -> HealthCheck {
+        HealthCheck {
+            name: None,
+            kind: CheckKind::Command {
+                command: command.to_string(),
+            },
+            timeout,
+        }
+    }
+
+    fn monitor(checks: Vec<HealthCheck>, reboot_on_failure: bool) -> HealthMonitor {
+        HealthMonitor::new(Some(HealthConfig {
+            delay: 0,
+            reboot_on_failure,
+            checks,
+        }))
+    }
+
+    async fn booted_status(client: &RaucClient) -> Option<BootStatus> {
+        let status = client.get_status().await.unwrap();
+        status.booted_slot().unwrap().1.boot_status
+    }
+
+    #[tokio::test]
+    async fn passing_checks_mark_the_booted_slot_good() {
+        let client = RaucClient::new(MockBackend::new());
+        client.mark_slot("booted", SlotMark::Bad).await.unwrap();
+        let monitor = monitor(vec![command("true", 10)], true);
+        assert_eq!(monitor.report().state, HealthState::Pending);
+
+        monitor
+            .clone()
+            .run(client.clone(), OperationLock::new())
+            .await;
+
+        let report = monitor.report();
+        assert_eq!(report.state, HealthState::Passed);
+        assert_eq!(report.checks.len(), 1);
+        assert_eq!(report.checks[0].name, "true");
+        assert_eq!(report.actions.len(), 1);
+        assert!(report.error.is_none());
+        assert_eq!(booted_status(&client).await, Some(BootStatus::Good));
+        assert_eq!(client.boot_id().await.unwrap(), "mock-boot-0");
+    }
+
+    #[tokio::test]
+    async fn failing_checks_mark_the_booted_slot_bad_and_reboot() {
+        let client = RaucClient::new(MockBackend::new());
+        let monitor = monitor(vec![command("true", 10), command("exit 3", 10)], true);
+
+        monitor
+            .clone()
+            .run(client.clone(), OperationLock::new())
+            .await;
+
+        let report = monitor.report();
+        assert_eq!(report.state, HealthState::Failed);
+        assert!(report.checks[0].passed);
+        assert!(!report.checks[1].passed);
+        assert!(report.checks[1].message.starts_with("Exited with"));
+        assert_eq!(report.actions.len(), 2);
+        assert_eq!(booted_status(&client).await, Some(BootStatus::Bad));
+        assert_eq!(client.boot_id().await.unwrap(), "mock-boot-1");
+    }
+
+    #[tokio::test]
+    async fn checks_fail_when_timed_out() {
+        let client = RaucClient::new(MockBackend::new());
+        let monitor = monitor(vec![command("sleep 10", 1)], false);
+
+        let started = Instant::now();
+        monitor
+            .clone()
+            .run(client.clone(), OperationLock::new())
+            .await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let report = monitor.report();
+        assert_eq!(report.state, HealthState::Failed);
+        assert_eq!(report.checks[0].message, "Timed out after 1 s");
+        // Without reboot_on_failure the slot is only marked
+        assert_eq!(report.actions.len(), 1);
+        assert_eq!(booted_status(&client).await, Some(BootStatus::Bad));
+        assert_eq!(client.boot_id().await.unwrap(), "mock-boot-0");
+    }
+
+    #[tokio::test]
+    async fn marking_waits_for_a_running_operation() {
+        let client = RaucClient::new(MockBackend::new());
+        client.mark_slot("booted", SlotMark::Bad).await.unwrap();
+        let lock = OperationLock::new();
+        let guard = lock.acquire(OperationKind::Install, "admin", None).unwrap();
+
+        let monitor = monitor(vec![command("true", 10)], false);
+        let run = tokio::spawn(monitor.clone().run(client.clone(), lock.clone()));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(monitor.report().state, HealthState::Running);
+        assert_eq!(booted_status(&client).await, Some(BootStatus::Bad));
+
+        drop(guard);
+        tokio::time::timeout(Duration::from_secs(1), run)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(monitor.report().state, HealthState::Passed);
+        assert!(monitor.report().error.is_none());
+        assert_eq!(booted_status(&client).await, Some(BootStatus::Good));
+    }
+}
@@ -0,0 +1,134 @@
+use crate::error::RaucError;
+use crate::jobs::{unix_now, JobInfo};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+const INDEX_FILE: &str = "index.json";
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// How many finished installations are kept
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    pub max_entries: usize,
+    pub max_age_days: u64,
+}
+
+struct HistoryInner {
+    dir: PathBuf,
+    retention: Retention,
+    /// Most recent installation first
+    entries: Mutex<Vec<JobInfo>>,
+}
+
+/// Record of finished installations, stored as a JSON index with one log file per installation
+#[derive(Clone)]
+pub struct InstallHistory {
+    inner: Arc<HistoryInner>,
+}
+
+impl HistoryInner {
+    fn log_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.log", id))
+    }
+
+    /// Drop entries beyond the retention limits along with their logs
+    fn prune(&self, entries: &mut Vec<JobInfo>) {
+        let deadline = unix_now().saturating_sub(self.retention.max_age_days * SECONDS_PER_DAY);
+
+        let mut position = 0;
+        entries.retain(|entry| {
+            position += 1;
+            let keep = position <= self.retention.max_entries && entry.created_at >= deadline;
+            if !keep {
+                let _ = std::fs::remove_file(self.log_path(&entry.id));
+            }
+            keep
+        });
+    }
+
+    fn save(&self, entries: &[JobInfo]) -> Result<(), RaucError> {
+        let index = serde_json::to_string_pretty(entries).map_err(|e| {
+            RaucError::Internal(format!("Failed to serialize install history: {}", e))
+        })?;
+
+        // Write to a temporary file first so a crash never leaves a truncated index
+        let temporary = self.dir.join(format!("{}.tmp", INDEX_FILE));
+        std::fs::write(&temporary, index)
+            .and_then(|_| std::fs::rename(&temporary, self.dir.join(INDEX_FILE)))
+            .map_err(|e| RaucError::Internal(format!("Failed to write install history: {}", e)))
+    }
+}
+
+impl InstallHistory {
+    /// Open the history in `dir`, loading the entries left by a previous run
+    pub fn open(dir: PathBuf, retention: Retention) -> Self {
+        let mut entries: Vec<JobInfo> = std::fs::read_to_string(dir.join(INDEX_FILE))
+            .ok()
+            .and_then(|index| match serde_json::from_str(&index) {
+                Ok(entries) => Some(entries),
+                Err(e) => {
+                    eprintln!("Ignoring unreadable install history: {}", e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        let inner = HistoryInner {
+            dir,
+            retention,
+            entries: Mutex::new(Vec::new()),
+        };
+
+        // Entries may have expired while the server was not running
+        let loaded = entries.len();
+        inner.prune(&mut entries);
+        if entries.len() != loaded {
+            if let Err(e) = inner.save(&entries) {
+                eprintln!("{}", e);
+            }
+        }
+        *inner.entries.lock().unwrap() = entries;
+
+        Self {
+            inner: Arc::new(inner),
+        }
+    }
+
+    /// Store a finished installation and its log
+    pub fn record(&self, entry: JobInfo, log: &str) -> Result<(), RaucError> {
+        std::fs::create_dir_all(&self.inner.dir).map_err(|e| {
+            RaucError::Internal(format!("Failed to create history directory: {}", e))
+        })?;
+        std::fs::write(self.inner.log_path(&entry.id), log)
+            .map_err(|e| RaucError::Internal(format!("Failed to write install log: {}", e)))?;
+
+        let mut entries = self.inner.entries.lock().unwrap();
+        entries.insert(0, entry);
+        self.inner.prune(&mut entries);
+        self.inner.save(&entries)
+    }
+
+    pub fn list(&self) -> Vec<JobInfo> {
+        self.inner.entries.lock().unwrap().clone()
+    }
+
+    /// Full output of an installation
+    pub fn log(&self, id: &str) -> Result<String, RaucError> {
+        let known = self
+            .inner
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|entry| entry.id == id);
+        if !known {
+            return Err(RaucError::NotFound(format!(
+                "Installation {} not found",
+                id
+            )));
+        }
+
+        std::fs::read_to_string(self.inner.log_path(id))
+            .map_err(|e| RaucError::Internal(format!("Failed to read install log: {}", e)))
+    }
+}
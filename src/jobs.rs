@@ -0,0 +1,564 @@
+use crate::backend::{InstallOutput, StreamingOptions};
+use crate::history::InstallHistory;
+use crate::lock::OperationGuard;
+use crate::progress::{self, InstallProgress, StepStatus};
+use crate::rauc::{RaucBundleInfo, RaucClient};
+use crate::reboot::RebootTracker;
+use rocket::request::{FromRequest, Outcome, Request};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tokio_stream::{Stream, StreamExt};
+use uuid::Uuid;
+
+/// Number of finished jobs kept in memory for late watchers
+const FINISHED_JOBS_KEPT: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl JobState {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobState::Succeeded | JobState::Failed)
+    }
+}
+
+/// Snapshot of an install job as returned by the API, also kept in the install history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobInfo {
+    pub id: String,
+    pub state: JobState,
+    /// Path or URL of the installed bundle
+    pub bundle: String,
+    /// `rauc info` output of the bundle, if it was inspected before installing
+    pub bundle_info: Option<RaucBundleInfo>,
+    /// Client that started the installation
+    pub started_by: String,
+    /// Address the installation was requested from, if known
+    #[serde(default)]
+    pub client_ip: Option<IpAddr>,
+    /// Unix timestamps in seconds
+    pub created_at: u64,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+    pub error: Option<String>,
+    /// Exit status of rauc once the installation finished
+    pub exit_status: Option<i32>,
+    /// Slots written by the installation
+    pub slots: Vec<String>,
+    /// Version policy warnings, e.g. about a downgrade
+    pub warnings: Vec<String>,
+    pub progress: InstallProgress,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+/// Event emitted by an install job, sent to clients as a Server-Sent Event
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum JobEvent {
+    /// A line of rauc output
+    Log {
+        stream: LogStream,
+        line: String,
+    },
+    /// Overall installation progress
+    Progress {
+        percentage: u8,
+        message: String,
+    },
+    /// An installation step started or ended
+    Step {
+        name: String,
+        status: StepStatus,
+        depth: usize,
+    },
+    Completed,
+    Failed {
+        error: String,
+    },
+}
+
+impl JobEvent {
+    /// SSE event name
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobEvent::Log { .. } => "log",
+            JobEvent::Progress { .. } => "progress",
+            JobEvent::Step { .. } => "step",
+            JobEvent::Completed => "completed",
+            JobEvent::Failed { .. } => "failed",
+        }
+    }
+}
+
+/// Value of the `Last-Event-ID` header sent by reconnecting SSE clients
+pub struct LastEventId(pub Option<usize>);
+
+impl LastEventId {
+    /// Index of the first event the client has not seen yet
+    pub fn next_event(&self) -> usize {
+        self.0.map_or(0, |id| id.saturating_add(1))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        let id = request
+            .headers()
+            .get_one("Last-Event-ID")
+            .and_then(|id| id.parse().ok());
+
+        Outcome::Success(LastEventId(id))
+    }
+}
+
+struct JobInner {
+    info: JobInfo,
+    /// All events so far, the index of an event is its SSE event ID
+    events: Vec<JobEvent>,
+}
+
+/// Bundle to install and the details recorded with the job
+pub struct InstallRequest {
+    /// Path or URL of the bundle
+    pub bundle: String,
+    pub info: Option<RaucBundleInfo>,
+    pub streaming: StreamingOptions,
+    /// Version policy warnings
+    pub warnings: Vec<String>,
+    pub started_by: String,
+    pub client_ip: Option<IpAddr>,
+}
+
+/// Install job running independently of the HTTP request that created it
+pub struct InstallJob {
+    inner: Mutex<JobInner>,
+    /// Kept out of `JobInfo` as headers may carry credentials
+    streaming: StreamingOptions,
+    history: InstallHistory,
+    reboot: RebootTracker,
+    /// Bumped whenever the log or state changes, to wake up watchers
+    updates: watch::Sender<u64>,
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+impl InstallJob {
+    fn new(request: InstallRequest, history: InstallHistory, reboot: RebootTracker) -> Self {
+        Self {
+            inner: Mutex::new(JobInner {
+                info: JobInfo {
+                    id: Uuid::new_v4().to_string(),
+                    state: JobState::Queued,
+                    bundle: request.bundle,
+                    bundle_info: request.info,
+                    started_by: request.started_by,
+                    client_ip: request.client_ip,
+                    created_at: unix_now(),
+                    started_at: None,
+                    finished_at: None,
+                    error: None,
+                    exit_status: None,
+                    slots: Vec::new(),
+                    warnings: request.warnings,
+                    progress: InstallProgress::default(),
+                },
+                events: Vec::new(),
+            }),
+            streaming: request.streaming,
+            history,
+            reboot,
+            updates: watch::channel(0).0,
+        }
+    }
+
+    pub fn info(&self) -> JobInfo {
+        self.inner.lock().unwrap().info.clone()
+    }
+
+    fn update(&self, apply: impl FnOnce(&mut JobInner)) {
+        apply(&mut self.inner.lock().unwrap());
+        self.updates.send_modify(|version| *version += 1);
+    }
+
+    fn push_output(&self, stream: LogStream, line: String) {
+        self.update(|inner| {
+            if let Some(progress) = progress::parse_line(&line) {
+                // rauc checks each slot it is about to write
+                let (step, status) = progress.step();
+                if let Some(slot) = step.strip_prefix("Checking slot ") {
+                    let slot = slot.to_string();
+                    if status == StepStatus::Running && !inner.info.slots.contains(&slot) {
+                        inner.info.slots.push(slot);
+                    }
+                }
+                if let Some(update) = inner.info.progress.apply(&progress) {
+                    inner.events.push(JobEvent::Step {
+                        name: update.name,
+                        status: update.status,
+                        depth: update.depth,
+                    });
+                }
+                inner.events.push(JobEvent::Progress {
+                    percentage: progress.percentage,
+                    message: progress.message,
+                });
+            }
+            inner.events.push(JobEvent::Log { stream, line });
+        });
+    }
+
+    fn finish(&self, result: Result<(), String>, exit_status: Option<i32>) {
+        self.update(|inner| {
+            inner.info.exit_status = exit_status;
+            match result {
+                Ok(()) => {
+                    inner.events.push(JobEvent::Completed);
+                    inner.info.state = JobState::Succeeded;
+                }
+                Err(error) => {
+                    inner.info.progress.abort();
+                    inner.info.state = JobState::Failed;
+                    inner.info.error = Some(error.clone());
+                    inner.events.push(JobEvent::Failed { error });
+                }
+            }
+            inner.info.finished_at = Some(unix_now());
+        });
+
+        let (info, log) = {
+            let inner = self.inner.lock().unwrap();
+            let log: String = inner
+                .events
+                .iter()
+                .filter_map(|event| match event {
+                    JobEvent::Log { line, .. } => Some(format!("{}\n", line)),
+                    JobEvent::Failed { error } => Some(format!("{}\n", error)),
+                    _ => None,
+                })
+                .collect();
+            (inner.info.clone(), log)
+        };
+        if let Err(e) = self.history.record(info, &log) {
+            eprintln!("Failed to record installation in history: {}", e);
+        }
+    }
+
+    /// Run the installation, holding the operation lock until it finishes
+    async fn run(self: Arc<Self>, client: RaucClient, _guard: OperationGuard) {
+        let bundle = self.info().bundle;
+        self.update(|inner| {
+            inner.info.state = JobState::Running;
+            inner.info.started_at = Some(unix_now());
+        });
+
+        let mut stream = match client.install_bundle(&bundle, &self.streaming).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                self.finish(Err(format!("Failed to start installation: {}", e)), None);
+                return;
+            }
+        };
+
+        while let Some(output) = stream.next().await {
+            match output {
+                InstallOutput::Stdout(line) => self.push_output(LogStream::Stdout, line),
+                InstallOutput::Stderr(line) => self.push_output(LogStream::Stderr, line),
+                InstallOutput::Finished {
+                    result,
+                    exit_status,
+                } => {
+                    let succeeded = result.is_ok();
+                    self.finish(result, exit_status);
+                    if succeeded {
+                        // The new slot is booted on the next reboot
+                        if let Err(e) = self.reboot.refresh(&client, &self.history).await {
+                            eprintln!("Failed to update pending reboot: {}", e);
+                        }
+                    }
+                    return;
+                }
+            }
+        }
+
+        self.finish(
+            Err("Installation output ended unexpectedly".to_string()),
+            None,
+        );
+    }
+
+    /// Stream events starting at index `from`, followed by live events until the job finishes
+    pub fn watch(self: Arc<Self>, from: usize) -> impl Stream<Item = (usize, JobEvent)> {
+        async_stream::stream! {
+            let mut updates = self.updates.subscribe();
+            let mut next_event = from;
+
+            loop {
+                let (start, events, finished) = {
+                    let inner = self.inner.lock().unwrap();
+                    let start = next_event.min(inner.events.len());
+                    (start, inner.events[start..].to_vec(), inner.info.state.is_finished())
+                };
+
+                // A client ahead of the job, e.g. with the ID of another job's event, gets
+                // the events from here on under their actual IDs
+                next_event = start;
+
+                for event in events {
+                    yield (next_event, event);
+                    next_event += 1;
+                }
+
+                if finished || updates.changed().await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Keeps track of recent install jobs, recording finished ones in the install history
+pub struct JobManager {
+    jobs: Mutex<VecDeque<Arc<InstallJob>>>,
+    history: InstallHistory,
+    reboot: RebootTracker,
+}
+
+impl JobManager {
+    pub fn new(history: InstallHistory, reboot: RebootTracker) -> Self {
+        Self {
+            jobs: Mutex::new(VecDeque::new()),
+            history,
+            reboot,
+        }
+    }
+
+    /// Create a job installing a bundle and start it in the background
+    pub fn start(
+        &self,
+        client: &RaucClient,
+        request: InstallRequest,
+        guard: OperationGuard,
+    ) -> Arc<InstallJob> {
+        let mut jobs = self.jobs.lock().unwrap();
+
+        let job = Arc::new(InstallJob::new(
+            request,
+            self.history.clone(),
+            self.reboot.clone(),
+        ));
+        jobs.push_front(job.clone());
+        while jobs.len() > FINISHED_JOBS_KEPT {
+            jobs.pop_back();
+        }
+
+        tokio::spawn(job.clone().run(client.clone(), guard));
+
+        job
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<InstallJob>> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|job| job.info().id == id)
+            .cloned()
+    }
+
+    /// All known jobs, most recent first
+    pub fn list(&self) -> Vec<JobInfo> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|job| job.info())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::Retention;
+    use crate::progress::Step;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    /// Job for a bundle that is never started, recording into a temporary history
+    fn job() -> (Arc<InstallJob>, TempDir) {
+        let dir = TempDir::new().unwrap();
+        let history = InstallHistory::open(
+            dir.path().join("history"),
+            Retention {
+                max_entries: 10,
+                max_age_days: 10,
+            },
+        );
+        let reboot = RebootTracker::open(dir.path().join("reboot.json"));
+        let request = InstallRequest {
+            bundle: "/data/update.raucb".to_string(),
+            info: None,
+            streaming: StreamingOptions::default(),
+            warnings: Vec::new(),
+            started_by: "admin".to_string(),
+            client_ip: Some(IpAddr::from([192, 168, 1, 20])),
+        };
+
+        (Arc::new(InstallJob::new(request, history, reboot)), dir)
+    }
+
+    fn replay(job: &InstallJob, output: &str) {
+        for line in output.lines() {
+            job.push_output(LogStream::Stdout, line.to_string());
+        }
+    }
+
+    async fn event_ids(job: &Arc<InstallJob>, from: usize) -> Vec<usize> {
+        let events = job.clone().watch(from).map(|(id, _)| id).collect();
+        tokio::time::timeout(Duration::from_secs(5), events)
+            .await
+            .expect("watch did not end with the job")
+    }
+
+    #[test]
+    fn last_event_id_resumes_after_the_seen_event() {
+        assert_eq!(LastEventId(None).next_event(), 0);
+        assert_eq!(LastEventId(Some(4)).next_event(), 5);
+        assert_eq!(LastEventId(Some(usize::MAX)).next_event(), usize::MAX);
+    }
+
+    #[tokio::test]
+    async fn watch_resumes_at_the_requested_event() {
+        let (job, _dir) = job();
+        job.push_output(LogStream::Stdout, "installing".to_string());
+        job.push_output(LogStream::Stderr, "warning".to_string());
+        job.finish(Ok(()), Some(0));
+
+        assert_eq!(event_ids(&job, 0).await, [0, 1, 2]);
+        assert_eq!(event_ids(&job, 2).await, [2]);
+    }
+
+    #[tokio::test]
+    async fn watch_from_beyond_the_end_labels_events_with_their_ids() {
+        let (job, _dir) = job();
+        job.push_output(LogStream::Stdout, "installing".to_string());
+
+        let watcher = tokio::spawn({
+            let job = job.clone();
+            async move { event_ids(&job, usize::MAX).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        job.push_output(LogStream::Stdout, "done".to_string());
+        job.finish(Ok(()), Some(0));
+
+        assert_eq!(watcher.await.unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn records_the_slots_rauc_checks() {
+        let (job, _dir) = job();
+        replay(
+            &job,
+            include_str!("../tests/fixtures/rauc-install-success.txt"),
+        );
+        job.finish(Ok(()), Some(0));
+
+        let info = job.info();
+        assert_eq!(info.state, JobState::Succeeded);
+        assert_eq!(info.slots, ["rootfs.1", "appfs.1"]);
+        assert_eq!(info.progress.percentage, 100);
+        assert_eq!(info.progress.steps[0].status, StepStatus::Done);
+
+        let inner = job.inner.lock().unwrap();
+        let steps = inner
+            .events
+            .iter()
+            .filter(|event| matches!(event, JobEvent::Step { .. }))
+            .count();
+        // Every step starts and ends
+        assert_eq!(steps, 2 * 11);
+        assert!(matches!(inner.events.last(), Some(JobEvent::Completed)));
+    }
+
+    #[test]
+    fn failed_install_writes_no_slot() {
+        let (job, _dir) = job();
+        replay(
+            &job,
+            include_str!("../tests/fixtures/rauc-install-signature-failed.txt"),
+        );
+        job.finish(
+            Err("Installation failed with status: 1".to_string()),
+            Some(1),
+        );
+
+        let info = job.info();
+        assert_eq!(info.state, JobState::Failed);
+        assert!(info.slots.is_empty());
+        assert_eq!(info.exit_status, Some(1));
+    }
+
+    #[test]
+    fn interrupted_install_fails_the_running_steps() {
+        let (job, _dir) = job();
+        let output: String = include_str!("../tests/fixtures/rauc-install-success.txt")
+            .lines()
+            .take_while(|line| !line.contains("Copying image to rootfs.1 done."))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        replay(&job, &output);
+        job.finish(Err("Lost connection to target".to_string()), None);
+
+        fn running(steps: &[Step]) -> usize {
+            steps
+                .iter()
+                .map(|step| (step.status == StepStatus::Running) as usize + running(&step.steps))
+                .sum()
+        }
+        let info = job.info();
+        assert_eq!(info.slots, ["rootfs.1"]);
+        assert_eq!(running(&info.progress.steps), 0);
+        assert_eq!(info.progress.steps[0].status, StepStatus::Failed);
+        assert_eq!(info.error.as_deref(), Some("Lost connection to target"));
+    }
+
+    #[test]
+    fn history_records_the_client_address() {
+        let (job, _dir) = job();
+        job.finish(Ok(()), Some(0));
+
+        let entry = job.history.list().remove(0);
+        assert_eq!(entry.started_by, "admin");
+        assert_eq!(entry.client_ip, Some(IpAddr::from([192, 168, 1, 20])));
+
+        // Entries recorded before the address was kept
+        let mut json = serde_json::to_value(&entry).unwrap();
+        json.as_object_mut().unwrap().remove("client_ip");
+        let entry: JobInfo = serde_json::from_value(json).unwrap();
+        assert_eq!(entry.client_ip, None);
+    }
+}
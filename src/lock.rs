@@ -0,0 +1,183 @@
+use crate::error::RaucError;
+use crate::jobs::unix_now;
+use serde::Serialize;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationKind {
+    Upload,
+    Install,
+    Reboot,
+    DeleteBundle,
+    MarkSlot,
+    Rollback,
+}
+
+impl fmt::Display for OperationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OperationKind::Upload => write!(f, "upload"),
+            OperationKind::Install => write!(f, "installation"),
+            OperationKind::Reboot => write!(f, "reboot"),
+            OperationKind::DeleteBundle => write!(f, "bundle deletion"),
+            OperationKind::MarkSlot => write!(f, "slot marking"),
+            OperationKind::Rollback => write!(f, "rollback"),
+        }
+    }
+}
+
+/// Operation currently holding the lock
+#[derive(Debug, Clone, Serialize)]
+pub struct Operation {
+    pub kind: OperationKind,
+    /// Client that started the operation
+    pub started_by: String,
+    /// Unix timestamp in seconds
+    pub started_at: u64,
+    pub bundle: Option<String>,
+}
+
+/// Server-wide lock ensuring only one operation touches the bundle or the slots at a time
+#[derive(Clone, Default)]
+pub struct OperationLock {
+    current: Arc<Mutex<Option<Operation>>>,
+    /// Wakes tasks waiting for the lock when it is released
+    released: Arc<Notify>,
+}
+
+/// Releases the operation lock when dropped
+pub struct OperationGuard {
+    current: Arc<Mutex<Option<Operation>>>,
+    released: Arc<Notify>,
+}
+
+impl OperationLock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Acquire the lock, failing with `OperationInProgress` if another operation holds it
+    pub fn acquire(
+        &self,
+        kind: OperationKind,
+        started_by: &str,
+        bundle: Option<&str>,
+    ) -> Result<OperationGuard, RaucError> {
+        let mut current = self.current.lock().unwrap();
+
+        if let Some(operation) = current.as_ref() {
+            return Err(RaucError::OperationInProgress(operation.clone()));
+        }
+
+        *current = Some(Operation {
+            kind,
+            started_by: started_by.to_string(),
+            started_at: unix_now(),
+            bundle: bundle.map(str::to_string),
+        });
+
+        Ok(OperationGuard {
+            current: self.current.clone(),
+            released: self.released.clone(),
+        })
+    }
+
+    /// Acquire the lock, waiting for the operation holding it to finish
+    ///
+    /// For work that must not be dropped because a client happens to run an operation, e.g.
+    /// marking the booted slot after the health checks.
+    pub async fn acquire_waiting(
+        &self,
+        kind: OperationKind,
+        started_by: &str,
+        bundle: Option<&str>,
+    ) -> OperationGuard {
+        loop {
+            // Created before trying, so a release in between is not missed
+            let released = self.released.notified();
+            match self.acquire(kind, started_by, bundle) {
+                Ok(guard) => return guard,
+                Err(_) => released.await,
+            }
+        }
+    }
+}
+
+impl Drop for OperationGuard {
+    fn drop(&mut self) {
+        *self.current.lock().unwrap() = None;
+        self.released.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_acquire_reports_the_running_operation() {
+        let lock = OperationLock::new();
+        let _guard = lock
+            .acquire(OperationKind::Install, "admin", Some("update.raucb"))
+            .unwrap();
+
+        let error = lock
+            .acquire(OperationKind::Reboot, "operator", None)
+            .err()
+            .unwrap();
+        let RaucError::OperationInProgress(operation) = &error else {
+            panic!("unexpected error: {:?}", error);
+        };
+        assert_eq!(operation.kind, OperationKind::Install);
+        assert_eq!(operation.started_by, "admin");
+        assert_eq!(operation.bundle.as_deref(), Some("update.raucb"));
+        assert!(operation.started_at > 0);
+        assert_eq!(
+            error.to_string(),
+            "Another installation started by admin is in progress"
+        );
+    }
+
+    #[test]
+    fn dropping_the_guard_releases_the_lock() {
+        let lock = OperationLock::new();
+        let guard = lock.acquire(OperationKind::Upload, "admin", None).unwrap();
+        drop(guard);
+
+        let guard = lock
+            .acquire(OperationKind::MarkSlot, "operator", None)
+            .unwrap();
+        // Clones share the lock
+        assert!(lock
+            .clone()
+            .acquire(OperationKind::Upload, "admin", None)
+            .is_err());
+        drop(guard);
+        assert!(lock.acquire(OperationKind::Upload, "admin", None).is_ok());
+    }
+
+    #[tokio::test]
+    async fn acquire_waiting_waits_for_the_release() {
+        let lock = OperationLock::new();
+        let guard = lock.acquire(OperationKind::Install, "admin", None).unwrap();
+
+        let waiting = lock.clone();
+        let waiter = tokio::spawn(async move {
+            let _guard = waiting
+                .acquire_waiting(OperationKind::MarkSlot, "health check", None)
+                .await;
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!waiter.is_finished());
+
+        drop(guard);
+        tokio::time::timeout(std::time::Duration::from_secs(1), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(lock.acquire(OperationKind::Upload, "admin", None).is_ok());
+    }
+}
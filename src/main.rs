@@ -1,22 +1,67 @@
 #[macro_use]
 extern crate rocket;
 
+mod auth;
+mod backend;
+mod bundles;
+mod error;
+mod health;
+mod history;
+mod jobs;
+mod lock;
+mod progress;
 mod rauc;
+mod reboot;
+mod rollback;
+mod tls;
+mod tokens;
+mod uploads;
+mod users;
+mod validation;
+mod version;
 
-use rauc::{RaucBundleInfo, RaucClient, RaucMode, RaucStatus};
-use rocket::data::{Limits, ToByteUnit};
+use auth::{
+    Auth, AuthConfig, CanInstall, CanManageSlots, CanManageTokens, CanManageUsers, CanRead,
+    CanReboot, CanUpload, Denied, Permissions, SessionInfo, User,
+};
+use backend::{
+    is_remote_bundle, DBusBackend, DBusBus, LocalBackend, MockBackend, StreamingOptions,
+};
+#[cfg(feature = "ssh-backend")]
+use backend::{SshBackend, SshConfig};
+use bundles::{sha256_file, BundleStore, StoredBundle};
+use error::RaucError;
+use health::{HealthConfig, HealthMonitor, HealthReport};
+use history::{InstallHistory, Retention};
+use jobs::{unix_now, InstallRequest, JobInfo, JobManager, LastEventId};
+use lock::{OperationKind, OperationLock};
+use rauc::{RaucBundleInfo, RaucClient, RaucStatus, SlotMark};
+use reboot::{RebootState, RebootTracker};
+use rocket::config::{MutualTls, TlsConfig};
+use rocket::data::{Data, Limits, ToByteUnit};
+use rocket::fairing::AdHoc;
 use rocket::form::Form;
 use rocket::fs::TempFile;
-use rocket::http::{ContentType, Status};
+use rocket::http::{ContentType, CookieJar};
 use rocket::response::content::RawHtml;
-use rocket::response::stream::TextStream;
+use rocket::response::stream::{Event, EventStream};
 use rocket::serde::json::Json;
 use rocket::State;
+use rollback::{RollbackPlan, RollbackResult};
 use rust_embed::RustEmbed;
+use serde::{Deserialize, Serialize};
 use std::env;
 use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::net::IpAddr;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tls::{ClientCertConfig, ServerCertificate};
+use tokens::{ApiToken, CreatedToken, NewToken, TokenStore};
 use tokio::fs;
+use uploads::{NewUpload, UploadManager, UploadSession};
+use users::{NewUser, UserInfo, UserStore, UserUpdate};
+use validation::validate_bundle;
+use version::{VersionConfig, VersionPolicy};
 
 #[derive(RustEmbed)]
 #[folder = "static/"]
@@ -27,9 +72,75 @@ struct Upload<'r> {
     file: TempFile<'r>,
 }
 
+/// Bundle streamed by rauc from an HTTP(S) server
+#[derive(Deserialize)]
+struct RemoteBundle {
+    url: String,
+    /// Extra request headers such as `Authorization: Bearer <token>`
+    #[serde(default)]
+    headers: Vec<String>,
+    /// Install even if the version policy forbids it
+    #[serde(default)]
+    force: bool,
+}
+
+impl RemoteBundle {
+    /// Validate the request and combine its headers with the configured TLS settings
+    fn streaming_options(&self, tls: &StreamingOptions) -> Result<StreamingOptions, RaucError> {
+        if !is_remote_bundle(&self.url) {
+            return Err(RaucError::BadRequest(
+                "Bundle URL must start with http:// or https://".to_string(),
+            ));
+        }
+
+        if let Some(header) = self
+            .headers
+            .iter()
+            .find(|header| !header.contains(':') || header.contains(['\r', '\n']))
+        {
+            return Err(RaucError::BadRequest(format!(
+                "Invalid HTTP header '{}', expected 'Name: value'",
+                header
+            )));
+        }
+
+        Ok(StreamingOptions {
+            http_headers: self.headers.clone(),
+            ..tls.clone()
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct Credentials {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+struct MarkRequest {
+    mark: SlotMark,
+}
+
+#[derive(Deserialize)]
+struct RollbackRequest {
+    /// Also mark the currently booted slot bad
+    #[serde(default)]
+    mark_bad: bool,
+}
+
+#[derive(Serialize)]
+struct SlotMarked {
+    slot: String,
+    mark: SlotMark,
+    /// Message reported by RAUC
+    message: String,
+}
+
 struct AppConfig {
-    upload_dir: PathBuf,
-    bundle_path: PathBuf,
+    /// TLS settings applied to all bundles installed from a URL
+    streaming_tls: StreamingOptions,
+    version: VersionConfig,
     web_ui_title: String,
     web_ui_primary_color: String,
     web_ui_background_color: String,
@@ -77,159 +188,932 @@ fn static_files(file: PathBuf) -> Option<(ContentType, Vec<u8>)> {
     Some((content_type, asset.data.to_vec()))
 }
 
-#[get("/api/status")]
-async fn api_status(client: &State<RaucClient>) -> Result<Json<RaucStatus>, (Status, String)> {
-    let status = client
-        .get_status()
+#[catch(401)]
+fn unauthorized(request: &rocket::Request) -> RaucError {
+    request
+        .local_cache(|| Denied(None))
+        .0
+        .clone()
+        .unwrap_or_else(|| RaucError::Unauthorized("Authentication required".to_string()))
+}
+
+#[catch(403)]
+fn forbidden(request: &rocket::Request) -> RaucError {
+    request
+        .local_cache(|| Denied(None))
+        .0
+        .clone()
+        .unwrap_or_else(|| RaucError::Forbidden("Permission denied".to_string()))
+}
+
+#[post("/api/auth/login", data = "<credentials>")]
+async fn api_login(
+    credentials: Json<Credentials>,
+    auth: &State<Auth>,
+    cookies: &CookieJar<'_>,
+    client_ip: Option<IpAddr>,
+) -> Result<Json<SessionInfo>, RaucError> {
+    let client = client_ip.map_or_else(|| "unknown".to_string(), |ip| ip.to_string());
+    match auth
+        .login(&credentials.username, &credentials.password, cookies)
         .await
-        .map_err(|e| (Status::InternalServerError, e))?;
-    Ok(Json(status))
+    {
+        Ok(session) => {
+            println!("{} logged in from {}", credentials.username, client);
+            Ok(Json(session))
+        }
+        Err(e) => {
+            eprintln!(
+                "Failed login as {} from {}: {}",
+                credentials.username, client, e
+            );
+            Err(e)
+        }
+    }
+}
+
+#[post("/api/auth/logout")]
+fn api_logout(auth: &State<Auth>, cookies: &CookieJar<'_>) {
+    auth.logout(cookies);
+}
+
+#[get("/api/auth/session")]
+fn api_session(user: User) -> Json<SessionInfo> {
+    Json(user.session.unwrap_or_default())
+}
+
+/// Certificate of the HTTPS listener, readable without logging in so it can be verified first
+#[get("/api/tls")]
+fn api_tls(
+    certificate: &State<Option<ServerCertificate>>,
+) -> Result<Json<ServerCertificate>, RaucError> {
+    certificate
+        .inner()
+        .clone()
+        .map(Json)
+        .ok_or_else(|| RaucError::NotFound("HTTPS is not enabled".to_string()))
+}
+
+#[get("/api/auth/permissions")]
+fn api_permissions(user: User) -> Json<Permissions> {
+    Json(user.permissions())
+}
+
+#[get("/api/users")]
+fn api_users(auth: &State<Auth>, _user: CanManageUsers) -> Json<Vec<UserInfo>> {
+    Json(auth.list_users())
+}
+
+#[post("/api/users", data = "<request>")]
+async fn api_user_create(
+    request: Json<NewUser>,
+    auth: &State<Auth>,
+    user: CanManageUsers,
+) -> Result<Json<UserInfo>, RaucError> {
+    let created = auth.create_user(request.into_inner(), &user.name).await?;
+    println!("User {} created by {}", created.username, user.name);
+    Ok(Json(created))
+}
+
+#[put("/api/users/<username>", data = "<request>")]
+async fn api_user_update(
+    username: &str,
+    request: Json<UserUpdate>,
+    auth: &State<Auth>,
+    user: CanManageUsers,
+) -> Result<Json<UserInfo>, RaucError> {
+    let updated = auth.update_user(username, request.into_inner()).await?;
+    println!("User {} changed by {}", updated.username, user.name);
+    Ok(Json(updated))
+}
+
+#[delete("/api/users/<username>")]
+fn api_user_delete(
+    username: &str,
+    auth: &State<Auth>,
+    user: CanManageUsers,
+) -> Result<Json<UserInfo>, RaucError> {
+    let deleted = auth.delete_user(username)?;
+    println!("User {} deleted by {}", deleted.username, user.name);
+    Ok(Json(deleted))
+}
+
+#[get("/api/tokens")]
+fn api_tokens(tokens: &State<TokenStore>, _user: CanManageTokens) -> Json<Vec<ApiToken>> {
+    Json(tokens.list())
+}
+
+#[post("/api/tokens", data = "<request>")]
+fn api_token_create(
+    request: Json<NewToken>,
+    tokens: &State<TokenStore>,
+    auth: &State<Auth>,
+    user: CanManageTokens,
+) -> Result<Json<CreatedToken>, RaucError> {
+    // Tokens would not be checked, and start working unnoticed once authentication is enabled
+    if !auth.identifies_requesters() {
+        return Err(RaucError::BadRequest(
+            "Authentication is not enabled, API tokens would not be checked".to_string(),
+        ));
+    }
+    let token = tokens.create(request.into_inner(), &user.name)?;
+    println!("API token {} created by {}", token.info.name, user.name);
+    Ok(Json(token))
+}
+
+#[delete("/api/tokens/<id>")]
+fn api_token_revoke(
+    id: &str,
+    tokens: &State<TokenStore>,
+    user: CanManageTokens,
+) -> Result<Json<ApiToken>, RaucError> {
+    let token = tokens.revoke(id)?;
+    println!("API token {} revoked by {}", token.name, user.name);
+    Ok(Json(token))
+}
+
+/// Slot status along with the installation waiting for a reboot, if any
+#[derive(Serialize)]
+struct StatusResponse {
+    #[serde(flatten)]
+    status: RaucStatus,
+    reboot: RebootState,
+}
+
+#[get("/api/status")]
+async fn api_status(
+    client: &State<RaucClient>,
+    tracker: &State<RebootTracker>,
+    history: &State<InstallHistory>,
+    _user: CanRead,
+) -> Result<Json<StatusResponse>, RaucError> {
+    let status = client.get_status().await?;
+    let boot_id = client.boot_id().await.ok();
+    let reboot = tracker.update(&status, boot_id, history);
+    Ok(Json(StatusResponse { status, reboot }))
 }
 
 #[post("/api/upload", data = "<upload>")]
 async fn api_upload(
     mut upload: Form<Upload<'_>>,
-    config: &State<AppConfig>,
+    store: &State<BundleStore>,
     client: &State<RaucClient>,
-) -> Result<String, (Status, String)> {
-    // Ensure the upload directory exists
-    fs::create_dir_all(&config.upload_dir).await.map_err(|e| {
-        (
-            Status::InternalServerError,
-            format!("Failed to create upload directory: {}", e),
-        )
-    })?;
+    lock: &State<OperationLock>,
+    requester: CanUpload,
+) -> Result<Json<StoredBundle>, RaucError> {
+    let file_name = upload
+        .file
+        .raw_name()
+        .map(|name| name.dangerous_unsafe_unsanitized_raw().as_str().to_string());
+    let _guard = lock.acquire(OperationKind::Upload, &requester.name, file_name.as_deref())?;
 
     // Save the uploaded file
+    let (id, path) = store.allocate().await?;
     upload
         .file
-        .persist_to(&config.bundle_path)
+        .persist_to(&path)
         .await
-        .map_err(|e| {
-            (
-                Status::InternalServerError,
-                format!("Failed to save uploaded file: {}", e),
-            )
-        })?;
+        .map_err(|e| RaucError::Internal(format!("Failed to save uploaded file: {}", e)))?;
 
-    // Verify file was written correctly
-    let metadata = fs::metadata(&config.bundle_path).await.map_err(|e| {
-        (
-            Status::InternalServerError,
-            format!("Failed to verify uploaded file: {}", e),
-        )
-    })?;
+    let sha256 = sha256_file(&path).await?;
+    let filename = file_name.unwrap_or_else(|| format!("{}.raucb", id));
+    let bundle = store_bundle(id, filename, sha256, &requester.name, store, client).await?;
+    Ok(Json(bundle))
+}
+
+/// Path of a stored bundle as passed to rauc
+fn path_str(path: &Path) -> Result<&str, RaucError> {
+    path.to_str()
+        .ok_or_else(|| RaucError::Internal("Invalid bundle path".to_string()))
+}
 
-    let file_size = metadata.len();
+/// Add a bundle file saved at `store.path(id)` to the repository
+async fn store_bundle(
+    id: String,
+    filename: String,
+    sha256: String,
+    uploaded_by: &str,
+    store: &BundleStore,
+    client: &RaucClient,
+) -> Result<StoredBundle, RaucError> {
+    let path = store.path(&id);
 
-    let bundle_path_str = config.bundle_path.to_str().ok_or_else(|| {
-        (
-            Status::InternalServerError,
-            "Invalid bundle path".to_string(),
-        )
-    })?;
+    // Keep a single copy of bundles uploaded twice
+    if let Some(existing) = store.find_by_sha256(&sha256) {
+        let _ = fs::remove_file(&path).await;
+        return Ok(existing);
+    }
 
-    // Copy file to target in development mode
-    let copy_result = client
-        .copy_file_to_target(bundle_path_str, bundle_path_str)
+    // Verify file was written correctly
+    let size = fs::metadata(&path)
         .await
-        .map_err(|e| (Status::InternalServerError, e))?;
+        .map_err(|e| RaucError::Internal(format!("Failed to verify uploaded file: {}", e)))?
+        .len();
 
-    Ok(format!(
-        "Bundle uploaded successfully to {} ({} bytes). {}",
-        config.bundle_path.display(),
-        file_size,
-        copy_result
-    ))
+    let path = path_str(&path)?;
+
+    // Copy file to target in development mode
+    client.copy_file_to_target(path, path).await?;
+
+    // Delete bundles that cannot be installed before anyone gets the chance to
+    let (info, validation) = match validate_bundle(client, path).await {
+        Ok(result) => result,
+        Err(e) => {
+            let _ = client.remove_file_from_target(path).await;
+            let _ = fs::remove_file(path).await;
+            return Err(e);
+        }
+    };
+
+    store.insert(StoredBundle {
+        id,
+        sha256,
+        filename,
+        size,
+        uploaded_at: unix_now(),
+        uploaded_by: uploaded_by.to_string(),
+        info,
+        validation,
+    })
+}
+
+#[post("/api/uploads", data = "<request>")]
+async fn api_upload_create(
+    request: Json<NewUpload>,
+    uploads: &State<UploadManager>,
+    _user: CanUpload,
+) -> Result<Json<UploadSession>, RaucError> {
+    let session = uploads.create(request.into_inner()).await?;
+    Ok(Json(session))
+}
+
+#[get("/api/uploads/<id>")]
+fn api_upload_session(
+    id: &str,
+    uploads: &State<UploadManager>,
+    _user: CanUpload,
+) -> Result<Json<UploadSession>, RaucError> {
+    Ok(Json(uploads.get(id)?))
+}
+
+#[put("/api/uploads/<id>?<offset>", data = "<chunk>")]
+async fn api_upload_chunk(
+    id: &str,
+    offset: u64,
+    chunk: Data<'_>,
+    uploads: &State<UploadManager>,
+    _user: CanUpload,
+) -> Result<Json<UploadSession>, RaucError> {
+    let session = uploads.write_chunk(id, offset, chunk).await?;
+    Ok(Json(session))
+}
+
+#[post("/api/uploads/<id>/finish")]
+async fn api_upload_finish(
+    id: &str,
+    uploads: &State<UploadManager>,
+    store: &State<BundleStore>,
+    client: &State<RaucClient>,
+    lock: &State<OperationLock>,
+    requester: CanUpload,
+) -> Result<Json<StoredBundle>, RaucError> {
+    let session = uploads.get(id)?;
+    let _guard = lock.acquire(
+        OperationKind::Upload,
+        &requester.name,
+        Some(&session.filename),
+    )?;
+
+    let (bundle_id, path) = store.allocate().await?;
+    uploads.finish(id, &path).await?;
+
+    let bundle = store_bundle(
+        bundle_id,
+        session.filename,
+        session.sha256,
+        &requester.name,
+        store,
+        client,
+    )
+    .await?;
+    Ok(Json(bundle))
+}
+
+#[delete("/api/uploads/<id>")]
+async fn api_upload_cancel(
+    id: &str,
+    uploads: &State<UploadManager>,
+    _user: CanUpload,
+) -> Result<(), RaucError> {
+    uploads.cancel(id).await
+}
+
+#[get("/api/bundles")]
+fn api_bundles(store: &State<BundleStore>, _user: CanRead) -> Json<Vec<StoredBundle>> {
+    Json(store.list())
+}
+
+#[get("/api/bundles/<id>")]
+fn api_bundle(
+    id: &str,
+    store: &State<BundleStore>,
+    _user: CanRead,
+) -> Result<Json<StoredBundle>, RaucError> {
+    Ok(Json(store.get(id)?))
+}
+
+#[delete("/api/bundles/<id>")]
+async fn api_bundle_delete(
+    id: &str,
+    store: &State<BundleStore>,
+    client: &State<RaucClient>,
+    lock: &State<OperationLock>,
+    requester: CanUpload,
+) -> Result<Json<StoredBundle>, RaucError> {
+    let bundle = store.get(id)?;
+    let _guard = lock.acquire(
+        OperationKind::DeleteBundle,
+        &requester.name,
+        Some(&bundle.filename),
+    )?;
+
+    client
+        .remove_file_from_target(path_str(&store.path(id))?)
+        .await?;
+    let bundle = store.remove(id).await?;
+    Ok(Json(bundle))
+}
+
+#[post("/api/bundles/<id>/install?<force>")]
+#[allow(clippy::too_many_arguments)]
+async fn api_bundle_install(
+    id: &str,
+    force: bool,
+    store: &State<BundleStore>,
+    client: &State<RaucClient>,
+    jobs: &State<JobManager>,
+    lock: &State<OperationLock>,
+    config: &State<AppConfig>,
+    requester: CanInstall,
+    client_ip: Option<IpAddr>,
+) -> Result<Json<JobInfo>, RaucError> {
+    let bundle = store.get(id)?;
+    let warnings = check_version(client, config, &bundle.info, force, &requester.name).await?;
+    install_stored_bundle(
+        &bundle,
+        store,
+        client,
+        jobs,
+        lock,
+        &requester.name,
+        client_ip,
+        warnings,
+    )
+    .map(Json)
+}
+
+/// Apply the version policy to installing a bundle over the booted slot
+async fn check_version(
+    client: &RaucClient,
+    config: &AppConfig,
+    info: &RaucBundleInfo,
+    force: bool,
+    requester: &str,
+) -> Result<Vec<String>, RaucError> {
+    if config.version.policy == VersionPolicy::Allow {
+        return Ok(Vec::new());
+    }
+
+    let status = client.get_status().await?;
+    config
+        .version
+        .check(status.booted_version(), &info.version, force, requester)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn install_stored_bundle(
+    bundle: &StoredBundle,
+    store: &BundleStore,
+    client: &RaucClient,
+    jobs: &JobManager,
+    lock: &OperationLock,
+    requester: &str,
+    client_ip: Option<IpAddr>,
+    warnings: Vec<String>,
+) -> Result<JobInfo, RaucError> {
+    let path = store.path(&bundle.id);
+    let guard = lock.acquire(OperationKind::Install, requester, Some(&bundle.filename))?;
+    let request = InstallRequest {
+        bundle: path_str(&path)?.to_string(),
+        info: Some(bundle.info.clone()),
+        streaming: StreamingOptions::default(),
+        warnings,
+        started_by: requester.to_string(),
+        client_ip,
+    };
+    Ok(jobs.start(client, request, guard).info())
 }
 
 #[get("/api/bundle-info")]
-async fn api_bundle_info(
+fn api_bundle_info(
+    store: &State<BundleStore>,
+    _user: CanRead,
+) -> Result<Json<RaucBundleInfo>, RaucError> {
+    Ok(Json(store.latest()?.info))
+}
+
+#[post("/api/bundle-info-url", data = "<bundle>")]
+async fn api_bundle_info_url(
+    bundle: Json<RemoteBundle>,
     client: &State<RaucClient>,
     config: &State<AppConfig>,
-) -> Result<Json<RaucBundleInfo>, (Status, String)> {
-    let bundle_path_str = config.bundle_path.to_str().ok_or_else(|| {
-        (
-            Status::InternalServerError,
-            "Invalid bundle path".to_string(),
-        )
-    })?;
+    _user: CanInstall,
+) -> Result<Json<RaucBundleInfo>, RaucError> {
+    let streaming = bundle.streaming_options(&config.streaming_tls)?;
 
-    let info = client
-        .get_bundle_info(bundle_path_str)
-        .await
-        .map_err(|e| (Status::InternalServerError, e))?;
+    let info = client.get_bundle_info(&bundle.url, &streaming).await?;
     Ok(Json(info))
 }
 
-#[get("/api/install")]
-fn api_install(client: &State<RaucClient>, config: &State<AppConfig>) -> TextStream![String] {
-    let bundle_path = config
-        .bundle_path
-        .to_str()
-        .unwrap_or("/tmp/rauc-bundles/upload_bundle.raucb")
-        .to_string();
-    let client = client.inner().clone();
-
-    TextStream! {
-        match client.install_bundle(&bundle_path).await {
-            Ok(stream) => {
-                for await result in stream {
-                    match result {
-                        Ok(line) => yield line,
-                        Err(e) => yield format!("[ERROR] {}\n", e),
-                    }
-                }
-            }
-            Err(e) => {
-                yield format!("[ERROR] Failed to start installation: {}\n", e);
-            }
+#[post("/api/install?<force>")]
+#[allow(clippy::too_many_arguments)]
+async fn api_install(
+    force: bool,
+    store: &State<BundleStore>,
+    client: &State<RaucClient>,
+    jobs: &State<JobManager>,
+    lock: &State<OperationLock>,
+    config: &State<AppConfig>,
+    requester: CanInstall,
+    client_ip: Option<IpAddr>,
+) -> Result<Json<JobInfo>, RaucError> {
+    let bundle = store.latest()?;
+    let warnings = check_version(client, config, &bundle.info, force, &requester.name).await?;
+    install_stored_bundle(
+        &bundle,
+        store,
+        client,
+        jobs,
+        lock,
+        &requester.name,
+        client_ip,
+        warnings,
+    )
+    .map(Json)
+}
+
+#[post("/api/install-url", data = "<bundle>")]
+async fn api_install_url(
+    bundle: Json<RemoteBundle>,
+    client: &State<RaucClient>,
+    jobs: &State<JobManager>,
+    config: &State<AppConfig>,
+    lock: &State<OperationLock>,
+    requester: CanInstall,
+    client_ip: Option<IpAddr>,
+) -> Result<Json<JobInfo>, RaucError> {
+    let streaming = bundle.streaming_options(&config.streaming_tls)?;
+    let info = client.get_bundle_info(&bundle.url, &streaming).await?;
+    let warnings = check_version(client, config, &info, bundle.force, &requester.name).await?;
+
+    let guard = lock.acquire(OperationKind::Install, &requester.name, Some(&bundle.url))?;
+    let request = InstallRequest {
+        bundle: bundle.url.clone(),
+        info: Some(info),
+        streaming,
+        warnings,
+        started_by: requester.name.clone(),
+        client_ip,
+    };
+    Ok(Json(jobs.start(client, request, guard).info()))
+}
+
+#[get("/api/jobs")]
+fn api_jobs(jobs: &State<JobManager>, _user: CanRead) -> Json<Vec<JobInfo>> {
+    Json(jobs.list())
+}
+
+#[get("/api/history")]
+fn api_history(history: &State<InstallHistory>, _user: CanRead) -> Json<Vec<JobInfo>> {
+    Json(history.list())
+}
+
+#[get("/api/history/<id>/log")]
+fn api_history_log(
+    id: &str,
+    history: &State<InstallHistory>,
+    _user: CanRead,
+) -> Result<String, RaucError> {
+    history.log(id)
+}
+
+#[get("/api/jobs/<id>")]
+fn api_job(id: &str, jobs: &State<JobManager>, _user: CanRead) -> Result<Json<JobInfo>, RaucError> {
+    let job = jobs
+        .get(id)
+        .ok_or_else(|| RaucError::NotFound(format!("Job {} not found", id)))?;
+    Ok(Json(job.info()))
+}
+
+#[get("/api/jobs/<id>/events")]
+fn api_job_events(
+    id: &str,
+    jobs: &State<JobManager>,
+    last_event_id: LastEventId,
+    _user: CanRead,
+) -> Result<EventStream![], RaucError> {
+    let job = jobs
+        .get(id)
+        .ok_or_else(|| RaucError::NotFound(format!("Job {} not found", id)))?;
+    // Resume after the last event a reconnecting client has seen
+    let events = job.watch(last_event_id.next_event());
+
+    Ok(EventStream! {
+        for await (index, event) in events {
+            yield Event::json(&event).event(event.name()).id(index.to_string());
         }
+    })
+}
+
+#[post("/api/slots/<name>/mark", data = "<request>")]
+async fn api_slot_mark(
+    name: &str,
+    request: Json<MarkRequest>,
+    client: &State<RaucClient>,
+    lock: &State<OperationLock>,
+    requester: CanManageSlots,
+) -> Result<Json<SlotMarked>, RaucError> {
+    // Only pass names RAUC knows on to the backend
+    let status = client.get_status().await?;
+    let known = status.slots().any(|(slot, _)| slot == name);
+    if !known && name != "booted" && name != "other" {
+        return Err(RaucError::NotFound(format!("Slot {} not found", name)));
+    }
+
+    let _guard = lock.acquire(OperationKind::MarkSlot, &requester.name, None)?;
+    let message = client.mark_slot(name, request.mark).await?;
+    println!(
+        "Slot {} marked {} by {}: {}",
+        name,
+        request.mark.as_str(),
+        requester.name,
+        message
+    );
+
+    Ok(Json(SlotMarked {
+        slot: name.to_string(),
+        mark: request.mark,
+        message,
+    }))
+}
+
+#[get("/api/rollback")]
+async fn api_rollback_plan(
+    client: &State<RaucClient>,
+    _user: CanRead,
+) -> Result<Json<RollbackPlan>, RaucError> {
+    let status = client.get_status().await?;
+    rollback::plan(&status).map(Json)
+}
+
+#[post("/api/rollback", data = "<request>")]
+async fn api_rollback(
+    request: Json<RollbackRequest>,
+    client: &State<RaucClient>,
+    lock: &State<OperationLock>,
+    tracker: &State<RebootTracker>,
+    history: &State<InstallHistory>,
+    requester: CanManageSlots,
+    _reboot: CanReboot,
+) -> Result<Json<RollbackResult>, RaucError> {
+    let _guard = lock.acquire(OperationKind::Rollback, &requester.name, None)?;
+    // Remember the current boot ID to verify the next boot, as for a plain reboot
+    if let Err(e) = tracker.refresh(client, history).await {
+        eprintln!("Failed to update pending reboot: {}", e);
     }
+    let result = rollback::rollback(client, request.mark_bad).await?;
+    println!(
+        "Rollback to {} started by {}",
+        result.plan.target_slot, requester.name
+    );
+    Ok(Json(result))
+}
+
+#[get("/api/health")]
+fn api_health(monitor: &State<HealthMonitor>, _user: CanRead) -> Json<HealthReport> {
+    Json(monitor.report())
 }
 
 #[post("/api/reboot")]
-async fn api_reboot(client: &State<RaucClient>) -> Result<String, (Status, String)> {
-    client
-        .reboot()
-        .await
-        .map_err(|e| (Status::InternalServerError, e))
+async fn api_reboot(
+    client: &State<RaucClient>,
+    lock: &State<OperationLock>,
+    tracker: &State<RebootTracker>,
+    history: &State<InstallHistory>,
+    requester: CanReboot,
+) -> Result<String, RaucError> {
+    let _guard = lock.acquire(OperationKind::Reboot, &requester.name, None)?;
+    // Remember the expected slot and the current boot ID to verify the next boot
+    if let Err(e) = tracker.refresh(client, history).await {
+        eprintln!("Failed to update pending reboot: {}", e);
+    }
+    client.reboot().await
+}
+
+const USERS_FILE: &str = "users.json";
+const TOKENS_FILE: &str = "api-tokens.json";
+
+/// Move the users and API tokens that earlier versions stored in the history directory
+fn migrate_credentials(history_dir: &Path, data_dir: &Path) {
+    for name in [USERS_FILE, TOKENS_FILE] {
+        let old = history_dir.join(name);
+        let new = data_dir.join(name);
+        if !old.exists() || new.exists() {
+            continue;
+        }
+
+        // Copy if the directories are on different file systems
+        let result = std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(data_dir)
+            .and_then(|_| std::fs::set_permissions(&old, std::fs::Permissions::from_mode(0o600)))
+            .and_then(|_| {
+                std::fs::rename(&old, &new)
+                    .or_else(|_| std::fs::copy(&old, &new).and_then(|_| std::fs::remove_file(&old)))
+            });
+        match result {
+            Ok(()) => println!("Moved {} to {}", old.display(), new.display()),
+            Err(e) => eprintln!(
+                "Failed to move {} to {}: {}",
+                old.display(),
+                new.display(),
+                e
+            ),
+        }
+    }
+}
+
+fn all_routes() -> Vec<rocket::Route> {
+    routes![
+        index,
+        static_files,
+        api_login,
+        api_logout,
+        api_session,
+        api_permissions,
+        api_tls,
+        api_users,
+        api_user_create,
+        api_user_update,
+        api_user_delete,
+        api_tokens,
+        api_token_create,
+        api_token_revoke,
+        api_status,
+        api_upload,
+        api_upload_create,
+        api_upload_session,
+        api_upload_chunk,
+        api_upload_finish,
+        api_upload_cancel,
+        api_bundles,
+        api_bundle,
+        api_bundle_delete,
+        api_bundle_install,
+        api_bundle_info,
+        api_bundle_info_url,
+        api_install,
+        api_install_url,
+        api_jobs,
+        api_history,
+        api_history_log,
+        api_job,
+        api_job_events,
+        api_slot_mark,
+        api_rollback_plan,
+        api_rollback,
+        api_health,
+        api_reboot
+    ]
 }
 
 #[launch]
 fn rocket() -> _ {
+    // `rauc-web-ui hash-password` reads a password from stdin and prints its hash
+    if env::args().nth(1).as_deref() == Some("hash-password") {
+        let mut password = String::new();
+        if let Err(e) = std::io::stdin().read_line(&mut password) {
+            eprintln!("Failed to read password: {}", e);
+            std::process::exit(1);
+        }
+        match auth::hash_password(password.trim_end_matches(['\r', '\n'])) {
+            Ok(hash) => println!("{}", hash),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
+
     // Load .env file if it exists
     let _ = dotenvy::dotenv();
 
-    // Determine mode based on environment variables
-    let mode = match (env::var("SSH_HOST"), env::var("SSH_PASSWORD")) {
-        (Ok(ssh_host), Ok(ssh_password)) => {
+    // Determine backend based on environment variables
+    let ssh_host = env::var("SSH_HOST").ok();
+    let backend = env::var("RAUC_BACKEND").unwrap_or_else(|_| {
+        if ssh_host.is_some() {
+            "ssh".to_string()
+        } else {
+            "local".to_string()
+        }
+    });
+
+    // Development backends only listen on localhost
+    let (rauc_client, listen_publicly) = match (backend.as_str(), ssh_host) {
+        #[cfg(feature = "ssh-backend")]
+        ("ssh", Some(destination)) => {
             println!("Running in DEVELOPMENT mode with SSH");
-            RaucMode::Development {
-                ssh_host,
-                ssh_password,
-            }
+            let known_hosts = env::var("SSH_KNOWN_HOSTS")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| {
+                    PathBuf::from(env::var("HOME").unwrap_or_else(|_| "/root".to_string()))
+                        .join(".ssh/known_hosts")
+                });
+            let config = SshConfig {
+                destination,
+                key_file: env::var("SSH_KEY").ok().map(PathBuf::from),
+                key_passphrase: env::var("SSH_KEY_PASSPHRASE").ok(),
+                agent: env::var_os("SSH_AUTH_SOCK").is_some(),
+                password: env::var("SSH_PASSWORD").ok(),
+                known_hosts,
+                accept_new_host_keys: env::var("SSH_ACCEPT_NEW_HOST_KEYS")
+                    .is_ok_and(|value| value == "true"),
+            };
+            let backend = SshBackend::new(config).unwrap_or_else(|e| panic!("{}", e));
+            (RaucClient::new(backend), false)
+        }
+        #[cfg(not(feature = "ssh-backend"))]
+        ("ssh", Some(_)) => panic!(
+            "The ssh backend is not part of this build, build with `--features ssh-backend` to use SSH_HOST"
+        ),
+        ("ssh", None) => panic!("RAUC_BACKEND=ssh requires SSH_HOST"),
+        ("mock", _) => {
+            println!("Running in DEVELOPMENT mode with simulated RAUC backend");
+            (RaucClient::new(MockBackend::new()), false)
         }
-        _ => {
+        ("dbus", _) => {
+            let bus = match env::var("RAUC_DBUS_BUS").as_deref() {
+                Ok("session") => DBusBus::Session,
+                _ => DBusBus::System,
+            };
+            println!(
+                "Running in PRODUCTION mode with RAUC D-Bus API ({:?} bus)",
+                bus
+            );
+            (RaucClient::new(DBusBackend::new(bus)), true)
+        }
+        ("local", _) => {
             if cfg!(debug_assertions) {
                 println!("Running in DEBUG mode with direct rauc binary (no SSH credentials)");
             } else {
                 println!("Running in PRODUCTION mode with direct rauc binary");
             }
-            RaucMode::Production
+            (RaucClient::new(LocalBackend::new()), true)
         }
+        (other, _) => panic!(
+            "Unknown RAUC_BACKEND '{}' (expected ssh, local, dbus or mock)",
+            other
+        ),
     };
 
-    let rauc_client = RaucClient::new(mode.clone());
-
     // Get upload directory from env or use default
-    let upload_dir = env::var("UPLOAD_TMP_DIR")
+    let upload_dir: PathBuf = env::var("UPLOAD_TMP_DIR")
         .unwrap_or_else(|_| "/tmp/rauc-bundles".to_string())
         .into();
+    let bundle_store = BundleStore::open(upload_dir.join("bundles"));
+
+    // Largest bundle accepted by uploads in one piece and in chunks
+    let upload_max_size = env::var("UPLOAD_MAX_SIZE_MIB")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(512)
+        .mebibytes();
+
+    // Install history and pending reboot, which need to be on persistent storage to
+    // survive reboots
+    let history_dir: PathBuf = env::var("HISTORY_DIR")
+        .unwrap_or_else(|_| "/var/lib/rauc-web-ui/history".to_string())
+        .into();
+
+    // Users and API tokens, kept apart from the history as only the server may read them
+    let data_dir: PathBuf = env::var("DATA_DIR")
+        .unwrap_or_else(|_| "/var/lib/rauc-web-ui/data".to_string())
+        .into();
+    migrate_credentials(&history_dir, &data_dir);
+
+    // HTTPS, on by default when listening on the network, with a self-signed certificate
+    // generated on first start unless one is configured
+    let tls_cert = env::var("TLS_CERT").ok();
+    let tls_key = env::var("TLS_KEY").ok();
+    let tls_dir: PathBuf = env::var("TLS_DIR")
+        .unwrap_or_else(|_| "/var/lib/rauc-web-ui/tls".to_string())
+        .into();
+    let tls_enabled = match env::var("TLS").as_deref() {
+        Ok("true") => true,
+        Ok("false") => false,
+        _ => listen_publicly || tls_cert.is_some(),
+    };
+    let certificate = tls_enabled.then(|| {
+        let certificate = ServerCertificate::load_or_generate(tls_cert, tls_key, &tls_dir)
+            .unwrap_or_else(|e| panic!("{}", e));
+        println!(
+            "TLS certificate SHA-256 fingerprint: {}",
+            certificate.sha256_fingerprint
+        );
+        certificate
+    });
+
+    // Client certificates issued by this CA identify users, see `ClientCertConfig`
+    let client_ca = env::var("TLS_CLIENT_CA").ok();
+    if client_ca.is_some() && !tls_enabled {
+        panic!("TLS_CLIENT_CA requires HTTPS, see TLS");
+    }
+    let client_certs = client_ca.as_ref().map(|_| ClientCertConfig {
+        identity: env::var("TLS_CLIENT_IDENTITY")
+            .unwrap_or_else(|_| "cn".to_string())
+            .parse()
+            .unwrap_or_else(|e| panic!("{}", e)),
+        default_role: env::var("TLS_CLIENT_DEFAULT_ROLE")
+            .ok()
+            .map(|role| role.parse().unwrap_or_else(|e| panic!("{}", e))),
+    });
+    let client_cert_required =
+        env::var("TLS_CLIENT_CERT_REQUIRED").is_ok_and(|value| value == "true");
+
+    // Login for the web UI and API, without it anyone who can reach the server can use it
+    let auth_config = env::var("AUTH_PASSWORD_HASH")
+        .ok()
+        .map(|password_hash| AuthConfig {
+            username: env::var("AUTH_USERNAME").unwrap_or_else(|_| "admin".to_string()),
+            password_hash,
+            session_timeout: env::var("SESSION_TIMEOUT_MINUTES")
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(30)
+                * 60,
+            secure_cookie: env::var("SESSION_COOKIE_SECURE")
+                .map(|value| value == "true")
+                .unwrap_or(tls_enabled),
+        });
+    if auth_config.is_none() && client_certs.is_none() && listen_publicly {
+        eprintln!(
+            "WARNING: AUTH_PASSWORD_HASH is not set, anyone on the network can install firmware"
+        );
+    }
+
+    let retention = Retention {
+        max_entries: env::var("HISTORY_MAX_ENTRIES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(100),
+        max_age_days: env::var("HISTORY_MAX_AGE_DAYS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(365),
+    };
+    let reboot_tracker = RebootTracker::open(history_dir.join("reboot-state.json"));
+    let token_store = TokenStore::open(data_dir.join(TOKENS_FILE));
+    let users = UserStore::open(data_dir.join(USERS_FILE));
+    let auth = Auth::new(auth_config, users, client_certs).unwrap_or_else(|e| panic!("{}", e));
+    let history = InstallHistory::open(history_dir, retention);
+
+    // TLS settings for bundles streamed from a URL, paths refer to the target system
+    let streaming_tls = StreamingOptions {
+        http_headers: Vec::new(),
+        tls_ca: env::var("STREAMING_TLS_CA").ok(),
+        tls_cert: env::var("STREAMING_TLS_CERT").ok(),
+        tls_key: env::var("STREAMING_TLS_KEY").ok(),
+        tls_no_verify: env::var("STREAMING_TLS_NO_VERIFY").is_ok_and(|value| value == "true"),
+    };
+
+    // Policy for installing bundles that are not newer than the booted slot
+    let version = VersionConfig {
+        policy: env::var("VERSION_POLICY")
+            .unwrap_or_else(|_| "warn".to_string())
+            .parse()
+            .unwrap_or_else(|e| panic!("{}", e)),
+        fallback: env::var("VERSION_FALLBACK")
+            .unwrap_or_else(|_| "numeric".to_string())
+            .parse()
+            .unwrap_or_else(|e| panic!("{}", e)),
+    };
 
-    let bundle_path = env::var("UPLOAD_TMP_DIR")
-        .map(|dir| PathBuf::from(dir).join("upload_bundle.raucb"))
-        .unwrap_or_else(|_| PathBuf::from("/tmp/rauc-bundles/upload_bundle.raucb"));
+    // Checks run once after startup to mark the booted slot good or bad
+    let health_config = env::var("HEALTH_CHECK_CONFIG")
+        .ok()
+        .map(|path| HealthConfig::load(Path::new(&path)).unwrap_or_else(|e| panic!("{}", e)));
 
     // Get theming configuration
     let web_ui_title = env::var("WEB_UI_TITLE").unwrap_or_else(|_| "Firmware Updater".to_string());
@@ -241,8 +1125,8 @@ fn rocket() -> _ {
         env::var("WEB_UI_FOREGROUND_COLOR").unwrap_or_else(|_| "rgb(17, 24, 39)".to_string()); // gray-900
 
     let app_config = AppConfig {
-        upload_dir,
-        bundle_path,
+        streaming_tls,
+        version,
         web_ui_title,
         web_ui_primary_color,
         web_ui_background_color,
@@ -256,33 +1140,521 @@ fn rocket() -> _ {
         .and_then(|p| p.parse::<u16>().ok())
         .unwrap_or(8000);
 
-    let address = (match mode {
-        RaucMode::Production { .. } => "0.0.0.0",
-        _ => "127.0.0.1",
+    let address = (if listen_publicly {
+        "0.0.0.0"
+    } else {
+        "127.0.0.1"
     })
     .parse()
     .unwrap();
+
+    // Optional plain HTTP listener redirecting to HTTPS
+    let redirect_port = env::var("TLS_REDIRECT_PORT")
+        .ok()
+        .and_then(|p| p.parse::<u16>().ok())
+        .filter(|_| tls_enabled);
+    let tls = certificate.as_ref().map(|certificate| {
+        let tls = TlsConfig::from_paths(&certificate.cert_path, &certificate.key_path);
+        match &client_ca {
+            Some(ca) => tls.with_mutual(MutualTls::from_path(ca).mandatory(client_cert_required)),
+            None => tls,
+        }
+    });
+
     rocket::build()
+        .manage(auth)
+        .manage(token_store)
         .manage(rauc_client)
+        .manage(JobManager::new(history.clone(), reboot_tracker.clone()))
+        .manage(history)
+        .manage(reboot_tracker)
+        .manage(OperationLock::new())
+        .manage(UploadManager::new(upload_dir, upload_max_size.as_u64()))
+        .manage(bundle_store)
         .manage(app_config)
+        .manage(HealthMonitor::new(health_config))
+        .manage(certificate)
+        .attach(AdHoc::on_liftoff("Boot verification", |rocket| {
+            Box::pin(async move {
+                let tracker = rocket.state::<RebootTracker>().unwrap();
+                let client = rocket.state::<RaucClient>().unwrap();
+                let history = rocket.state::<InstallHistory>().unwrap();
+                if let Err(e) = tracker.refresh(client, history).await {
+                    eprintln!("Failed to verify booted slot: {}", e);
+                }
+            })
+        }))
+        .attach(AdHoc::on_liftoff("Health checks", |rocket| {
+            Box::pin(async move {
+                let monitor = rocket.state::<HealthMonitor>().unwrap().clone();
+                let client = rocket.state::<RaucClient>().unwrap().clone();
+                let lock = rocket.state::<OperationLock>().unwrap().clone();
+                tokio::spawn(monitor.run(client, lock));
+            })
+        }))
+        .attach(AdHoc::on_liftoff("HTTPS redirect", move |_| {
+            Box::pin(async move {
+                if let Some(redirect_port) = redirect_port {
+                    let server = tls::redirect_server(address, redirect_port, port);
+                    tokio::spawn(async move {
+                        if let Err(e) = server.launch().await {
+                            eprintln!("Failed to run HTTPS redirect: {}", e);
+                        }
+                    });
+                }
+            })
+        }))
         .configure(rocket::Config {
             limits: Limits::default()
-                .limit("file", 512.mebibytes())
-                .limit("data-form", 512.mebibytes()),
+                .limit("file", upload_max_size)
+                .limit("data-form", upload_max_size),
             address,
             port,
+            tls,
             ..Default::default()
         })
-        .mount(
-            "/",
-            routes![
-                index,
-                static_files,
-                api_status,
-                api_upload,
-                api_bundle_info,
-                api_install,
-                api_reboot
-            ],
+        .mount("/", all_routes())
+        .register("/api", catchers![unauthorized, forbidden])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use auth::SESSION_COOKIE;
+    use backend::{InstallStream, RaucBackend};
+    use rocket::http::Status;
+    use rocket::local::asynchronous::Client;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tempfile::TempDir;
+    use tls::ClientIdentity;
+    use tokens::Scope;
+    use users::Role;
+    use version::FallbackScheme;
+
+    /// Backend call as `(method, bundle, streaming options)`
+    type Call = (&'static str, String, StreamingOptions);
+
+    /// `MockBackend` remembering the bundles it was asked to inspect or install
+    #[derive(Clone, Default)]
+    struct RecordingBackend {
+        mock: MockBackend,
+        calls: Arc<Mutex<Vec<Call>>>,
+    }
+
+    impl RecordingBackend {
+        fn record(&self, method: &'static str, bundle: &str, streaming: &StreamingOptions) {
+            let call = (method, bundle.to_string(), streaming.clone());
+            self.calls.lock().unwrap().push(call);
+        }
+
+        fn calls(&self, method: &str) -> Vec<Call> {
+            let calls = self.calls.lock().unwrap();
+            calls
+                .iter()
+                .filter(|call| call.0 == method)
+                .cloned()
+                .collect()
+        }
+    }
+
+    #[async_trait]
+    impl RaucBackend for RecordingBackend {
+        async fn get_status(&self) -> Result<RaucStatus, RaucError> {
+            self.mock.get_status().await
+        }
+
+        async fn get_bundle_info(
+            &self,
+            bundle: &str,
+            streaming: &StreamingOptions,
+        ) -> Result<RaucBundleInfo, RaucError> {
+            self.record("info", bundle, streaming);
+            self.mock.get_bundle_info(bundle, streaming).await
+        }
+
+        async fn install_bundle(
+            &self,
+            bundle: &str,
+            streaming: &StreamingOptions,
+        ) -> Result<InstallStream, RaucError> {
+            self.record("install", bundle, streaming);
+            self.mock.install_bundle(bundle, streaming).await
+        }
+
+        async fn boot_id(&self) -> Result<String, RaucError> {
+            self.mock.boot_id().await
+        }
+
+        async fn mark_slot(&self, slot: &str, mark: SlotMark) -> Result<String, RaucError> {
+            self.mock.mark_slot(slot, mark).await
+        }
+
+        async fn reboot(&self) -> Result<String, RaucError> {
+            self.mock.reboot().await
+        }
+    }
+
+    /// Server with all routes and state kept in `dir`, like `rocket()` without the environment
+    async fn client(
+        dir: &TempDir,
+        backend: RecordingBackend,
+        auth: Option<AuthConfig>,
+        client_certs: Option<ClientCertConfig>,
+    ) -> Client {
+        let users = UserStore::open(dir.path().join(USERS_FILE));
+        let reboot_tracker = RebootTracker::open(dir.path().join("reboot-state.json"));
+        let retention = Retention {
+            max_entries: 100,
+            max_age_days: 365,
+        };
+        let history = InstallHistory::open(dir.path().join("history"), retention);
+        let app_config = AppConfig {
+            streaming_tls: StreamingOptions {
+                tls_ca: Some("/etc/rauc/streaming-ca.pem".to_string()),
+                ..Default::default()
+            },
+            version: VersionConfig {
+                policy: VersionPolicy::Warn,
+                fallback: FallbackScheme::Numeric,
+            },
+            web_ui_title: "Firmware Updater".to_string(),
+            web_ui_primary_color: String::new(),
+            web_ui_background_color: String::new(),
+            web_ui_foreground_color: String::new(),
+        };
+
+        let rocket = rocket::build()
+            .manage(Auth::new(auth, users, client_certs).unwrap())
+            .manage(TokenStore::open(dir.path().join(TOKENS_FILE)))
+            .manage(RaucClient::new(backend))
+            .manage(JobManager::new(history.clone(), reboot_tracker.clone()))
+            .manage(history)
+            .manage(reboot_tracker)
+            .manage(OperationLock::new())
+            .manage(UploadManager::new(dir.path().join("uploads"), 1 << 20))
+            .manage(BundleStore::open(dir.path().join("bundles")))
+            .manage(app_config)
+            .manage(HealthMonitor::new(None))
+            .manage(None::<ServerCertificate>)
+            .mount("/", all_routes())
+            .register("/api", catchers![unauthorized, forbidden]);
+        Client::tracked(rocket).await.unwrap()
+    }
+
+    fn remote_bundle(url: &str, headers: &[&str]) -> RemoteBundle {
+        RemoteBundle {
+            url: url.to_string(),
+            headers: headers.iter().map(|header| header.to_string()).collect(),
+            force: false,
+        }
+    }
+
+    #[test]
+    fn remote_bundle_combines_headers_with_configured_tls() {
+        let tls = StreamingOptions {
+            tls_ca: Some("/etc/rauc/streaming-ca.pem".to_string()),
+            tls_no_verify: true,
+            ..Default::default()
+        };
+        let bundle = remote_bundle(
+            "https://updates.example.com/update.raucb",
+            &["Authorization: Bearer secret", "X-Device:42"],
+        );
+
+        let options = bundle.streaming_options(&tls).unwrap();
+        assert_eq!(
+            options.http_headers,
+            ["Authorization: Bearer secret", "X-Device:42"]
+        );
+        assert_eq!(
+            options.tls_ca.as_deref(),
+            Some("/etc/rauc/streaming-ca.pem")
+        );
+        assert!(options.tls_no_verify);
+    }
+
+    #[test]
+    fn remote_bundle_rejects_invalid_requests() {
+        let tls = StreamingOptions::default();
+        let url = "https://updates.example.com/update.raucb";
+        let rejected = [
+            remote_bundle("/data/update.raucb", &[]),
+            remote_bundle("ftp://updates.example.com/update.raucb", &[]),
+            remote_bundle(url, &["Bearer secret"]),
+            remote_bundle(url, &["X-Device: 42\r\nX-Injected: 1"]),
+            remote_bundle(url, &["X-Device: 42\n"]),
+        ];
+
+        for bundle in rejected {
+            let error = bundle.streaming_options(&tls).unwrap_err();
+            assert!(matches!(error, RaucError::BadRequest(_)), "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn credentials_move_out_of_the_history_directory() {
+        let dir = TempDir::new().unwrap();
+        let history_dir = dir.path().join("history");
+        let data_dir = dir.path().join("data");
+        std::fs::create_dir(&history_dir).unwrap();
+        std::fs::write(history_dir.join(USERS_FILE), "[]").unwrap();
+        std::fs::write(history_dir.join(TOKENS_FILE), "[]").unwrap();
+        std::fs::write(history_dir.join("reboot-state.json"), "{}").unwrap();
+
+        migrate_credentials(&history_dir, &data_dir);
+        for name in [USERS_FILE, TOKENS_FILE] {
+            assert!(!history_dir.join(name).exists());
+            let metadata = std::fs::metadata(data_dir.join(name)).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        }
+        let metadata = std::fs::metadata(&data_dir).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o700);
+        assert!(history_dir.join("reboot-state.json").exists());
+
+        // Files already in the data directory are kept
+        std::fs::write(history_dir.join(USERS_FILE), "[{}]").unwrap();
+        migrate_credentials(&history_dir, &data_dir);
+        assert_eq!(
+            std::fs::read_to_string(data_dir.join(USERS_FILE)).unwrap(),
+            "[]"
+        );
+    }
+
+    #[tokio::test]
+    async fn tokens_cannot_be_created_without_authentication() {
+        let dir = TempDir::new().unwrap();
+        let client = client(&dir, RecordingBackend::default(), None, None).await;
+        let token = serde_json::json!({ "name": "ci", "scopes": ["read"] });
+
+        let response = client
+            .post("/api/tokens")
+            .header(ContentType::JSON)
+            .body(token.to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(client
+            .rocket()
+            .state::<TokenStore>()
+            .unwrap()
+            .list()
+            .is_empty());
+    }
+
+    fn auth_config() -> AuthConfig {
+        AuthConfig {
+            username: "admin".to_string(),
+            password_hash: auth::hash_password("administrator").unwrap(),
+            session_timeout: 60,
+            secure_cookie: false,
+        }
+    }
+
+    async fn login(client: &Client, username: &str, password: &str) -> Status {
+        let credentials = serde_json::json!({ "username": username, "password": password });
+        client
+            .post("/api/auth/login")
+            .header(ContentType::JSON)
+            .body(credentials.to_string())
+            .dispatch()
+            .await
+            .status()
+    }
+
+    /// Name the requester is identified by, or the status if the request is rejected
+    async fn requester(
+        request: rocket::local::asynchronous::LocalRequest<'_>,
+    ) -> Result<String, Status> {
+        let response = request.dispatch().await;
+        if response.status() != Status::Ok {
+            return Err(response.status());
+        }
+        let permissions: serde_json::Value = response.into_json().await.unwrap();
+        Ok(permissions["name"].as_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn api_requires_a_session_when_auth_is_enabled() {
+        let dir = TempDir::new().unwrap();
+        let client = client(&dir, RecordingBackend::default(), Some(auth_config()), None).await;
+
+        let response = client.get("/api/status").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let error: serde_json::Value = response.into_json().await.unwrap();
+        assert_eq!(error["code"], "unauthorized");
+
+        assert_eq!(
+            login(&client, "admin", "wrong password").await,
+            Status::Unauthorized
+        );
+        assert_eq!(
+            login(&client, "nobody", "administrator").await,
+            Status::Unauthorized
+        );
+        assert!(client.cookies().get(SESSION_COOKIE).is_none());
+
+        assert_eq!(login(&client, "admin", "administrator").await, Status::Ok);
+        assert!(client.cookies().get(SESSION_COOKIE).is_some());
+        let response = client.get("/api/status").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let session: serde_json::Value = client
+            .get("/api/auth/session")
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        assert_eq!(session["username"], "admin");
+
+        let response = client.post("/api/auth/logout").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client.get("/api/status").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[tokio::test]
+    async fn requester_is_identified_by_token_then_session_then_certificate() {
+        let dir = TempDir::new().unwrap();
+        let client_certs = ClientCertConfig {
+            identity: ClientIdentity::CommonName,
+            default_role: Some(Role::Viewer),
+        };
+        let client = client(
+            &dir,
+            RecordingBackend::default(),
+            Some(auth_config()),
+            Some(client_certs),
         )
+        .await;
+
+        let mut params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "device-1");
+        let key = rcgen::KeyPair::generate().unwrap();
+        let certificate = params.self_signed(&key).unwrap().pem();
+
+        let new_token = NewToken {
+            name: "ci".to_string(),
+            scopes: vec![Scope::Read],
+            expires_in_days: None,
+        };
+        let tokens = client.rocket().state::<TokenStore>().unwrap();
+        let token = tokens.create(new_token, "admin").unwrap().token;
+        let bearer =
+            |token: &str| rocket::http::Header::new("Authorization", format!("Bearer {}", token));
+        assert_eq!(login(&client, "admin", "administrator").await, Status::Ok);
+
+        let permissions = || client.get("/api/auth/permissions");
+        let all = permissions()
+            .header(bearer(&token))
+            .identity(certificate.as_bytes());
+        assert_eq!(requester(all).await.as_deref(), Ok("token ci"));
+        // An invalid token is rejected rather than falling back to the session
+        let invalid = permissions()
+            .header(bearer("rwu_invalid"))
+            .identity(certificate.as_bytes());
+        assert_eq!(requester(invalid).await, Err(Status::Unauthorized));
+
+        let session = permissions().identity(certificate.as_bytes());
+        assert_eq!(requester(session).await.as_deref(), Ok("admin"));
+
+        client.post("/api/auth/logout").dispatch().await;
+        let certificate_only = permissions().identity(certificate.as_bytes());
+        assert_eq!(requester(certificate_only).await.as_deref(), Ok("device-1"));
+        assert_eq!(requester(permissions()).await, Err(Status::Unauthorized));
+    }
+
+    #[tokio::test]
+    async fn viewers_cannot_change_the_system() {
+        let dir = TempDir::new().unwrap();
+        let client = client(&dir, RecordingBackend::default(), Some(auth_config()), None).await;
+        let auth = client.rocket().state::<Auth>().unwrap();
+        for (username, role) in [("viewer", Role::Viewer), ("operator", Role::Operator)] {
+            let user = NewUser {
+                username: username.to_string(),
+                password: format!("{} password", username),
+                role,
+            };
+            auth.create_user(user, "admin").await.unwrap();
+        }
+
+        assert_eq!(
+            login(&client, "viewer", "viewer password").await,
+            Status::Ok
+        );
+        let response = client.get("/api/status").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        for path in ["/api/install", "/api/reboot", "/api/slots/other/mark"] {
+            let response = client
+                .post(path)
+                .header(ContentType::JSON)
+                .body(r#"{"mark": "active"}"#)
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Forbidden, "{}", path);
+            let error: serde_json::Value = response.into_json().await.unwrap();
+            assert_eq!(error["code"], "forbidden", "{}", path);
+        }
+
+        // Operators get past the permission check, failing as no bundle was uploaded
+        assert_eq!(
+            login(&client, "operator", "operator password").await,
+            Status::Ok
+        );
+        let response = client.post("/api/install").dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[tokio::test]
+    async fn install_url_passes_streaming_options_to_the_backend() {
+        let dir = TempDir::new().unwrap();
+        let backend = RecordingBackend::default();
+        let client = client(&dir, backend.clone(), None, None).await;
+        let url = "https://updates.example.com/update.raucb";
+
+        let response = client
+            .post("/api/install-url")
+            .header(ContentType::JSON)
+            .body(
+                serde_json::json!({
+                    "url": url,
+                    "headers": ["Authorization: Bearer secret"],
+                })
+                .to_string(),
+            )
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        // The installation runs in a job started by the request
+        let mut calls = Vec::new();
+        for _ in 0..50 {
+            calls = backend.calls("install");
+            if !calls.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        calls.extend(backend.calls("info"));
+        assert_eq!(calls.len(), 2);
+
+        for (method, bundle, streaming) in calls {
+            assert_eq!(bundle, url, "{}", method);
+            assert_eq!(
+                streaming.http_headers,
+                ["Authorization: Bearer secret"],
+                "{}",
+                method
+            );
+            assert_eq!(
+                streaming.tls_ca.as_deref(),
+                Some("/etc/rauc/streaming-ca.pem"),
+                "{}",
+                method
+            );
+        }
+    }
 }
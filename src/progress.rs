@@ -0,0 +1,335 @@
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Progress report printed by `rauc install`, e.g. ` 40% Checking bundle done.`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgressLine {
+    pub percentage: u8,
+    pub message: String,
+}
+
+/// Parse a progress line of rauc's install output, returning `None` for other output
+pub fn parse_line(line: &str) -> Option<ProgressLine> {
+    let (percentage, message) = line.trim_start().split_once("% ")?;
+    let percentage = percentage.parse::<u8>().ok().filter(|p| *p <= 100)?;
+
+    Some(ProgressLine {
+        percentage,
+        message: message.trim().to_string(),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepStatus {
+    Running,
+    Done,
+    Failed,
+    /// Ended without rauc reporting its end, as a step containing it ended first
+    Skipped,
+}
+
+impl ProgressLine {
+    /// Name of the step the message refers to and whether it started or ended
+    pub fn step(&self) -> (&str, StepStatus) {
+        if let Some(name) = self.message.strip_suffix(" done.") {
+            (name, StepStatus::Done)
+        } else if let Some(name) = self.message.strip_suffix(" failed.") {
+            (name, StepStatus::Failed)
+        } else {
+            (&self.message, StepStatus::Running)
+        }
+    }
+}
+
+/// Step of an installation, e.g. "Verifying signature" nested in "Checking bundle"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Step {
+    pub name: String,
+    pub status: StepStatus,
+    /// Unix timestamp in milliseconds
+    pub started_at: u64,
+    pub duration_ms: Option<u64>,
+    pub steps: Vec<Step>,
+}
+
+/// Change to a step caused by a progress line
+#[derive(Debug, Clone)]
+pub struct StepUpdate {
+    pub name: String,
+    pub status: StepStatus,
+    /// Nesting level, 0 for top-level steps
+    pub depth: usize,
+}
+
+/// Installation progress reconstructed from rauc's progress lines
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InstallProgress {
+    pub percentage: u8,
+    pub message: Option<String>,
+    pub steps: Vec<Step>,
+    /// Indices leading from `steps` to the innermost running step
+    #[serde(skip)]
+    open: Vec<usize>,
+}
+
+fn unix_now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+impl InstallProgress {
+    /// Running step at the given depth of the open path
+    fn open_step(&mut self, depth: usize) -> &mut Step {
+        let mut step = &mut self.steps[self.open[0]];
+        for index in &self.open[1..=depth] {
+            step = &mut step.steps[*index];
+        }
+        step
+    }
+
+    /// End the open step at `depth` with `status` and the steps still open inside it with
+    /// `nested`
+    fn close_from(&mut self, depth: usize, status: StepStatus, nested: StepStatus) {
+        let now = unix_now_ms();
+        for level in (depth..self.open.len()).rev() {
+            let step = self.open_step(level);
+            step.status = if level == depth { status } else { nested };
+            step.duration_ms = Some(now.saturating_sub(step.started_at));
+        }
+        self.open.truncate(depth);
+    }
+
+    /// Update percentage and step tree, returning the step that started or ended
+    pub fn apply(&mut self, line: &ProgressLine) -> Option<StepUpdate> {
+        self.percentage = line.percentage;
+        self.message = Some(line.message.clone());
+
+        let (name, status) = line.step();
+        if status == StepStatus::Running {
+            let step = Step {
+                name: name.to_string(),
+                status,
+                started_at: unix_now_ms(),
+                duration_ms: None,
+                steps: Vec::new(),
+            };
+            let depth = self.open.len();
+            let siblings = if depth == 0 {
+                &mut self.steps
+            } else {
+                &mut self.open_step(depth - 1).steps
+            };
+            siblings.push(step);
+            let index = siblings.len() - 1;
+            self.open.push(index);
+
+            return Some(StepUpdate {
+                name: name.to_string(),
+                status,
+                depth,
+            });
+        }
+
+        // Match the innermost running step with that name, steps nested in it whose end rauc
+        // did not report are skipped
+        let depth = (0..self.open.len())
+            .rev()
+            .find(|depth| self.open_step(*depth).name == name)?;
+        self.close_from(depth, status, StepStatus::Skipped);
+
+        Some(StepUpdate {
+            name: name.to_string(),
+            status,
+            depth,
+        })
+    }
+
+    /// Mark all steps still running as failed, e.g. when rauc exited unexpectedly
+    pub fn abort(&mut self) {
+        self.close_from(0, StepStatus::Failed, StepStatus::Failed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUCCESS: &str = include_str!("../tests/fixtures/rauc-install-success.txt");
+    const SIGNATURE_FAILED: &str =
+        include_str!("../tests/fixtures/rauc-install-signature-failed.txt");
+
+    fn apply_all(output: &str) -> InstallProgress {
+        let mut progress = InstallProgress::default();
+        for line in output.lines().filter_map(parse_line) {
+            progress.apply(&line);
+        }
+        progress
+    }
+
+    /// Steps as `(depth, name, status)`, depth first
+    fn flatten(steps: &[Step], depth: usize) -> Vec<(usize, String, StepStatus)> {
+        steps
+            .iter()
+            .flat_map(|step| {
+                let mut steps = vec![(depth, step.name.clone(), step.status)];
+                steps.extend(flatten(&step.steps, depth + 1));
+                steps
+            })
+            .collect()
+    }
+
+    fn steps(steps: &[(usize, &str, StepStatus)]) -> Vec<(usize, String, StepStatus)> {
+        steps
+            .iter()
+            .map(|(depth, name, status)| (*depth, name.to_string(), *status))
+            .collect()
+    }
+
+    #[test]
+    fn parses_progress_lines_only() {
+        assert_eq!(
+            parse_line(" 40% Checking bundle done."),
+            Some(ProgressLine {
+                percentage: 40,
+                message: "Checking bundle done.".to_string(),
+            })
+        );
+        assert_eq!(parse_line("100% Installing done.").unwrap().percentage, 100);
+        assert_eq!(parse_line("installing"), None);
+        assert_eq!(
+            parse_line("Installing `/data/update.raucb` succeeded"),
+            None
+        );
+        assert_eq!(parse_line("LastError: Failed to check bundle"), None);
+        assert_eq!(parse_line("150% Installing"), None);
+        assert_eq!(parse_line("-5% Installing"), None);
+
+        let lines = SUCCESS.lines().filter_map(parse_line).count();
+        assert_eq!(lines, SUCCESS.lines().count() - 3);
+    }
+
+    #[test]
+    fn successful_install_builds_step_tree() {
+        use StepStatus::Done;
+
+        let progress = apply_all(SUCCESS);
+        assert_eq!(progress.percentage, 100);
+        assert_eq!(progress.message.as_deref(), Some("Installing done."));
+        assert_eq!(
+            flatten(&progress.steps, 0),
+            steps(&[
+                (0, "Installing", Done),
+                (1, "Determining slot states", Done),
+                (1, "Checking bundle", Done),
+                (2, "Verifying signature", Done),
+                (1, "Checking manifest contents", Done),
+                (1, "Determining target install group", Done),
+                (1, "Updating slots", Done),
+                (2, "Checking slot rootfs.1", Done),
+                (2, "Copying image to rootfs.1", Done),
+                (2, "Checking slot appfs.1", Done),
+                (2, "Copying image to appfs.1", Done),
+            ])
+        );
+        assert!(progress.steps[0].duration_ms.is_some());
+        assert!(progress.open.is_empty());
+    }
+
+    #[test]
+    fn failed_install_marks_enclosing_steps_failed() {
+        use StepStatus::{Done, Failed};
+
+        let progress = apply_all(SIGNATURE_FAILED);
+        assert_eq!(progress.percentage, 100);
+        assert_eq!(progress.message.as_deref(), Some("Installing failed."));
+        assert_eq!(
+            flatten(&progress.steps, 0),
+            steps(&[
+                (0, "Installing", Failed),
+                (1, "Determining slot states", Done),
+                (1, "Checking bundle", Failed),
+                (2, "Verifying signature", Failed),
+            ])
+        );
+    }
+
+    #[test]
+    fn apply_reports_step_changes() {
+        let mut progress = InstallProgress::default();
+        let mut updates = SIGNATURE_FAILED
+            .lines()
+            .filter_map(parse_line)
+            .filter_map(|line| progress.apply(&line))
+            .map(|update| (update.depth, update.name, update.status));
+
+        assert_eq!(
+            updates.nth(4),
+            Some((2, "Verifying signature".to_string(), StepStatus::Running))
+        );
+        assert_eq!(
+            updates.next(),
+            Some((2, "Verifying signature".to_string(), StepStatus::Failed))
+        );
+    }
+
+    #[test]
+    fn ending_an_outer_step_skips_the_steps_nested_in_it() {
+        let mut progress = InstallProgress::default();
+        for line in [
+            "  0% Installing",
+            " 10% Checking bundle",
+            " 20% Verifying signature",
+        ] {
+            progress.apply(&parse_line(line).unwrap());
+        }
+        progress.apply(&parse_line(" 30% Installing done.").unwrap());
+
+        assert_eq!(
+            flatten(&progress.steps, 0),
+            steps(&[
+                (0, "Installing", StepStatus::Done),
+                (1, "Checking bundle", StepStatus::Skipped),
+                (2, "Verifying signature", StepStatus::Skipped),
+            ])
+        );
+    }
+
+    #[test]
+    fn abort_fails_the_running_steps() {
+        use StepStatus::{Done, Failed};
+
+        // rauc stopped while copying the first image
+        let output: String = SUCCESS
+            .lines()
+            .take_while(|line| !line.contains("Copying image to rootfs.1 done."))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        let mut progress = apply_all(&output);
+        assert_eq!(progress.percentage, 85);
+        progress.abort();
+
+        assert_eq!(
+            flatten(&progress.steps, 0),
+            steps(&[
+                (0, "Installing", Failed),
+                (1, "Determining slot states", Done),
+                (1, "Checking bundle", Done),
+                (2, "Verifying signature", Done),
+                (1, "Checking manifest contents", Done),
+                (1, "Determining target install group", Done),
+                (1, "Updating slots", Failed),
+                (2, "Checking slot rootfs.1", Done),
+                (2, "Copying image to rootfs.1", Failed),
+            ])
+        );
+        assert!(progress.open.is_empty());
+        assert!(progress.steps[0].duration_ms.is_some());
+
+        // Nothing left to abort
+        progress.abort();
+        assert_eq!(progress.steps[0].status, Failed);
+    }
+}
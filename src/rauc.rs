@@ -1,102 +1,210 @@
+use crate::backend::{InstallStream, RaucBackend, StreamingOptions};
+use crate::error::RaucError;
 use serde::{Deserialize, Serialize};
-use std::process::{Command, Stdio};
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::Command as TokioCommand;
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Unrecognized JSON fields, kept so output from newer rauc versions round-trips
+pub type ExtraFields = serde_json::Map<String, serde_json::Value>;
+
+/// System status as reported by `rauc status --detailed --output-format=json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RaucStatus {
     pub compatible: String,
+    #[serde(default)]
     pub variant: String,
     pub booted: String,
-    pub boot_primary: String,
-    pub slots: Vec<serde_json::Value>,
-    #[serde(rename = "artifact-repositories")]
-    pub artifact_repositories: Vec<serde_json::Value>,
+    pub boot_primary: Option<String>,
+    /// Each entry maps a single slot name (e.g. `rootfs.0`) to its status
+    pub slots: Vec<BTreeMap<String, RaucSlot>>,
+    #[serde(rename = "artifact-repositories", default)]
+    pub artifact_repositories: Vec<ArtifactRepository>,
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+impl RaucStatus {
+    /// All slots with their names, in the order RAUC reported them
+    pub fn slots(&self) -> impl Iterator<Item = (&str, &RaucSlot)> {
+        self.slots
+            .iter()
+            .flat_map(|slot| slot.iter())
+            .map(|(name, slot)| (name.as_str(), slot))
+    }
+
+    pub fn booted_slot(&self) -> Option<(&str, &RaucSlot)> {
+        self.slots()
+            .find(|(_, slot)| slot.state == SlotState::Booted)
+    }
+
+    /// Version of the bundle installed in the booted slot, if RAUC recorded one
+    pub fn booted_version(&self) -> Option<&str> {
+        self.booted_slot()
+            .and_then(|(_, slot)| slot.installed_version())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RaucSlot {
+    pub class: String,
+    pub device: String,
+    #[serde(rename = "type")]
+    pub slot_type: String,
+    pub bootname: Option<String>,
+    pub state: SlotState,
+    pub parent: Option<String>,
+    pub mountpoint: Option<String>,
+    pub boot_status: Option<BootStatus>,
+    pub slot_status: Option<SlotStatus>,
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+impl RaucSlot {
+    /// Version of the bundle last installed into the slot, if RAUC recorded one
+    pub fn installed_version(&self) -> Option<&str> {
+        self.slot_status
+            .as_ref()
+            .and_then(|status| status.bundle.as_ref())
+            .and_then(|bundle| bundle.version.as_deref())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlotState {
+    Booted,
+    Active,
+    Inactive,
+    #[serde(other)]
+    Unknown,
+}
+
+/// Mark set with `rauc status mark-good|mark-bad|mark-active`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlotMark {
+    Good,
+    Bad,
+    /// Boot the slot next
+    Active,
+}
+
+impl SlotMark {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SlotMark::Good => "good",
+            SlotMark::Bad => "bad",
+            SlotMark::Active => "active",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BootStatus {
+    Good,
+    Bad,
+    #[serde(other)]
+    Unknown,
+}
+
+/// Per-slot information RAUC records about the last installation into a slot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlotStatus {
+    pub bundle: Option<InstalledBundle>,
+    pub checksum: Option<SlotChecksum>,
+    pub installed: Option<SlotEvent>,
+    pub activated: Option<SlotEvent>,
+    pub status: Option<String>,
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstalledBundle {
+    pub compatible: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    pub build: Option<String>,
+    pub hash: Option<String>,
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlotChecksum {
+    pub sha256: Option<String>,
+    pub size: Option<u64>,
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+/// Timestamp and counter of a slot installation or activation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlotEvent {
+    pub timestamp: Option<String>,
+    pub count: Option<u64>,
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtifactRepository {
+    pub name: String,
+    pub description: Option<String>,
+    pub path: Option<String>,
+    #[serde(rename = "type")]
+    pub repository_type: Option<String>,
+    #[serde(flatten)]
+    pub extra: ExtraFields,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Bundle information as reported by `rauc info --output-format=json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RaucBundleInfo {
     pub compatible: String,
     pub version: String,
-    pub description: String,
-    pub build: String,
-    pub format: String,
-    pub hooks: Vec<serde_json::Value>,
-    pub hash: String,
-    pub images: Vec<serde_json::Value>,
+    pub description: Option<String>,
+    pub build: Option<String>,
+    pub format: Option<String>,
+    #[serde(default)]
+    pub hooks: Vec<String>,
+    pub hash: Option<String>,
+    /// Each entry maps a single slot class (e.g. `rootfs`) to the image for it
+    #[serde(default)]
+    pub images: Vec<BTreeMap<String, RaucBundleImage>>,
+    /// Manifest `[meta.<group>]` sections
+    #[serde(default)]
+    pub meta: BTreeMap<String, BTreeMap<String, String>>,
+    #[serde(flatten)]
+    pub extra: ExtraFields,
 }
 
-#[derive(Clone)]
-pub enum RaucMode {
-    Development {
-        ssh_host: String,
-        ssh_password: String,
-    },
-    Production,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RaucBundleImage {
+    pub variant: Option<String>,
+    pub filename: Option<String>,
+    pub checksum: Option<String>,
+    pub size: Option<u64>,
+    #[serde(default)]
+    pub hooks: Vec<String>,
+    #[serde(default)]
+    pub adaptive: Vec<String>,
+    #[serde(flatten)]
+    pub extra: ExtraFields,
 }
 
+/// Front end for RAUC operations, delegating to the configured backend
 #[derive(Clone)]
 pub struct RaucClient {
-    mode: RaucMode,
+    backend: Arc<dyn RaucBackend>,
 }
 
 impl RaucClient {
-    pub fn new(mode: RaucMode) -> Self {
-        Self { mode }
-    }
-
-    /// Helper method to execute SSH commands with sshpass in development mode
-    fn execute_ssh_command(&self, args: &[&str]) -> Result<std::process::Output, String> {
-        match &self.mode {
-            RaucMode::Development {
-                ssh_host,
-                ssh_password,
-            } => {
-                let mut ssh_args = vec![
-                    "-o",
-                    "StrictHostKeyChecking=no",
-                    "-o",
-                    "UserKnownHostsFile=/dev/null",
-                    ssh_host.as_str(),
-                ];
-                ssh_args.extend_from_slice(args);
-
-                Command::new("sshpass")
-                    .arg("-p")
-                    .arg(ssh_password)
-                    .arg("ssh")
-                    .args(&ssh_args)
-                    .output()
-                    .map_err(|e| format!("Failed to execute sshpass ssh command: {}", e))
-            }
-            RaucMode::Production => Err("SSH command called in production mode".to_string()),
-        }
-    }
-
-    /// Helper method to execute SCP commands with sshpass in development mode
-    fn execute_scp_command(
-        &self,
-        local_path: &str,
-        remote_path: &str,
-    ) -> Result<std::process::Output, String> {
-        match &self.mode {
-            RaucMode::Development {
-                ssh_host,
-                ssh_password,
-            } => Command::new("sshpass")
-                .arg("-p")
-                .arg(ssh_password)
-                .arg("scp")
-                .arg("-p")
-                .arg("-o")
-                .arg("StrictHostKeyChecking=no")
-                .arg("-o")
-                .arg("UserKnownHostsFile=/dev/null")
-                .arg(local_path)
-                .arg(format!("{}:{}", ssh_host, remote_path))
-                .output()
-                .map_err(|e| format!("Failed to execute scp command: {}", e)),
-            RaucMode::Production => Err("SCP command called in production mode".to_string()),
+    pub fn new(backend: impl RaucBackend + 'static) -> Self {
+        Self {
+            backend: Arc::new(backend),
         }
     }
 
@@ -104,219 +212,137 @@ impl RaucClient {
         &self,
         local_path: &str,
         target_path: &str,
-    ) -> Result<String, String> {
-        match &self.mode {
-            RaucMode::Development { ssh_host, .. } => {
-                // Get local file size before transfer
-                let local_size = std::fs::metadata(local_path)
-                    .map_err(|e| format!("Failed to read local file metadata: {}", e))?
-                    .len();
-
-                // Ensure parent directory exists on target
-                let parent_dir = std::path::Path::new(target_path)
-                    .parent()
-                    .ok_or_else(|| "Invalid target path".to_string())?;
-
-                let mkdir_output =
-                    self.execute_ssh_command(&[&format!("mkdir -p {}", parent_dir.display())])?;
-
-                if !mkdir_output.status.success() {
-                    let stderr = String::from_utf8_lossy(&mkdir_output.stderr);
-                    return Err(format!("Failed to create remote directory: {}", stderr));
-                }
-
-                // Copy file to target
-                let scp_output = self.execute_scp_command(local_path, target_path)?;
-
-                if !scp_output.status.success() {
-                    let stderr = String::from_utf8_lossy(&scp_output.stderr);
-                    return Err(format!("scp command failed: {}", stderr));
-                }
-
-                // Verify remote file size
-                let stat_output =
-                    self.execute_ssh_command(&[&format!("stat -c %s {}", target_path)])?;
-
-                if stat_output.status.success() {
-                    let remote_size_str = String::from_utf8_lossy(&stat_output.stdout);
-                    if let Ok(remote_size) = remote_size_str.trim().parse::<u64>() {
-                        if remote_size != local_size {
-                            return Err(format!(
-                                "File size mismatch after transfer: local {} bytes, remote {} bytes",
-                                local_size, remote_size
-                            ));
-                        }
-                        return Ok(format!(
-                            "File copied to {} successfully ({} bytes verified)",
-                            ssh_host, remote_size
-                        ));
-                    }
-                }
-
-                Ok(format!(
-                    "File copied to {} successfully ({} bytes)",
-                    ssh_host, local_size
-                ))
-            }
-            RaucMode::Production => {
-                // In production, file is already local, no copy needed
-                Ok("File already on target system (production mode)".to_string())
-            }
-        }
+    ) -> Result<String, RaucError> {
+        self.backend
+            .copy_file_to_target(local_path, target_path)
+            .await
     }
 
-    async fn execute_command(&self, args: &[&str]) -> Result<String, String> {
-        let output = match &self.mode {
-            RaucMode::Development { .. } => {
-                let mut rauc_args = vec!["rauc"];
-                rauc_args.extend_from_slice(args);
-                self.execute_ssh_command(&rauc_args)?
-            }
-            RaucMode::Production => Command::new("rauc")
-                .args(args)
-                .output()
-                .map_err(|e| format!("Failed to execute rauc command: {}", e))?,
-        };
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(format!("rauc command failed: {}", stderr));
-        }
+    pub async fn remove_file_from_target(&self, target_path: &str) -> Result<(), RaucError> {
+        self.backend.remove_file_from_target(target_path).await
+    }
+
+    pub async fn get_status(&self) -> Result<RaucStatus, RaucError> {
+        self.backend.get_status().await
+    }
 
-        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    pub async fn get_bundle_info(
+        &self,
+        bundle: &str,
+        streaming: &StreamingOptions,
+    ) -> Result<RaucBundleInfo, RaucError> {
+        self.backend.get_bundle_info(bundle, streaming).await
     }
 
-    pub async fn get_status(&self) -> Result<RaucStatus, String> {
-        let stdout = self
-            .execute_command(&["status", "--output-format=json"])
-            .await?;
+    pub async fn install_bundle(
+        &self,
+        bundle: &str,
+        streaming: &StreamingOptions,
+    ) -> Result<InstallStream, RaucError> {
+        self.backend.install_bundle(bundle, streaming).await
+    }
 
-        let status: RaucStatus = serde_json::from_str(&stdout)
-            .map_err(|e| format!("Failed to parse rauc status JSON: {}", e))?;
+    pub async fn boot_id(&self) -> Result<String, RaucError> {
+        self.backend.boot_id().await
+    }
 
-        Ok(status)
+    /// Mark `slot`, which is a slot name, `booted` or `other`
+    pub async fn mark_slot(&self, slot: &str, mark: SlotMark) -> Result<String, RaucError> {
+        self.backend.mark_slot(slot, mark).await
     }
 
-    pub async fn get_bundle_info(&self, bundle_path: &str) -> Result<RaucBundleInfo, String> {
-        let stdout = self
-            .execute_command(&["info", "--output-format=json", bundle_path])
-            .await?;
+    pub async fn reboot(&self) -> Result<String, RaucError> {
+        self.backend.reboot().await
+    }
+}
 
-        let info: RaucBundleInfo = serde_json::from_str(&stdout)
-            .map_err(|e| format!("Failed to parse rauc bundle info JSON: {}", e))?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    const STATUS: &str = include_str!("../tests/fixtures/rauc-status.json");
+    const INFO: &str = include_str!("../tests/fixtures/rauc-info.json");
+
+    /// Add a field unknown to this version at `pointer`
+    fn add_field(value: &mut Value, pointer: &str, name: &str) {
+        value
+            .pointer_mut(pointer)
+            .and_then(Value::as_object_mut)
+            .unwrap()
+            .insert(name.to_string(), json!({ "added-in": "rauc 2.0" }));
+    }
 
-        Ok(info)
+    #[test]
+    fn parses_status() {
+        let status: RaucStatus = serde_json::from_str(STATUS).unwrap();
+        assert_eq!(status.compatible, "raspberrypi5");
+        assert_eq!(status.boot_primary.as_deref(), Some("rootfs.0"));
+
+        let names: Vec<&str> = status.slots().map(|(name, _)| name).collect();
+        assert_eq!(names, ["rootfs.0", "appfs.0", "rootfs.1", "appfs.1"]);
+        assert_eq!(status.booted_slot().unwrap().0, "rootfs.0");
+        assert_eq!(status.booted_version(), Some("v20200703"));
+
+        let (_, rootfs1) = status.slots().nth(2).unwrap();
+        assert_eq!(rootfs1.state, SlotState::Inactive);
+        assert_eq!(rootfs1.boot_status, Some(BootStatus::Bad));
+        assert_eq!(rootfs1.installed_version(), Some("v20200601"));
+        let (_, appfs1) = status.slots().nth(3).unwrap();
+        assert_eq!(appfs1.parent.as_deref(), Some("rootfs.1"));
+        assert_eq!(appfs1.installed_version(), None);
     }
 
-    pub async fn install_bundle(
-        &self,
-        bundle_path: &str,
-    ) -> Result<impl tokio_stream::Stream<Item = Result<String, std::io::Error>>, String> {
-        let (mut command, description) = match &self.mode {
-            RaucMode::Development {
-                ssh_host,
-                ssh_password,
-            } => {
-                let ssh_args = vec![
-                    "-o".to_string(),
-                    "StrictHostKeyChecking=no".to_string(),
-                    "-o".to_string(),
-                    "UserKnownHostsFile=/dev/null".to_string(),
-                    ssh_host.clone(),
-                    "rauc".to_string(),
-                    "install".to_string(),
-                    bundle_path.to_string(),
-                ];
-
-                let mut cmd = TokioCommand::new("sshpass");
-                cmd.arg("-p").arg(ssh_password).arg("ssh").args(&ssh_args);
-
-                (cmd, "ssh install")
-            }
-            RaucMode::Production => {
-                let mut cmd = TokioCommand::new("rauc");
-                cmd.arg("install").arg(bundle_path);
-                (cmd, "local install")
-            }
-        };
-
-        let mut child = command
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(|e| format!("Failed to spawn {} command: {}", description, e))?;
-
-        let stdout = child
-            .stdout
-            .take()
-            .ok_or_else(|| "Failed to capture stdout".to_string())?;
-        let stderr = child
-            .stderr
-            .take()
-            .ok_or_else(|| "Failed to capture stderr".to_string())?;
-
-        let stream = async_stream::stream! {
-            let mut stdout_reader = BufReader::new(stdout).lines();
-            let mut stderr_reader = BufReader::new(stderr).lines();
-
-            loop {
-                tokio::select! {
-                    result = stdout_reader.next_line() => {
-                        match result {
-                            Ok(Some(line)) => yield Ok(format!("[OUT] {}\n", line)),
-                            Ok(None) => break,
-                            Err(e) => yield Err(e),
-                        }
-                    }
-                    result = stderr_reader.next_line() => {
-                        match result {
-                            Ok(Some(line)) => yield Ok(format!("[ERR] {}\n", line)),
-                            Ok(None) => {},
-                            Err(e) => yield Err(e),
-                        }
-                    }
-                }
-            }
-
-            // Wait for process to complete
-            match child.wait().await {
-                Ok(status) => {
-                    if status.success() {
-                        yield Ok("\n[DONE] Installation completed successfully\n".to_string());
-                    } else {
-                        yield Ok(format!("\n[ERROR] Installation failed with status: {}\n", status));
-                    }
-                }
-                Err(e) => {
-                    yield Ok(format!("\n[ERROR] Failed to wait for process: {}\n", e));
-                }
-            }
-        };
-
-        Ok(stream)
+    #[test]
+    fn status_keeps_unknown_fields() {
+        let mut original: Value = serde_json::from_str(STATUS).unwrap();
+        add_field(&mut original, "", "boot-attempts");
+        add_field(&mut original, "/slots/0/rootfs.0", "size");
+        add_field(&mut original, "/slots/0/rootfs.0/slot_status", "trust");
+        add_field(
+            &mut original,
+            "/slots/0/rootfs.0/slot_status/bundle",
+            "meta",
+        );
+        add_field(
+            &mut original,
+            "/slots/0/rootfs.0/slot_status/checksum",
+            "sha512",
+        );
+        add_field(
+            &mut original,
+            "/slots/0/rootfs.0/slot_status/installed",
+            "by",
+        );
+
+        let status: RaucStatus = serde_json::from_value(original.clone()).unwrap();
+        assert!(status.extra.contains_key("boot-attempts"));
+        assert_eq!(serde_json::to_value(&status).unwrap(), original);
     }
 
-    pub async fn reboot(&self) -> Result<String, String> {
-        match &self.mode {
-            RaucMode::Development { .. } => {
-                let output = self.execute_ssh_command(&["reboot"])?;
-
-                // Note: reboot might not return successfully as the connection will be dropped
-                if !output.status.success() {
-                    let stderr = String::from_utf8_lossy(&output.stderr);
-                    // Don't treat this as a fatal error, as the connection may drop
-                    eprintln!("Reboot stderr (may be expected): {}", stderr);
-                }
-
-                Ok("Reboot command sent".to_string())
-            }
-            RaucMode::Production => {
-                let _ = Command::new("reboot")
-                    .output()
-                    .map_err(|e| format!("Failed to execute reboot command: {}", e))?;
-
-                Ok("Reboot initiated".to_string())
-            }
-        }
+    #[test]
+    fn parses_bundle_info() {
+        let info: RaucBundleInfo = serde_json::from_str(INFO).unwrap();
+        assert_eq!(info.version, "v20200703");
+        assert_eq!(info.format.as_deref(), Some("verity"));
+        assert_eq!(info.hooks, ["install-check"]);
+        assert_eq!(info.meta["update"]["channel"], "stable");
+
+        let rootfs = &info.images[0]["rootfs"];
+        assert_eq!(rootfs.filename.as_deref(), Some("rootfs.ext4"));
+        assert_eq!(rootfs.size, Some(268435456));
+        assert_eq!(rootfs.adaptive, ["block-hash-index"]);
+        assert!(info.images[1]["appfs"].hooks.is_empty());
+    }
+
+    #[test]
+    fn bundle_info_keeps_unknown_fields() {
+        let mut original: Value = serde_json::from_str(INFO).unwrap();
+        add_field(&mut original, "", "signers");
+        add_field(&mut original, "/images/0/rootfs", "artifact");
+
+        let info: RaucBundleInfo = serde_json::from_value(original.clone()).unwrap();
+        assert!(info.extra.contains_key("signers"));
+        assert_eq!(serde_json::to_value(&info).unwrap(), original);
     }
 }
@@ -0,0 +1,312 @@
+use crate::error::RaucError;
+use crate::history::InstallHistory;
+use crate::jobs::{unix_now, JobState};
+use crate::rauc::{RaucClient, RaucStatus};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Installed slot waiting for a reboot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingReboot {
+    /// Slot the system is expected to boot next
+    pub slot: String,
+    /// Version of the bundle installed into the slot, from the install history
+    pub version: Option<String>,
+    pub job_id: Option<String>,
+    /// Unix timestamp in seconds
+    pub installed_at: u64,
+    /// Boot ID of the target when the installation was noticed
+    boot_id: Option<String>,
+}
+
+/// Whether the system came up in the expected slot after the last reboot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BootVerification {
+    pub expected_slot: String,
+    pub booted_slot: Option<String>,
+    pub success: bool,
+    pub version: Option<String>,
+    pub job_id: Option<String>,
+    /// Unix timestamp in seconds
+    pub verified_at: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RebootState {
+    pub pending: Option<PendingReboot>,
+    pub last_boot: Option<BootVerification>,
+}
+
+/// Keeps track of staged installations across restarts of the server and reboots
+#[derive(Clone)]
+pub struct RebootTracker {
+    path: PathBuf,
+    state: Arc<Mutex<RebootState>>,
+}
+
+impl RebootTracker {
+    /// Load the state persisted at `path` by a previous run
+    pub fn open(path: PathBuf) -> Self {
+        let state = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|state| match serde_json::from_str(&state) {
+                Ok(state) => Some(state),
+                Err(e) => {
+                    eprintln!("Ignoring unreadable reboot state: {}", e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        Self {
+            path,
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    fn save(&self, state: &RebootState) -> Result<(), RaucError> {
+        let json = serde_json::to_string_pretty(state)
+            .map_err(|e| RaucError::Internal(format!("Failed to serialize reboot state: {}", e)))?;
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| {
+                RaucError::Internal(format!("Failed to create reboot state directory: {}", e))
+            })?;
+        }
+
+        // Write to a temporary file first so a crash never leaves a truncated file
+        let temporary = self.path.with_extension("tmp");
+        std::fs::write(&temporary, json)
+            .and_then(|_| std::fs::rename(&temporary, &self.path))
+            .map_err(|e| RaucError::Internal(format!("Failed to write reboot state: {}", e)))
+    }
+
+    /// Update the state from the current slot status and boot ID
+    ///
+    /// An installation is pending while RAUC's primary slot differs from the booted one. Once
+    /// the boot ID changes, the booted slot is compared with the slot that was expected.
+    pub fn update(
+        &self,
+        status: &RaucStatus,
+        boot_id: Option<String>,
+        history: &InstallHistory,
+    ) -> RebootState {
+        let booted = status.booted_slot().map(|(name, _)| name.to_string());
+        let mut state = self.state.lock().unwrap();
+        let before = serde_json::to_value(&*state).ok();
+
+        if let Some(pending) = state.pending.clone() {
+            let rebooted = match (&pending.boot_id, &boot_id) {
+                (Some(before), Some(now)) => before != now,
+                // Without boot IDs a reboot is only noticed once the expected slot is booted
+                _ => booted.as_deref() == Some(pending.slot.as_str()),
+            };
+
+            if rebooted {
+                let success = booted.as_deref() == Some(pending.slot.as_str());
+                if success {
+                    println!("Booted installed slot {} as expected", pending.slot);
+                } else {
+                    eprintln!(
+                        "Expected to boot slot {} after installation, but booted {}",
+                        pending.slot,
+                        booted.as_deref().unwrap_or("unknown")
+                    );
+                }
+
+                state.last_boot = Some(BootVerification {
+                    expected_slot: pending.slot,
+                    booted_slot: booted.clone(),
+                    success,
+                    version: pending.version,
+                    job_id: pending.job_id,
+                    verified_at: unix_now(),
+                });
+                state.pending = None;
+            } else if status.boot_primary == booted {
+                // The booted slot was made primary again, e.g. by marking it active
+                state.pending = None;
+            }
+        }
+
+        if state.pending.is_none() {
+            if let Some(primary) = status.boot_primary.clone().filter(|_| booted.is_some()) {
+                if Some(&primary) != booted.as_ref() {
+                    // The primary slot may also have been switched without installing, e.g. by
+                    // a rollback, in which case the last installation went to another slot
+                    let installation = history
+                        .list()
+                        .into_iter()
+                        .find(|entry| entry.state == JobState::Succeeded)
+                        .filter(|entry| entry.slots.is_empty() || entry.slots.contains(&primary));
+
+                    state.pending = Some(PendingReboot {
+                        slot: primary,
+                        version: installation
+                            .as_ref()
+                            .and_then(|entry| entry.bundle_info.as_ref())
+                            .map(|info| info.version.clone()),
+                        job_id: installation.as_ref().map(|entry| entry.id.clone()),
+                        installed_at: installation
+                            .and_then(|entry| entry.finished_at)
+                            .unwrap_or_else(unix_now),
+                        boot_id,
+                    });
+                }
+            }
+        }
+
+        if serde_json::to_value(&*state).ok() != before {
+            if let Err(e) = self.save(&state) {
+                eprintln!("{}", e);
+            }
+        }
+
+        state.clone()
+    }
+
+    /// Fetch the slot status and boot ID from the target and update the state
+    pub async fn refresh(
+        &self,
+        client: &RaucClient,
+        history: &InstallHistory,
+    ) -> Result<RebootState, RaucError> {
+        let status = client.get_status().await?;
+        let boot_id = client.boot_id().await.ok();
+        Ok(self.update(&status, boot_id, history))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use crate::history::Retention;
+    use crate::rauc::SlotMark;
+    use tempfile::TempDir;
+
+    struct Fixture {
+        dir: TempDir,
+        client: RaucClient,
+        history: InstallHistory,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let dir = TempDir::new().unwrap();
+            let retention = Retention {
+                max_entries: 10,
+                max_age_days: 365,
+            };
+            let history = InstallHistory::open(dir.path().join("history"), retention);
+            Self {
+                dir,
+                client: RaucClient::new(MockBackend::new()),
+                history,
+            }
+        }
+
+        fn tracker(&self) -> RebootTracker {
+            RebootTracker::open(self.dir.path().join("reboot-state.json"))
+        }
+
+        async fn update(&self, tracker: &RebootTracker, boot_id: &str) -> RebootState {
+            let status = self.client.get_status().await.unwrap();
+            tracker.update(&status, Some(boot_id.to_string()), &self.history)
+        }
+    }
+
+    #[tokio::test]
+    async fn nothing_is_pending_while_the_booted_slot_is_primary() {
+        let fixture = Fixture::new();
+        let state = fixture.update(&fixture.tracker(), "boot-1").await;
+        assert!(state.pending.is_none());
+        assert!(state.last_boot.is_none());
+    }
+
+    #[tokio::test]
+    async fn installation_stays_pending_until_a_new_boot() {
+        let fixture = Fixture::new();
+        let tracker = fixture.tracker();
+        fixture
+            .client
+            .mark_slot("other", SlotMark::Active)
+            .await
+            .unwrap();
+
+        let state = fixture.update(&tracker, "boot-1").await;
+        let pending = state.pending.unwrap();
+        assert_eq!(pending.slot, "rootfs.1");
+        assert_eq!(pending.boot_id.as_deref(), Some("boot-1"));
+
+        // Same boot, e.g. after restarting the server
+        let state = fixture.update(&fixture.tracker(), "boot-1").await;
+        assert_eq!(state.pending.unwrap().slot, "rootfs.1");
+        assert!(state.last_boot.is_none());
+    }
+
+    #[tokio::test]
+    async fn new_boot_of_the_expected_slot_is_verified() {
+        let fixture = Fixture::new();
+        let tracker = fixture.tracker();
+        fixture
+            .client
+            .mark_slot("other", SlotMark::Active)
+            .await
+            .unwrap();
+        fixture.update(&tracker, "boot-1").await;
+
+        fixture.client.reboot().await.unwrap();
+        let state = fixture.update(&tracker, "boot-2").await;
+        assert!(state.pending.is_none());
+        let last_boot = state.last_boot.unwrap();
+        assert!(last_boot.success);
+        assert_eq!(last_boot.expected_slot, "rootfs.1");
+        assert_eq!(last_boot.booted_slot.as_deref(), Some("rootfs.1"));
+
+        // The verification survives a restart
+        let state = fixture.tracker().state.lock().unwrap().clone();
+        assert!(state.last_boot.unwrap().success);
+    }
+
+    #[tokio::test]
+    async fn new_boot_of_the_old_slot_is_a_fallback() {
+        let fixture = Fixture::new();
+        let tracker = fixture.tracker();
+        fixture
+            .client
+            .mark_slot("other", SlotMark::Active)
+            .await
+            .unwrap();
+        fixture.update(&tracker, "boot-1").await;
+
+        // The bootloader gave up on rootfs.1 and booted rootfs.0 again
+        let state = fixture.update(&tracker, "boot-2").await;
+        let last_boot = state.last_boot.unwrap();
+        assert!(!last_boot.success);
+        assert_eq!(last_boot.expected_slot, "rootfs.1");
+        assert_eq!(last_boot.booted_slot.as_deref(), Some("rootfs.0"));
+    }
+
+    #[tokio::test]
+    async fn marking_the_booted_slot_active_cancels_the_pending_reboot() {
+        let fixture = Fixture::new();
+        let tracker = fixture.tracker();
+        fixture
+            .client
+            .mark_slot("other", SlotMark::Active)
+            .await
+            .unwrap();
+        fixture.update(&tracker, "boot-1").await;
+
+        fixture
+            .client
+            .mark_slot("booted", SlotMark::Active)
+            .await
+            .unwrap();
+        let state = fixture.update(&tracker, "boot-1").await;
+        assert!(state.pending.is_none());
+        assert!(state.last_boot.is_none());
+    }
+}
@@ -0,0 +1,203 @@
+use crate::error::RaucError;
+use crate::rauc::{RaucClient, RaucSlot, RaucStatus, SlotMark};
+use serde::Serialize;
+
+/// Slot a rollback would boot, as shown to the user before confirming
+#[derive(Debug, Clone, Serialize)]
+pub struct RollbackPlan {
+    /// Currently booted slot
+    pub booted_slot: String,
+    pub booted_version: Option<String>,
+    /// Slot of the same group that will be booted after the rollback
+    pub target_slot: String,
+    pub target_version: String,
+    pub target_bootname: Option<String>,
+}
+
+/// Result of a rollback, with the messages of each step
+#[derive(Debug, Clone, Serialize)]
+pub struct RollbackResult {
+    pub plan: RollbackPlan,
+    pub messages: Vec<String>,
+}
+
+/// Whether RAUC recorded a completed installation into the slot
+fn has_valid_bundle(slot: &RaucSlot) -> bool {
+    let status = slot
+        .slot_status
+        .as_ref()
+        .and_then(|status| status.status.as_deref());
+    slot.installed_version().is_some() && status.is_none_or(|status| status == "ok")
+}
+
+/// Work out the other bootable slot of the booted slot's group
+///
+/// Fails with `RollbackUnavailable` if there is no such slot or it holds no valid bundle.
+pub fn plan(status: &RaucStatus) -> Result<RollbackPlan, RaucError> {
+    let (booted_name, booted) = status.booted_slot().ok_or_else(|| {
+        RaucError::RollbackUnavailable("RAUC does not report a booted slot".to_string())
+    })?;
+
+    let candidates: Vec<(&str, &RaucSlot)> = status
+        .slots()
+        .filter(|(name, slot)| {
+            *name != booted_name
+                && slot.class == booted.class
+                && slot.bootname.is_some()
+                && slot.parent.is_none()
+        })
+        .collect();
+
+    if candidates.is_empty() {
+        return Err(RaucError::RollbackUnavailable(format!(
+            "There is no other bootable {} slot",
+            booted.class
+        )));
+    }
+
+    let (target_name, target) = candidates
+        .iter()
+        .find(|(_, slot)| has_valid_bundle(slot))
+        .ok_or_else(|| {
+            RaucError::RollbackUnavailable(format!(
+                "Slot {} has no valid installed bundle",
+                candidates[0].0
+            ))
+        })?;
+
+    Ok(RollbackPlan {
+        booted_slot: booted_name.to_string(),
+        booted_version: booted.installed_version().map(str::to_string),
+        target_slot: target_name.to_string(),
+        target_version: target.installed_version().unwrap_or_default().to_string(),
+        target_bootname: target.bootname.clone(),
+    })
+}
+
+/// Boot the other slot: mark it active, optionally mark the booted slot bad, then reboot
+pub async fn rollback(client: &RaucClient, mark_bad: bool) -> Result<RollbackResult, RaucError> {
+    let plan = plan(&client.get_status().await?)?;
+    let mut messages = vec![
+        client
+            .mark_slot(&plan.target_slot, SlotMark::Active)
+            .await?,
+    ];
+
+    if mark_bad {
+        messages.push(client.mark_slot(&plan.booted_slot, SlotMark::Bad).await?);
+    }
+
+    messages.push(client.reboot().await?);
+
+    Ok(RollbackResult { plan, messages })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use crate::rauc::{BootStatus, SlotState};
+
+    async fn mock_status() -> RaucStatus {
+        RaucClient::new(MockBackend::new())
+            .get_status()
+            .await
+            .unwrap()
+    }
+
+    fn slot_mut<'a>(status: &'a mut RaucStatus, name: &str) -> &'a mut RaucSlot {
+        status
+            .slots
+            .iter_mut()
+            .find_map(|slot| slot.get_mut(name))
+            .unwrap()
+    }
+
+    fn unavailable(result: Result<RollbackPlan, RaucError>) -> String {
+        match result {
+            Err(RaucError::RollbackUnavailable(message)) => message,
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn plans_booting_the_other_slot_of_the_group() {
+        let plan = plan(&mock_status().await).unwrap();
+        assert_eq!(plan.booted_slot, "rootfs.0");
+        assert_eq!(plan.booted_version.as_deref(), Some("0.9.0"));
+        assert_eq!(plan.target_slot, "rootfs.1");
+        assert_eq!(plan.target_version, "0.8.0");
+        assert_eq!(plan.target_bootname.as_deref(), Some("B"));
+    }
+
+    #[tokio::test]
+    async fn refuses_slots_without_a_valid_bundle() {
+        let mut status = mock_status().await;
+        let slot_status = slot_mut(&mut status, "rootfs.1")
+            .slot_status
+            .as_mut()
+            .unwrap();
+        slot_status.status = Some("failed".to_string());
+        assert_eq!(
+            unavailable(plan(&status)),
+            "Slot rootfs.1 has no valid installed bundle"
+        );
+
+        let mut status = mock_status().await;
+        slot_mut(&mut status, "rootfs.1").slot_status = None;
+        assert_eq!(
+            unavailable(plan(&status)),
+            "Slot rootfs.1 has no valid installed bundle"
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_without_another_bootable_slot() {
+        let mut status = mock_status().await;
+        status.slots.retain(|slot| !slot.contains_key("rootfs.1"));
+        assert_eq!(
+            unavailable(plan(&status)),
+            "There is no other bootable rootfs slot"
+        );
+
+        let mut status = mock_status().await;
+        slot_mut(&mut status, "rootfs.1").bootname = None;
+        assert_eq!(
+            unavailable(plan(&status)),
+            "There is no other bootable rootfs slot"
+        );
+
+        let mut status = mock_status().await;
+        slot_mut(&mut status, "rootfs.0").state = SlotState::Inactive;
+        assert_eq!(
+            unavailable(plan(&status)),
+            "RAUC does not report a booted slot"
+        );
+    }
+
+    #[tokio::test]
+    async fn rollback_boots_the_other_slot() {
+        for mark_bad in [false, true] {
+            let client = RaucClient::new(MockBackend::new());
+            let result = rollback(&client, mark_bad).await.unwrap();
+            assert_eq!(result.plan.target_slot, "rootfs.1");
+            assert_eq!(result.messages.len(), if mark_bad { 3 } else { 2 });
+
+            // The mock reboots into the primary slot right away
+            let status = client.get_status().await.unwrap();
+            let (booted, _) = status.booted_slot().unwrap();
+            assert_eq!(booted, "rootfs.1");
+            let previous = status
+                .slots()
+                .find(|(name, _)| *name == "rootfs.0")
+                .unwrap()
+                .1;
+            let expected = if mark_bad {
+                BootStatus::Bad
+            } else {
+                BootStatus::Good
+            };
+            assert_eq!(previous.boot_status, Some(expected), "{}", mark_bad);
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use crate::users::Role;
+use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair};
+use rocket::config::Shutdown;
+use rocket::http::uri::{Host, Origin};
+use rocket::mtls::x509::GeneralName;
+use rocket::mtls::Certificate;
+use rocket::response::Redirect;
+use rocket::{Build, Rocket, State};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::IpAddr;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const GENERATED_CERT_FILE: &str = "tls-cert.pem";
+const GENERATED_KEY_FILE: &str = "tls-key.pem";
+
+/// Certificate the server presents to clients
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerCertificate {
+    /// SHA-256 of the DER encoded certificate, colon separated hex as shown by browsers
+    pub sha256_fingerprint: String,
+    /// Generated on the device rather than configured with `TLS_CERT` and `TLS_KEY`
+    pub generated: bool,
+    /// PEM encoded certificate chain, e.g. for clients to pin
+    pub certificate: String,
+    #[serde(skip)]
+    pub cert_path: PathBuf,
+    #[serde(skip)]
+    pub key_path: PathBuf,
+}
+
+impl ServerCertificate {
+    /// Use the configured certificate and key, or the self-signed certificate in `dir`, which
+    /// is generated on first start
+    pub fn load_or_generate(
+        cert: Option<String>,
+        key: Option<String>,
+        dir: &Path,
+    ) -> Result<Self, String> {
+        match (cert, key) {
+            (Some(cert), Some(key)) => Self::load(cert.into(), key.into(), false),
+            (None, None) => {
+                let cert_path = dir.join(GENERATED_CERT_FILE);
+                let key_path = dir.join(GENERATED_KEY_FILE);
+                if !cert_path.exists() || !key_path.exists() {
+                    generate(&cert_path, &key_path)?;
+                }
+                Self::load(cert_path, key_path, true)
+            }
+            _ => Err("TLS_CERT and TLS_KEY must be set together".to_string()),
+        }
+    }
+
+    fn load(cert_path: PathBuf, key_path: PathBuf, generated: bool) -> Result<Self, String> {
+        let certificate = std::fs::read_to_string(&cert_path).map_err(|e| {
+            format!(
+                "Failed to read TLS certificate {}: {}",
+                cert_path.display(),
+                e
+            )
+        })?;
+        let leaf = pem::parse_many(&certificate)
+            .map_err(|e| format!("Failed to parse TLS certificate: {}", e))?
+            .into_iter()
+            .find(|block| block.tag() == "CERTIFICATE")
+            .ok_or_else(|| format!("{} does not contain a certificate", cert_path.display()))?;
+
+        Ok(Self {
+            sha256_fingerprint: fingerprint(leaf.contents()),
+            generated,
+            certificate,
+            cert_path,
+            key_path,
+        })
+    }
+}
+
+fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn hostname() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+        .ok()
+        .map(|hostname| hostname.trim().to_string())
+        .filter(|hostname| !hostname.is_empty())
+}
+
+/// Names the device is reachable under: its hostname, localhost and the addresses of all
+/// network interfaces
+fn subject_alt_names() -> Vec<String> {
+    let mut names = Vec::new();
+    if let Some(hostname) = hostname() {
+        names.push(hostname);
+    }
+    names.push("localhost".to_string());
+    match if_addrs::get_if_addrs() {
+        Ok(interfaces) => {
+            for interface in interfaces {
+                let address = interface.ip().to_string();
+                if !names.contains(&address) {
+                    names.push(address);
+                }
+            }
+        }
+        Err(e) => eprintln!("Failed to list network interfaces: {}", e),
+    }
+    names
+}
+
+/// Create a self-signed certificate for the device
+fn generate(cert_path: &Path, key_path: &Path) -> Result<(), String> {
+    let names = subject_alt_names();
+    let mut params = CertificateParams::new(names.clone())
+        .map_err(|e| format!("Failed to create TLS certificate parameters: {}", e))?;
+    let mut subject = DistinguishedName::new();
+    subject.push(DnType::CommonName, names[0].clone());
+    subject.push(DnType::OrganizationName, "RAUC Web UI");
+    params.distinguished_name = subject;
+
+    let key = KeyPair::generate().map_err(|e| format!("Failed to generate TLS key: {}", e))?;
+    let cert = params
+        .self_signed(&key)
+        .map_err(|e| format!("Failed to sign TLS certificate: {}", e))?;
+
+    if let Some(dir) = cert_path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create TLS certificate directory: {}", e))?;
+    }
+    write_file(key_path, &key.serialize_pem(), 0o600)
+        .map_err(|e| format!("Failed to write TLS key: {}", e))?;
+    write_file(cert_path, &cert.pem(), 0o644)
+        .map_err(|e| format!("Failed to write TLS certificate: {}", e))?;
+
+    println!(
+        "Generated self-signed TLS certificate for {}",
+        names.join(", ")
+    );
+    Ok(())
+}
+
+/// Write to a temporary file first so a crash never leaves a truncated file
+fn write_file(path: &Path, contents: &str, mode: u32) -> std::io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(&temporary)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&temporary, path)
+}
+
+/// Field of a client certificate naming the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientIdentity {
+    /// Common name of the subject
+    CommonName,
+    /// Email address in the subject alternative names, or else in the subject
+    Email,
+    /// DNS name in the subject alternative names
+    Dns,
+    /// URI in the subject alternative names
+    Uri,
+}
+
+impl FromStr for ClientIdentity {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "cn" => Ok(ClientIdentity::CommonName),
+            "email" => Ok(ClientIdentity::Email),
+            "dns" => Ok(ClientIdentity::Dns),
+            "uri" => Ok(ClientIdentity::Uri),
+            other => Err(format!(
+                "Unknown client certificate identity '{}' (expected cn, email, dns or uri)",
+                other
+            )),
+        }
+    }
+}
+
+/// Mapping of client certificates, verified by Rocket against `TLS_CLIENT_CA`, to users
+#[derive(Debug, Clone)]
+pub struct ClientCertConfig {
+    pub identity: ClientIdentity,
+    /// Role of certificates not naming a known user, which are rejected if not set
+    pub default_role: Option<Role>,
+}
+
+impl ClientCertConfig {
+    /// Name of the user a certificate identifies
+    pub fn identity(&self, certificate: &Certificate<'_>) -> Option<String> {
+        let alt_name = |matches: fn(&GeneralName<'_>) -> Option<String>| {
+            certificate
+                .subject_alternative_name()
+                .ok()
+                .flatten()
+                .and_then(|names| names.value.general_names.iter().find_map(matches))
+        };
+
+        let name = match self.identity {
+            ClientIdentity::CommonName => certificate.subject().common_name().map(str::to_string),
+            ClientIdentity::Email => alt_name(|name| match name {
+                GeneralName::RFC822Name(email) => Some(email.to_string()),
+                _ => None,
+            })
+            .or_else(|| certificate.subject().email().map(str::to_string)),
+            ClientIdentity::Dns => alt_name(|name| match name {
+                GeneralName::DNSName(dns) => Some(dns.to_string()),
+                _ => None,
+            }),
+            ClientIdentity::Uri => alt_name(|name| match name {
+                GeneralName::URI(uri) => Some(uri.to_string()),
+                _ => None,
+            }),
+        };
+        name.filter(|name| !name.is_empty())
+    }
+}
+
+/// Port of the HTTPS listener plain HTTP requests are redirected to
+struct HttpsPort(u16);
+
+#[get("/<_..>")]
+fn redirect_to_https(uri: &Origin<'_>, host: &Host<'_>, https_port: &State<HttpsPort>) -> Redirect {
+    let authority = match https_port.0 {
+        443 => host.domain().to_string(),
+        port => format!("{}:{}", host.domain(), port),
+    };
+    Redirect::permanent(format!("https://{}{}", authority, uri))
+}
+
+/// Plain HTTP listener redirecting every request to the HTTPS listener on `https_port`
+pub fn redirect_server(address: IpAddr, port: u16, https_port: u16) -> Rocket<Build> {
+    rocket::custom(rocket::Config {
+        address,
+        port,
+        // The main server handles signals, the process ends with it
+        shutdown: Shutdown {
+            ctrlc: false,
+            signals: Default::default(),
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .manage(HttpsPort(https_port))
+    .mount("/", routes![redirect_to_https])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{DnValue, SanType};
+    use rocket::http::Status;
+    use rocket::local::asynchronous::Client;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// OID of the PKCS #9 emailAddress attribute of a subject
+    const EMAIL_ADDRESS: [u64; 7] = [1, 2, 840, 113549, 1, 9, 1];
+
+    #[get("/identity")]
+    fn identity(certificate: Certificate<'_>, config: &State<ClientCertConfig>) -> Option<String> {
+        config.identity(&certificate)
+    }
+
+    /// Identity `identity` maps a client certificate with `subject` and `alt_names` to
+    async fn identify(
+        identity: ClientIdentity,
+        subject: Vec<(DnType, &str)>,
+        alt_names: Vec<SanType>,
+    ) -> Option<String> {
+        let mut params = CertificateParams::default();
+        // The default names contain a placeholder common name
+        params.distinguished_name = DistinguishedName::new();
+        for (ty, value) in subject {
+            params
+                .distinguished_name
+                .push(ty, DnValue::Utf8String(value.to_string()));
+        }
+        params.subject_alt_names = alt_names;
+        let key = KeyPair::generate().unwrap();
+        let certificate = params.self_signed(&key).unwrap().pem();
+
+        let config = ClientCertConfig {
+            identity,
+            default_role: None,
+        };
+        let rocket = rocket::build().manage(config).mount("/", routes![identity]);
+        let client = Client::untracked(rocket).await.unwrap();
+        let response = client
+            .get("/identity")
+            .identity(certificate.as_bytes())
+            .dispatch()
+            .await;
+        if response.status() == Status::NotFound {
+            return None;
+        }
+        response.into_string().await
+    }
+
+    fn email(address: &str) -> SanType {
+        SanType::Rfc822Name(address.try_into().unwrap())
+    }
+
+    fn dns(name: &str) -> SanType {
+        SanType::DnsName(name.try_into().unwrap())
+    }
+
+    fn uri(uri: &str) -> SanType {
+        SanType::URI(uri.try_into().unwrap())
+    }
+
+    #[test]
+    fn generated_certificate_is_reused() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let tls_dir = dir.path().join("tls");
+
+        let first = ServerCertificate::load_or_generate(None, None, &tls_dir).unwrap();
+        assert!(first.generated);
+        assert_eq!(first.cert_path, tls_dir.join(GENERATED_CERT_FILE));
+        let key = std::fs::read_to_string(&first.key_path).unwrap();
+        let mode = std::fs::metadata(&first.key_path)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let second = ServerCertificate::load_or_generate(None, None, &tls_dir).unwrap();
+        assert_eq!(second.sha256_fingerprint, first.sha256_fingerprint);
+        assert_eq!(second.certificate, first.certificate);
+        assert_eq!(std::fs::read_to_string(&second.key_path).unwrap(), key);
+    }
+
+    #[test]
+    fn configured_certificate_needs_key() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let generated = ServerCertificate::load_or_generate(None, None, dir.path()).unwrap();
+
+        let configured = ServerCertificate::load_or_generate(
+            Some(generated.cert_path.display().to_string()),
+            Some(generated.key_path.display().to_string()),
+            Path::new("/nonexistent"),
+        )
+        .unwrap();
+        assert!(!configured.generated);
+        assert_eq!(configured.sha256_fingerprint, generated.sha256_fingerprint);
+
+        let result = ServerCertificate::load_or_generate(
+            Some(generated.cert_path.display().to_string()),
+            None,
+            dir.path(),
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            "TLS_CERT and TLS_KEY must be set together"
+        );
+    }
+
+    #[tokio::test]
+    async fn common_name_identifies_the_user() {
+        let subject = vec![
+            (DnType::CommonName, "device-1"),
+            (DnType::OrganizationName, "Example"),
+        ];
+        let name = identify(
+            ClientIdentity::CommonName,
+            subject,
+            vec![dns("device.example")],
+        );
+        assert_eq!(name.await.as_deref(), Some("device-1"));
+
+        let subject = vec![(DnType::OrganizationName, "Example")];
+        let name = identify(ClientIdentity::CommonName, subject, vec![]);
+        assert_eq!(name.await, None);
+        let subject = vec![(DnType::CommonName, "")];
+        let name = identify(ClientIdentity::CommonName, subject, vec![]);
+        assert_eq!(name.await, None);
+    }
+
+    #[tokio::test]
+    async fn email_prefers_the_alternative_names_over_the_subject() {
+        let email_subject = || {
+            vec![
+                (DnType::CommonName, "device-1"),
+                (
+                    DnType::CustomDnType(EMAIL_ADDRESS.to_vec()),
+                    "subject@example.com",
+                ),
+            ]
+        };
+        let alt_names = vec![dns("device.example"), email("alt@example.com")];
+        let name = identify(ClientIdentity::Email, email_subject(), alt_names);
+        assert_eq!(name.await.as_deref(), Some("alt@example.com"));
+
+        let name = identify(
+            ClientIdentity::Email,
+            email_subject(),
+            vec![dns("device.example")],
+        );
+        assert_eq!(name.await.as_deref(), Some("subject@example.com"));
+
+        let subject = vec![(DnType::CommonName, "device-1")];
+        let name = identify(ClientIdentity::Email, subject, vec![]);
+        assert_eq!(name.await, None);
+    }
+
+    #[tokio::test]
+    async fn dns_and_uri_come_from_the_alternative_names() {
+        let subject = || vec![(DnType::CommonName, "device-1")];
+        let alt_names = || {
+            vec![
+                email("device@example.com"),
+                dns("device.example"),
+                uri("spiffe://example/device-1"),
+            ]
+        };
+        let name = identify(ClientIdentity::Dns, subject(), alt_names());
+        assert_eq!(name.await.as_deref(), Some("device.example"));
+        let name = identify(ClientIdentity::Uri, subject(), alt_names());
+        assert_eq!(name.await.as_deref(), Some("spiffe://example/device-1"));
+
+        // The common name is not used as a fallback
+        let name = identify(
+            ClientIdentity::Dns,
+            subject(),
+            vec![email("device@example.com")],
+        );
+        assert_eq!(name.await, None);
+        let name = identify(ClientIdentity::Uri, subject(), vec![dns("device.example")]);
+        assert_eq!(name.await, None);
+    }
+}
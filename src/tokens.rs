@@ -0,0 +1,418 @@
+use crate::error::RaucError;
+use crate::jobs::unix_now;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::{DirBuilder, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+/// Prefix of every token, making them easy to recognize e.g. in leaked files
+const TOKEN_PREFIX: &str = "rwu_";
+/// Seconds between writes of a token's last use, to spare the flash storage
+const LAST_USE_RESOLUTION: u64 = 60;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Permission to use a part of the API, granted to users by their role and to API tokens
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Read the system status, bundles, jobs, history and health checks
+    Read,
+    /// Upload and delete bundles
+    Upload,
+    /// Inspect and install bundles
+    Install,
+    Reboot,
+    /// Mark slots and roll back
+    Slots,
+    /// Manage users, never granted to API tokens
+    Users,
+    /// Manage API tokens, never granted to API tokens
+    Tokens,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 7] = [
+        Scope::Read,
+        Scope::Upload,
+        Scope::Install,
+        Scope::Reboot,
+        Scope::Slots,
+        Scope::Users,
+        Scope::Tokens,
+    ];
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Read => write!(f, "read"),
+            Scope::Upload => write!(f, "upload"),
+            Scope::Install => write!(f, "install"),
+            Scope::Reboot => write!(f, "reboot"),
+            Scope::Slots => write!(f, "slots"),
+            Scope::Users => write!(f, "users"),
+            Scope::Tokens => write!(f, "tokens"),
+        }
+    }
+}
+
+/// API token as returned by the API, without the secret
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_by: String,
+    /// Unix timestamps in seconds
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    /// Recorded with a resolution of a minute
+    pub last_used_at: Option<u64>,
+}
+
+/// Body of a request creating a token
+#[derive(Debug, Deserialize)]
+pub struct NewToken {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// Never expires if not set
+    pub expires_in_days: Option<u64>,
+}
+
+/// Newly created token, the only time the secret is returned
+#[derive(Debug, Serialize)]
+pub struct CreatedToken {
+    pub token: String,
+    #[serde(flatten)]
+    pub info: ApiToken,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredToken {
+    #[serde(flatten)]
+    info: ApiToken,
+    /// SHA-256 of the secret, hex encoded
+    sha256: String,
+}
+
+/// API tokens, persisted as a JSON file
+#[derive(Clone)]
+pub struct TokenStore {
+    path: PathBuf,
+    tokens: Arc<Mutex<Vec<StoredToken>>>,
+}
+
+fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+impl TokenStore {
+    /// Load the tokens stored at `path` by a previous run
+    pub fn open(path: PathBuf) -> Self {
+        let tokens = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|tokens| match serde_json::from_str(&tokens) {
+                Ok(tokens) => Some(tokens),
+                Err(e) => {
+                    eprintln!("Ignoring unreadable API tokens: {}", e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        Self {
+            path,
+            tokens: Arc::new(Mutex::new(tokens)),
+        }
+    }
+
+    fn save(&self, tokens: &[StoredToken]) -> Result<(), RaucError> {
+        let json = serde_json::to_string_pretty(tokens)
+            .map_err(|e| RaucError::Internal(format!("Failed to serialize API tokens: {}", e)))?;
+
+        if let Some(dir) = self.path.parent() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .map_err(|e| {
+                    RaucError::Internal(format!("Failed to create API token directory: {}", e))
+                })?;
+        }
+
+        // Write to a temporary file first so a crash never leaves a truncated file, only
+        // readable by the server as it holds credentials
+        let temporary = self.path.with_extension("tmp");
+        let _ = std::fs::remove_file(&temporary);
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&temporary)
+            .and_then(|mut file| file.write_all(json.as_bytes()))
+            .and_then(|_| std::fs::rename(&temporary, &self.path))
+            .map_err(|e| RaucError::Internal(format!("Failed to write API tokens: {}", e)))
+    }
+
+    pub fn create(&self, request: NewToken, created_by: &str) -> Result<CreatedToken, RaucError> {
+        let name = request.name.trim();
+        if name.is_empty() {
+            return Err(RaucError::BadRequest(
+                "Token name must not be empty".to_string(),
+            ));
+        }
+        if request.scopes.is_empty() {
+            return Err(RaucError::BadRequest(
+                "Token needs at least one scope".to_string(),
+            ));
+        }
+        if let Some(scope) = request
+            .scopes
+            .iter()
+            .find(|scope| matches!(scope, Scope::Users | Scope::Tokens))
+        {
+            return Err(RaucError::BadRequest(format!(
+                "API tokens cannot have the {} scope",
+                scope
+            )));
+        }
+
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        let token = secret
+            .iter()
+            .fold(TOKEN_PREFIX.to_string(), |mut token, byte| {
+                token.push_str(&format!("{:02x}", byte));
+                token
+            });
+
+        let now = unix_now();
+        let mut scopes = Vec::new();
+        for scope in request.scopes {
+            if !scopes.contains(&scope) {
+                scopes.push(scope);
+            }
+        }
+        let info = ApiToken {
+            id: Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            scopes,
+            created_by: created_by.to_string(),
+            created_at: now,
+            expires_at: request
+                .expires_in_days
+                .map(|days| now + days * SECONDS_PER_DAY),
+            last_used_at: None,
+        };
+
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.push(StoredToken {
+            info: info.clone(),
+            sha256: hash_token(&token),
+        });
+        self.save(&tokens)?;
+
+        Ok(CreatedToken { token, info })
+    }
+
+    pub fn list(&self) -> Vec<ApiToken> {
+        self.tokens
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|token| token.info.clone())
+            .collect()
+    }
+
+    pub fn revoke(&self, id: &str) -> Result<ApiToken, RaucError> {
+        let mut tokens = self.tokens.lock().unwrap();
+        let position = tokens
+            .iter()
+            .position(|token| token.info.id == id)
+            .ok_or_else(|| RaucError::NotFound(format!("API token {} not found", id)))?;
+
+        let token = tokens.remove(position);
+        self.save(&tokens)?;
+        Ok(token.info)
+    }
+
+    /// Find the token with the given secret if it has not expired, recording its use
+    pub fn authenticate(&self, token: &str) -> Option<ApiToken> {
+        if !token.starts_with(TOKEN_PREFIX) {
+            return None;
+        }
+
+        let sha256 = hash_token(token);
+        let now = unix_now();
+        let mut tokens = self.tokens.lock().unwrap();
+        let stored = tokens.iter_mut().find(|stored| stored.sha256 == sha256)?;
+        if stored
+            .info
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= now)
+        {
+            return None;
+        }
+
+        let outdated = stored
+            .info
+            .last_used_at
+            .is_none_or(|last_used_at| last_used_at + LAST_USE_RESOLUTION <= now);
+        let info = if outdated {
+            stored.info.last_used_at = Some(now);
+            let info = stored.info.clone();
+            if let Err(e) = self.save(&tokens) {
+                eprintln!("{}", e);
+            }
+            info
+        } else {
+            stored.info.clone()
+        };
+
+        Some(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    fn new_token(scopes: &[Scope], expires_in_days: Option<u64>) -> NewToken {
+        NewToken {
+            name: "ci".to_string(),
+            scopes: scopes.to_vec(),
+            expires_in_days,
+        }
+    }
+
+    fn store(dir: &TempDir) -> TokenStore {
+        TokenStore::open(dir.path().join("api-tokens.json"))
+    }
+
+    /// Change the stored token as if time had passed
+    fn set(store: &TokenStore, id: &str, apply: impl FnOnce(&mut ApiToken)) {
+        let mut tokens = store.tokens.lock().unwrap();
+        let stored = tokens.iter_mut().find(|token| token.info.id == id).unwrap();
+        apply(&mut stored.info);
+        store.save(&tokens).unwrap();
+    }
+
+    #[test]
+    fn only_the_hash_of_the_secret_is_stored() {
+        let dir = TempDir::new().unwrap();
+        let created = store(&dir)
+            .create(new_token(&[Scope::Read], None), "admin")
+            .unwrap();
+        assert!(created.token.starts_with(TOKEN_PREFIX));
+        assert_eq!(created.token.len(), TOKEN_PREFIX.len() + 64);
+
+        let path = dir.path().join("api-tokens.json");
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let file = std::fs::read_to_string(&path).unwrap();
+        assert!(!file.contains(&created.token[TOKEN_PREFIX.len()..]));
+        assert!(file.contains(&hash_token(&created.token)));
+
+        // The secret still authenticates after a restart
+        let token = store(&dir).authenticate(&created.token).unwrap();
+        assert_eq!(token.id, created.info.id);
+        assert_eq!(token.created_by, "admin");
+        assert!(store(&dir).authenticate("rwu_0123").is_none());
+        assert!(store(&dir)
+            .authenticate(&created.token[TOKEN_PREFIX.len()..])
+            .is_none());
+    }
+
+    #[test]
+    fn expired_and_revoked_tokens_are_rejected() {
+        let dir = TempDir::new().unwrap();
+        let store = store(&dir);
+        let created = store
+            .create(new_token(&[Scope::Read], Some(30)), "admin")
+            .unwrap();
+        let expires_at = created.info.expires_at.unwrap();
+        assert_eq!(expires_at, created.info.created_at + 30 * SECONDS_PER_DAY);
+        assert!(store.authenticate(&created.token).is_some());
+
+        set(&store, &created.info.id, |token| {
+            token.expires_at = Some(unix_now())
+        });
+        assert!(store.authenticate(&created.token).is_none());
+
+        let created = store
+            .create(new_token(&[Scope::Read], None), "admin")
+            .unwrap();
+        assert!(created.info.expires_at.is_none());
+        store.revoke(&created.info.id).unwrap();
+        assert!(store.authenticate(&created.token).is_none());
+        assert!(matches!(
+            store.revoke(&created.info.id),
+            Err(RaucError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn tokens_carry_their_scopes() {
+        let dir = TempDir::new().unwrap();
+        let store = store(&dir);
+        let scopes = [Scope::Read, Scope::Install, Scope::Read];
+        let created = store.create(new_token(&scopes, None), "admin").unwrap();
+
+        let token = store.authenticate(&created.token).unwrap();
+        assert_eq!(token.scopes, [Scope::Read, Scope::Install]);
+
+        for scopes in [&[][..], &[Scope::Read, Scope::Users], &[Scope::Tokens]] {
+            let error = store.create(new_token(scopes, None), "admin").unwrap_err();
+            assert!(matches!(error, RaucError::BadRequest(_)), "{:?}", scopes);
+        }
+        let mut unnamed = new_token(&[Scope::Read], None);
+        unnamed.name = "  ".to_string();
+        assert!(store.create(unnamed, "admin").is_err());
+        assert_eq!(store.list().len(), 1);
+    }
+
+    #[test]
+    fn last_use_is_recorded_once_a_minute() {
+        let dir = TempDir::new().unwrap();
+        let store = store(&dir);
+        let created = store
+            .create(new_token(&[Scope::Read], None), "admin")
+            .unwrap();
+        let last_used_at = |store: &TokenStore| store.list()[0].last_used_at;
+        let persisted = || last_used_at(&TokenStore::open(dir.path().join("api-tokens.json")));
+
+        let now = unix_now();
+        store.authenticate(&created.token).unwrap();
+        assert!(last_used_at(&store).unwrap() >= now);
+        assert_eq!(persisted(), last_used_at(&store));
+
+        // Within the resolution the earlier use is kept, without writing the file
+        let recent = now - LAST_USE_RESOLUTION + 5;
+        set(&store, &created.info.id, |token| {
+            token.last_used_at = Some(recent)
+        });
+        std::fs::remove_file(dir.path().join("api-tokens.json")).unwrap();
+        store.authenticate(&created.token).unwrap();
+        assert_eq!(last_used_at(&store), Some(recent));
+        assert!(!dir.path().join("api-tokens.json").exists());
+
+        let outdated = now - LAST_USE_RESOLUTION;
+        set(&store, &created.info.id, |token| {
+            token.last_used_at = Some(outdated)
+        });
+        store.authenticate(&created.token).unwrap();
+        assert!(last_used_at(&store).unwrap() >= now);
+        assert_eq!(persisted(), last_used_at(&store));
+    }
+}
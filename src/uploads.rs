@@ -0,0 +1,456 @@
+use crate::bundles::sha256_file;
+use crate::error::RaucError;
+use crate::jobs::unix_now;
+use rocket::data::{Data, ToByteUnit};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use uuid::Uuid;
+
+/// Largest number of bytes accepted from a single chunk request, in MiB
+const MAX_CHUNK_MIB: u64 = 64;
+/// Sessions without a chunk for this long are discarded, in seconds
+const SESSION_TIMEOUT: u64 = 24 * 60 * 60;
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Request to start a chunked upload
+#[derive(Debug, Deserialize)]
+pub struct NewUpload {
+    pub filename: String,
+    /// Total size of the bundle in bytes
+    pub size: u64,
+    /// Expected SHA-256 of the whole bundle, hex encoded
+    pub sha256: String,
+}
+
+/// State of a chunked upload as returned by the API
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadSession {
+    pub id: String,
+    pub filename: String,
+    pub size: u64,
+    pub sha256: String,
+    /// Number of bytes received from the start of the file, the offset of the next chunk
+    pub received: u64,
+    /// Unix timestamps in seconds
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+struct Upload {
+    session: Mutex<UploadSession>,
+    /// Serializes writes to the part file
+    writing: tokio::sync::Mutex<()>,
+}
+
+impl Upload {
+    fn session(&self) -> UploadSession {
+        self.session.lock().unwrap().clone()
+    }
+}
+
+/// Keeps track of chunked uploads, assembling each bundle in a part file
+pub struct UploadManager {
+    dir: PathBuf,
+    /// Largest bundle accepted, in bytes
+    max_size: u64,
+    uploads: Mutex<HashMap<String, Arc<Upload>>>,
+}
+
+fn io_error(context: &str, error: std::io::Error) -> RaucError {
+    RaucError::Internal(format!("{}: {}", context, error))
+}
+
+/// Copy `reader` to `file`, returning the bytes written along with any read or write error
+async fn copy_chunk(
+    reader: &mut (impl AsyncRead + Unpin),
+    file: &mut File,
+) -> (u64, Option<RaucError>) {
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut written = 0;
+
+    loop {
+        let read = match reader.read(&mut buffer).await {
+            Ok(0) => return (written, None),
+            Ok(read) => read,
+            Err(e) => return (written, Some(io_error("Failed to receive chunk", e))),
+        };
+
+        if let Err(e) = file.write_all(&buffer[..read]).await {
+            return (written, Some(io_error("Failed to write chunk", e)));
+        }
+        written += read as u64;
+    }
+}
+
+impl UploadManager {
+    /// Sessions only live in memory, so part files left by a previous run are deleted
+    pub fn new(dir: PathBuf, max_size: u64) -> Self {
+        if let Ok(entries) = std::fs::read_dir(&dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path
+                    .extension()
+                    .is_some_and(|extension| extension == "part")
+                {
+                    if let Err(e) = std::fs::remove_file(&path) {
+                        eprintln!("Failed to delete stale upload {}: {}", path.display(), e);
+                    }
+                }
+            }
+        }
+
+        Self {
+            dir,
+            max_size,
+            uploads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn part_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.part", id))
+    }
+
+    fn upload(&self, id: &str) -> Result<Arc<Upload>, RaucError> {
+        self.uploads
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .ok_or_else(|| RaucError::NotFound(format!("Upload {} not found", id)))
+    }
+
+    /// Forget an upload and delete its part file
+    async fn discard(&self, id: &str) {
+        self.uploads.lock().unwrap().remove(id);
+        let _ = fs::remove_file(self.part_path(id)).await;
+    }
+
+    /// Discard uploads that have been abandoned
+    async fn prune(&self) {
+        let deadline = unix_now().saturating_sub(SESSION_TIMEOUT);
+        let expired: Vec<String> = self
+            .uploads
+            .lock()
+            .unwrap()
+            .values()
+            .map(|upload| upload.session())
+            .filter(|session| session.updated_at < deadline)
+            .map(|session| session.id)
+            .collect();
+
+        for id in expired {
+            self.discard(&id).await;
+        }
+    }
+
+    pub async fn create(&self, request: NewUpload) -> Result<UploadSession, RaucError> {
+        let sha256 = request.sha256.to_lowercase();
+        if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(RaucError::BadRequest(
+                "sha256 must be 64 hexadecimal characters".to_string(),
+            ));
+        }
+        if request.size == 0 {
+            return Err(RaucError::BadRequest(
+                "Bundle must not be empty".to_string(),
+            ));
+        }
+        if request.size > self.max_size {
+            return Err(RaucError::BadRequest(format!(
+                "Bundle of {} bytes exceeds the upload limit of {} bytes",
+                request.size, self.max_size
+            )));
+        }
+
+        self.prune().await;
+
+        fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|e| io_error("Failed to create upload directory", e))?;
+
+        let now = unix_now();
+        let session = UploadSession {
+            id: Uuid::new_v4().to_string(),
+            filename: request.filename,
+            size: request.size,
+            sha256,
+            received: 0,
+            created_at: now,
+            updated_at: now,
+        };
+
+        File::create(self.part_path(&session.id))
+            .await
+            .map_err(|e| io_error("Failed to create upload file", e))?;
+
+        self.uploads.lock().unwrap().insert(
+            session.id.clone(),
+            Arc::new(Upload {
+                session: Mutex::new(session.clone()),
+                writing: tokio::sync::Mutex::new(()),
+            }),
+        );
+
+        Ok(session)
+    }
+
+    pub fn get(&self, id: &str) -> Result<UploadSession, RaucError> {
+        Ok(self.upload(id)?.session())
+    }
+
+    /// Write a chunk starting at `offset`, which may overlap bytes already received
+    ///
+    /// Bytes received before a dropped connection are kept, so the client can resume at
+    /// `received`. Data past the announced size or the chunk limit is ignored.
+    pub async fn write_chunk(
+        &self,
+        id: &str,
+        offset: u64,
+        data: Data<'_>,
+    ) -> Result<UploadSession, RaucError> {
+        self.write_from(id, offset, |limit| data.open(limit.bytes()))
+            .await
+    }
+
+    /// Write the reader returned by `open` for the number of bytes accepted at `offset`
+    async fn write_from<R: AsyncRead + Unpin>(
+        &self,
+        id: &str,
+        offset: u64,
+        open: impl FnOnce(u64) -> R,
+    ) -> Result<UploadSession, RaucError> {
+        let upload = self.upload(id)?;
+        let _writing = upload.writing.lock().await;
+        let session = upload.session();
+
+        if offset > session.received {
+            return Err(RaucError::BadRequest(format!(
+                "Chunk offset {} is past the {} bytes received so far",
+                offset, session.received
+            )));
+        }
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(self.part_path(id))
+            .await
+            .map_err(|e| io_error("Failed to open upload file", e))?;
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(|e| io_error("Failed to seek in upload file", e))?;
+
+        let limit = (session.size - offset).min(MAX_CHUNK_MIB * 1024 * 1024);
+        let mut stream = open(limit);
+        let (written, error) = copy_chunk(&mut stream, &mut file).await;
+        file.flush()
+            .await
+            .map_err(|e| io_error("Failed to write chunk", e))?;
+
+        let session = {
+            let mut session = upload.session.lock().unwrap();
+            session.received = session.received.max(offset + written);
+            session.updated_at = unix_now();
+            session.clone()
+        };
+
+        match error {
+            Some(error) => Err(error),
+            None => Ok(session),
+        }
+    }
+
+    /// Verify the assembled file and move it to `destination`
+    ///
+    /// A checksum mismatch discards the upload, as the corrupted bytes cannot be located.
+    pub async fn finish(&self, id: &str, destination: &Path) -> Result<UploadSession, RaucError> {
+        let upload = self.upload(id)?;
+        let _writing = upload.writing.lock().await;
+        let session = upload.session();
+
+        if session.received < session.size {
+            return Err(RaucError::BadRequest(format!(
+                "Upload incomplete, received {} of {} bytes",
+                session.received, session.size
+            )));
+        }
+
+        let part_path = self.part_path(id);
+        let actual = sha256_file(&part_path).await?;
+        if actual != session.sha256 {
+            self.discard(id).await;
+            return Err(RaucError::ChecksumMismatch {
+                expected: session.sha256,
+                actual,
+            });
+        }
+
+        fs::rename(&part_path, destination)
+            .await
+            .map_err(|e| io_error("Failed to move uploaded file", e))?;
+        self.uploads.lock().unwrap().remove(id);
+
+        Ok(session)
+    }
+
+    pub async fn cancel(&self, id: &str) -> Result<(), RaucError> {
+        let upload = self.upload(id)?;
+        let _writing = upload.writing.lock().await;
+        self.discard(id).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const MAX_SIZE: u64 = 1024;
+    const BUNDLE: &[u8] = b"hsqs bundle contents";
+    const BUNDLE_SHA256: &str = "5b7d1bc72ddb74f4dd5e6ab74cf1973b372938740ddd34b05dbfab11237db80d";
+
+    fn new_upload(size: u64) -> NewUpload {
+        NewUpload {
+            filename: "update.raucb".to_string(),
+            size,
+            sha256: "aa0b961f6f5a494f1cd97be12aa647be6fefd17c1d818598b5e138109454eda8".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn create_rejects_empty_and_oversized_bundles() {
+        let dir = TempDir::new().unwrap();
+        let uploads = UploadManager::new(dir.path().to_path_buf(), MAX_SIZE);
+
+        for size in [0, MAX_SIZE + 1, u64::MAX] {
+            let error = uploads.create(new_upload(size)).await.unwrap_err();
+            assert!(matches!(error, RaucError::BadRequest(_)), "{:?}", error);
+        }
+        assert!(std::fs::read_dir(dir.path()).unwrap().next().is_none());
+
+        let session = uploads.create(new_upload(MAX_SIZE)).await.unwrap();
+        assert_eq!(session.size, MAX_SIZE);
+        assert!(dir.path().join(format!("{}.part", session.id)).exists());
+    }
+
+    #[tokio::test]
+    async fn new_deletes_part_files_of_a_previous_run() {
+        let dir = TempDir::new().unwrap();
+        let part = {
+            let uploads = UploadManager::new(dir.path().to_path_buf(), MAX_SIZE);
+            let session = uploads.create(new_upload(MAX_SIZE)).await.unwrap();
+            dir.path().join(format!("{}.part", session.id))
+        };
+        let bundle = dir.path().join("bundles");
+        std::fs::create_dir(&bundle).unwrap();
+        assert!(part.exists());
+
+        UploadManager::new(dir.path().to_path_buf(), MAX_SIZE);
+        assert!(!part.exists());
+        assert!(bundle.exists());
+    }
+
+    /// Write `chunk` at `offset`, limited like a chunk request
+    async fn write(
+        uploads: &UploadManager,
+        id: &str,
+        offset: u64,
+        chunk: &'static [u8],
+    ) -> Result<UploadSession, RaucError> {
+        uploads
+            .write_from(id, offset, |limit| chunk.take(limit))
+            .await
+    }
+
+    async fn create_bundle_upload(uploads: &UploadManager) -> UploadSession {
+        let mut request = new_upload(BUNDLE.len() as u64);
+        request.sha256 = BUNDLE_SHA256.to_uppercase();
+        uploads.create(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn chunks_resume_at_the_received_offset() {
+        let dir = TempDir::new().unwrap();
+        let uploads = UploadManager::new(dir.path().to_path_buf(), MAX_SIZE);
+        let session = create_bundle_upload(&uploads).await;
+
+        let session = write(&uploads, &session.id, 0, &BUNDLE[..8]).await.unwrap();
+        assert_eq!(session.received, 8);
+        // A retried chunk overlapping received bytes is accepted
+        let session = write(&uploads, &session.id, 4, &BUNDLE[4..12])
+            .await
+            .unwrap();
+        assert_eq!(session.received, 12);
+        let session = write(&uploads, &session.id, 12, &BUNDLE[12..])
+            .await
+            .unwrap();
+        assert_eq!(session.received, BUNDLE.len() as u64);
+        assert_eq!(uploads.get(&session.id).unwrap().received, session.received);
+
+        let destination = dir.path().join("update.raucb");
+        uploads.finish(&session.id, &destination).await.unwrap();
+        assert_eq!(std::fs::read(&destination).unwrap(), BUNDLE);
+        assert!(!dir.path().join(format!("{}.part", session.id)).exists());
+        assert!(uploads.get(&session.id).is_err());
+    }
+
+    #[tokio::test]
+    async fn chunks_past_the_received_bytes_are_rejected() {
+        let dir = TempDir::new().unwrap();
+        let uploads = UploadManager::new(dir.path().to_path_buf(), MAX_SIZE);
+        let session = create_bundle_upload(&uploads).await;
+        write(&uploads, &session.id, 0, &BUNDLE[..8]).await.unwrap();
+
+        let error = write(&uploads, &session.id, 9, &BUNDLE[9..])
+            .await
+            .unwrap_err();
+        assert!(matches!(error, RaucError::BadRequest(_)), "{:?}", error);
+        assert_eq!(uploads.get(&session.id).unwrap().received, 8);
+
+        let error = uploads
+            .finish(&session.id, &dir.path().join("update.raucb"))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, RaucError::BadRequest(_)), "{:?}", error);
+    }
+
+    #[tokio::test]
+    async fn data_past_the_announced_size_is_ignored() {
+        let dir = TempDir::new().unwrap();
+        let uploads = UploadManager::new(dir.path().to_path_buf(), MAX_SIZE);
+        let session = create_bundle_upload(&uploads).await;
+
+        let chunk = b"hsqs bundle contents and trailing garbage";
+        let session = write(&uploads, &session.id, 0, chunk).await.unwrap();
+        assert_eq!(session.received, BUNDLE.len() as u64);
+
+        let destination = dir.path().join("update.raucb");
+        uploads.finish(&session.id, &destination).await.unwrap();
+        assert_eq!(std::fs::read(&destination).unwrap(), BUNDLE);
+    }
+
+    #[tokio::test]
+    async fn checksum_mismatch_discards_the_upload() {
+        let dir = TempDir::new().unwrap();
+        let uploads = UploadManager::new(dir.path().to_path_buf(), MAX_SIZE);
+        let session = create_bundle_upload(&uploads).await;
+        write(&uploads, &session.id, 0, b"hsqs bundle CONTENTS")
+            .await
+            .unwrap();
+
+        let destination = dir.path().join("update.raucb");
+        let error = uploads.finish(&session.id, &destination).await.unwrap_err();
+        match error {
+            RaucError::ChecksumMismatch { expected, .. } => assert_eq!(expected, BUNDLE_SHA256),
+            error => panic!("{:?}", error),
+        }
+        assert!(!destination.exists());
+        assert!(!dir.path().join(format!("{}.part", session.id)).exists());
+        assert!(uploads.get(&session.id).is_err());
+    }
+}
@@ -0,0 +1,299 @@
+use crate::error::RaucError;
+use crate::jobs::unix_now;
+use crate::tokens::Scope;
+use serde::{Deserialize, Serialize};
+use std::fs::{DirBuilder, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// Minimum length of passwords set through the API
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Read the status, bundles and history
+    Viewer,
+    /// Also upload and install bundles and reboot
+    Operator,
+    /// Also manage slots, users and API tokens
+    Admin,
+}
+
+impl Role {
+    pub fn permissions(&self) -> Vec<Scope> {
+        match self {
+            Role::Viewer => vec![Scope::Read],
+            Role::Operator => vec![Scope::Read, Scope::Upload, Scope::Install, Scope::Reboot],
+            Role::Admin => Scope::ALL.to_vec(),
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "viewer" => Ok(Role::Viewer),
+            "operator" => Ok(Role::Operator),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!(
+                "Unknown role '{}' (expected viewer, operator or admin)",
+                other
+            )),
+        }
+    }
+}
+
+/// User as returned by the API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserInfo {
+    pub username: String,
+    pub role: Role,
+    pub created_by: Option<String>,
+    /// Unix timestamp in seconds
+    pub created_at: Option<u64>,
+    /// The administrator configured through `AUTH_USERNAME` and `AUTH_PASSWORD_HASH`, which
+    /// cannot be changed through the API
+    #[serde(default)]
+    pub configured: bool,
+}
+
+/// Body of a request creating a user
+#[derive(Debug, Deserialize)]
+pub struct NewUser {
+    pub username: String,
+    pub password: String,
+    pub role: Role,
+}
+
+/// Body of a request changing a user, fields that are not set are kept
+#[derive(Debug, Deserialize)]
+pub struct UserUpdate {
+    pub password: Option<String>,
+    pub role: Option<Role>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredUser {
+    #[serde(flatten)]
+    info: UserInfo,
+    /// Argon2 hash in PHC string format
+    password_hash: String,
+}
+
+/// Users added through the API, persisted as a JSON file
+#[derive(Clone)]
+pub struct UserStore {
+    path: PathBuf,
+    users: Arc<Mutex<Vec<StoredUser>>>,
+}
+
+/// Check a password set through the API
+pub fn validate_password(password: &str) -> Result<(), RaucError> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(RaucError::BadRequest(format!(
+            "Password must have at least {} characters",
+            MIN_PASSWORD_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+impl UserStore {
+    /// Load the users stored at `path` by a previous run
+    pub fn open(path: PathBuf) -> Self {
+        let users = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|users| match serde_json::from_str(&users) {
+                Ok(users) => Some(users),
+                Err(e) => {
+                    eprintln!("Ignoring unreadable users: {}", e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        Self {
+            path,
+            users: Arc::new(Mutex::new(users)),
+        }
+    }
+
+    fn save(&self, users: &[StoredUser]) -> Result<(), RaucError> {
+        let json = serde_json::to_string_pretty(users)
+            .map_err(|e| RaucError::Internal(format!("Failed to serialize users: {}", e)))?;
+
+        if let Some(dir) = self.path.parent() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .map_err(|e| {
+                    RaucError::Internal(format!("Failed to create user directory: {}", e))
+                })?;
+        }
+
+        // Write to a temporary file first so a crash never leaves a truncated file, only
+        // readable by the server as it holds credentials
+        let temporary = self.path.with_extension("tmp");
+        let _ = std::fs::remove_file(&temporary);
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&temporary)
+            .and_then(|mut file| file.write_all(json.as_bytes()))
+            .and_then(|_| std::fs::rename(&temporary, &self.path))
+            .map_err(|e| RaucError::Internal(format!("Failed to write users: {}", e)))
+    }
+
+    pub fn list(&self) -> Vec<UserInfo> {
+        self.users
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|user| user.info.clone())
+            .collect()
+    }
+
+    /// Role and password hash of a user
+    pub fn credentials(&self, username: &str) -> Option<(Role, String)> {
+        self.users
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|user| user.info.username == username)
+            .map(|user| (user.info.role, user.password_hash.clone()))
+    }
+
+    pub fn create(
+        &self,
+        username: &str,
+        role: Role,
+        password_hash: String,
+        created_by: &str,
+    ) -> Result<UserInfo, RaucError> {
+        let mut users = self.users.lock().unwrap();
+        if users.iter().any(|user| user.info.username == username) {
+            return Err(RaucError::BadRequest(format!(
+                "User {} already exists",
+                username
+            )));
+        }
+
+        let info = UserInfo {
+            username: username.to_string(),
+            role,
+            created_by: Some(created_by.to_string()),
+            created_at: Some(unix_now()),
+            configured: false,
+        };
+        users.push(StoredUser {
+            info: info.clone(),
+            password_hash,
+        });
+        self.save(&users)?;
+        Ok(info)
+    }
+
+    pub fn update(
+        &self,
+        username: &str,
+        role: Option<Role>,
+        password_hash: Option<String>,
+    ) -> Result<UserInfo, RaucError> {
+        let mut users = self.users.lock().unwrap();
+        let user = users
+            .iter_mut()
+            .find(|user| user.info.username == username)
+            .ok_or_else(|| RaucError::NotFound(format!("User {} not found", username)))?;
+
+        if let Some(role) = role {
+            user.info.role = role;
+        }
+        if let Some(password_hash) = password_hash {
+            user.password_hash = password_hash;
+        }
+        let info = user.info.clone();
+        self.save(&users)?;
+        Ok(info)
+    }
+
+    pub fn delete(&self, username: &str) -> Result<UserInfo, RaucError> {
+        let mut users = self.users.lock().unwrap();
+        let position = users
+            .iter()
+            .position(|user| user.info.username == username)
+            .ok_or_else(|| RaucError::NotFound(format!("User {} not found", username)))?;
+
+        let user = users.remove(position);
+        self.save(&users)?;
+        Ok(user.info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::User;
+
+    #[test]
+    fn roles_grant_their_permissions() {
+        use Scope::{Install, Read, Reboot, Slots, Tokens, Upload, Users};
+
+        // Scopes in the order of `Scope::ALL`
+        let table = [
+            (
+                Role::Viewer,
+                vec![Read],
+                [Upload, Install, Reboot, Slots, Users, Tokens].to_vec(),
+            ),
+            (
+                Role::Operator,
+                [Read, Upload, Install, Reboot].to_vec(),
+                [Slots, Users, Tokens].to_vec(),
+            ),
+            (Role::Admin, Scope::ALL.to_vec(), Vec::new()),
+        ];
+
+        for (role, granted, denied) in table {
+            assert_eq!(role.permissions(), granted, "{:?}", role);
+
+            let user = User {
+                name: "user".to_string(),
+                session: None,
+                role: Some(role),
+                permissions: role.permissions(),
+            };
+            for scope in granted {
+                assert!(user.require(scope).is_ok(), "{:?} {}", role, scope);
+            }
+            for scope in denied {
+                let error = user.require(scope).unwrap_err();
+                assert!(
+                    matches!(error, RaucError::Forbidden(_)),
+                    "{:?} {}",
+                    role,
+                    scope
+                );
+                assert_eq!(
+                    error.to_string(),
+                    format!("The {} permission is required", scope)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn parses_roles() {
+        assert_eq!("viewer".parse::<Role>(), Ok(Role::Viewer));
+        assert_eq!("operator".parse::<Role>(), Ok(Role::Operator));
+        assert_eq!("admin".parse::<Role>(), Ok(Role::Admin));
+        assert!("root".parse::<Role>().is_err());
+    }
+}
@@ -0,0 +1,314 @@
+use crate::backend::StreamingOptions;
+use crate::error::RaucError;
+use crate::rauc::{RaucBundleInfo, RaucClient};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckName {
+    /// The bundle can be read by rauc
+    Format,
+    /// The bundle signature verifies against the system keyring
+    Signature,
+    /// The bundle's compatible matches the system
+    Compatible,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Passed,
+    Failed,
+    /// Not run because an earlier check failed
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidationCheck {
+    pub name: CheckName,
+    pub status: CheckStatus,
+    pub message: String,
+    /// Raw stderr output of rauc for failed checks
+    pub stderr: Option<String>,
+}
+
+/// Verdict on an uploaded bundle
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleValidation {
+    pub valid: bool,
+    pub checks: Vec<ValidationCheck>,
+}
+
+impl BundleValidation {
+    fn new(checks: Vec<ValidationCheck>) -> Self {
+        Self {
+            valid: checks
+                .iter()
+                .all(|check| check.status == CheckStatus::Passed),
+            checks,
+        }
+    }
+
+    /// First failed check, which caused the bundle to be rejected
+    pub fn failure(&self) -> Option<&ValidationCheck> {
+        self.checks
+            .iter()
+            .find(|check| check.status == CheckStatus::Failed)
+    }
+}
+
+fn check(name: CheckName, status: CheckStatus, message: impl Into<String>) -> ValidationCheck {
+    ValidationCheck {
+        name,
+        status,
+        message: message.into(),
+        stderr: None,
+    }
+}
+
+/// Checks for a bundle rauc refused to inspect, based on the classified error
+fn inspection_failed(error: &RaucError) -> Vec<ValidationCheck> {
+    let failed = |name| ValidationCheck {
+        name,
+        status: CheckStatus::Failed,
+        message: error.to_string(),
+        stderr: error.stderr().map(str::to_string),
+    };
+    let skipped = |name| check(name, CheckStatus::Skipped, "Not checked");
+
+    match error {
+        RaucError::InvalidSignature { .. } => vec![
+            check(CheckName::Format, CheckStatus::Passed, "Bundle is readable"),
+            failed(CheckName::Signature),
+            skipped(CheckName::Compatible),
+        ],
+        // rauc names the mismatch without saying whether it verified the signature first
+        RaucError::IncompatibleBundle { .. } => vec![
+            check(CheckName::Format, CheckStatus::Passed, "Bundle is readable"),
+            skipped(CheckName::Signature),
+            failed(CheckName::Compatible),
+        ],
+        _ => vec![
+            failed(CheckName::Format),
+            skipped(CheckName::Signature),
+            skipped(CheckName::Compatible),
+        ],
+    }
+}
+
+/// Inspect a bundle with `rauc info` and check that it can be installed on the system
+///
+/// Fails with `BundleRejected` if rauc found the bundle invalid. Other errors, including rauc
+/// failing for a reason that was not recognized, mean the bundle could not be checked.
+pub async fn validate_bundle(
+    client: &RaucClient,
+    path: &str,
+) -> Result<(RaucBundleInfo, BundleValidation), RaucError> {
+    let info = match client
+        .get_bundle_info(path, &StreamingOptions::default())
+        .await
+    {
+        Ok(info) => info,
+        Err(
+            error @ (RaucError::InvalidSignature { .. }
+            | RaucError::IncompatibleBundle { .. }
+            | RaucError::InvalidBundle { .. }),
+        ) => {
+            let validation = BundleValidation::new(inspection_failed(&error));
+            return Err(RaucError::BundleRejected(validation));
+        }
+        Err(error) => return Err(error),
+    };
+
+    let status = client.get_status().await?;
+    let compatible = if info.compatible == status.compatible {
+        check(
+            CheckName::Compatible,
+            CheckStatus::Passed,
+            format!("Bundle is compatible with '{}'", status.compatible),
+        )
+    } else {
+        check(
+            CheckName::Compatible,
+            CheckStatus::Failed,
+            format!(
+                "Bundle compatible '{}' does not match system compatible '{}'",
+                info.compatible, status.compatible
+            ),
+        )
+    };
+
+    let validation = BundleValidation::new(vec![
+        check(
+            CheckName::Format,
+            CheckStatus::Passed,
+            match &info.format {
+                Some(format) => format!("Bundle is readable ({} format)", format),
+                None => "Bundle is readable".to_string(),
+            },
+        ),
+        check(
+            CheckName::Signature,
+            CheckStatus::Passed,
+            "Signature is valid",
+        ),
+        compatible,
+    ]);
+
+    if validation.valid {
+        Ok((info, validation))
+    } else {
+        Err(RaucError::BundleRejected(validation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{InstallStream, MockBackend, RaucBackend};
+    use crate::rauc::{RaucStatus, SlotMark};
+    use async_trait::async_trait;
+    use tempfile::TempDir;
+
+    fn statuses(checks: &[ValidationCheck]) -> Vec<(CheckName, CheckStatus)> {
+        checks
+            .iter()
+            .map(|check| (check.name, check.status))
+            .collect()
+    }
+
+    #[test]
+    fn inspection_failures_name_the_failed_check() {
+        use CheckName::{Compatible, Format, Signature};
+        use CheckStatus::{Failed, Passed, Skipped};
+
+        let stderr = "rauc: error message".to_string();
+        let cases = [
+            (
+                RaucError::InvalidSignature {
+                    stderr: stderr.clone(),
+                },
+                [(Format, Passed), (Signature, Failed), (Compatible, Skipped)],
+            ),
+            (
+                RaucError::IncompatibleBundle {
+                    stderr: stderr.clone(),
+                },
+                [(Format, Passed), (Signature, Skipped), (Compatible, Failed)],
+            ),
+            (
+                RaucError::InvalidBundle {
+                    stderr: stderr.clone(),
+                },
+                [
+                    (Format, Failed),
+                    (Signature, Skipped),
+                    (Compatible, Skipped),
+                ],
+            ),
+        ];
+
+        for (error, expected) in cases {
+            let validation = BundleValidation::new(inspection_failed(&error));
+            assert_eq!(statuses(&validation.checks), expected, "{:?}", error);
+            assert!(!validation.valid);
+
+            let failure = validation.failure().unwrap();
+            assert_eq!(failure.message, error.to_string());
+            assert_eq!(failure.stderr.as_deref(), Some(stderr.as_str()));
+        }
+    }
+
+    /// Backend whose `rauc info` fails with the given stderr
+    struct FailingInfo(&'static str);
+
+    #[async_trait]
+    impl RaucBackend for FailingInfo {
+        async fn get_status(&self) -> Result<RaucStatus, RaucError> {
+            MockBackend::new().get_status().await
+        }
+
+        async fn get_bundle_info(
+            &self,
+            _bundle: &str,
+            _streaming: &StreamingOptions,
+        ) -> Result<RaucBundleInfo, RaucError> {
+            Err(RaucError::from_rauc_stderr(self.0))
+        }
+
+        async fn install_bundle(
+            &self,
+            _bundle: &str,
+            _streaming: &StreamingOptions,
+        ) -> Result<InstallStream, RaucError> {
+            unimplemented!()
+        }
+
+        async fn mark_slot(&self, _slot: &str, _mark: SlotMark) -> Result<String, RaucError> {
+            unimplemented!()
+        }
+
+        async fn reboot(&self) -> Result<String, RaucError> {
+            unimplemented!()
+        }
+    }
+
+    #[tokio::test]
+    async fn validate_bundle_passes_readable_compatible_bundles() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("update.raucb");
+        std::fs::write(&path, b"hsqs").unwrap();
+
+        let client = RaucClient::new(MockBackend::new());
+        let (info, validation) = validate_bundle(&client, path.to_str().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(info.version, "1.0.0");
+        assert!(validation.valid);
+        assert_eq!(validation.checks.len(), 3);
+        assert!(validation.failure().is_none());
+    }
+
+    #[tokio::test]
+    async fn validate_bundle_rejects_only_invalid_bundles() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("update.raucb");
+        std::fs::write(&path, b"").unwrap();
+
+        // The mock reports empty files as invalid bundles
+        let client = RaucClient::new(MockBackend::new());
+        match validate_bundle(&client, path.to_str().unwrap()).await {
+            Err(RaucError::BundleRejected(validation)) => {
+                assert_eq!(validation.failure().unwrap().name, CheckName::Format)
+            }
+            result => panic!("{:?}", result),
+        }
+
+        let client = RaucClient::new(FailingInfo(
+            "Failed to verify bundle: signature verification failed: bad signature",
+        ));
+        match validate_bundle(&client, "/data/update.raucb").await {
+            Err(RaucError::BundleRejected(validation)) => {
+                assert_eq!(validation.failure().unwrap().name, CheckName::Signature)
+            }
+            result => panic!("{:?}", result),
+        }
+
+        // An unrecognized rauc failure says nothing about the bundle
+        let client = RaucClient::new(FailingInfo("rauc: out of memory"));
+        let error = validate_bundle(&client, "/data/update.raucb")
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, RaucError::CommandFailed { .. }),
+            "{:?}",
+            error
+        );
+
+        let client = RaucClient::new(MockBackend::new());
+        let error = validate_bundle(&client, "/data/missing.raucb")
+            .await
+            .unwrap_err();
+        assert!(matches!(error, RaucError::NoBundle), "{:?}", error);
+    }
+}
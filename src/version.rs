@@ -0,0 +1,270 @@
+use crate::error::RaucError;
+use semver::Version;
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+/// How to treat installing a bundle that is older than or equal to the installed version
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionPolicy {
+    Allow,
+    /// Install, but attach a warning to the job
+    Warn,
+    /// Refuse unless the install request is forced
+    Forbid,
+}
+
+impl FromStr for VersionPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "allow" => Ok(VersionPolicy::Allow),
+            "warn" => Ok(VersionPolicy::Warn),
+            "forbid" => Ok(VersionPolicy::Forbid),
+            other => Err(format!(
+                "Unknown version policy '{}' (expected allow, warn or forbid)",
+                other
+            )),
+        }
+    }
+}
+
+/// Comparison used for versions that are not valid semver
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FallbackScheme {
+    /// Compare the numbers in the version one by one, e.g. `2024.05.1` or `v20200703`
+    Numeric,
+    /// Compare the versions as plain strings
+    Lexical,
+    /// Treat the versions as not comparable
+    None,
+}
+
+impl FromStr for FallbackScheme {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "numeric" => Ok(FallbackScheme::Numeric),
+            "lexical" => Ok(FallbackScheme::Lexical),
+            "none" => Ok(FallbackScheme::None),
+            other => Err(format!(
+                "Unknown version scheme '{}' (expected numeric, lexical or none)",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionChange {
+    Upgrade,
+    Reinstall,
+    Downgrade,
+    /// The versions could not be compared
+    Unknown,
+}
+
+fn parse_semver(version: &str) -> Option<Version> {
+    let version = version.trim();
+    let version = version.strip_prefix(['v', 'V']).unwrap_or(version);
+    Version::parse(version).ok()
+}
+
+fn numbers(version: &str) -> Vec<u64> {
+    version
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|part| part.parse().ok())
+        .collect()
+}
+
+/// Compare two versions as semver, or with the fallback scheme if either is not semver
+pub fn compare(installed: &str, candidate: &str, fallback: FallbackScheme) -> Option<Ordering> {
+    if let (Some(installed), Some(candidate)) = (parse_semver(installed), parse_semver(candidate)) {
+        // Build metadata does not take part in semver precedence
+        let precedence = |v: &Version| (v.major, v.minor, v.patch, v.pre.clone());
+        return Some(precedence(&candidate).cmp(&precedence(&installed)));
+    }
+
+    match fallback {
+        FallbackScheme::Numeric => {
+            let (installed, candidate) = (numbers(installed), numbers(candidate));
+            if installed.is_empty() || candidate.is_empty() {
+                return None;
+            }
+            Some(candidate.cmp(&installed))
+        }
+        FallbackScheme::Lexical => Some(candidate.cmp(installed)),
+        FallbackScheme::None => None,
+    }
+}
+
+/// Version settings applied before installing a bundle
+#[derive(Debug, Clone, Copy)]
+pub struct VersionConfig {
+    pub policy: VersionPolicy,
+    pub fallback: FallbackScheme,
+}
+
+impl VersionConfig {
+    pub fn change(&self, installed: Option<&str>, candidate: &str) -> VersionChange {
+        match installed.and_then(|installed| compare(installed, candidate, self.fallback)) {
+            Some(Ordering::Greater) => VersionChange::Upgrade,
+            Some(Ordering::Equal) => VersionChange::Reinstall,
+            Some(Ordering::Less) => VersionChange::Downgrade,
+            None => VersionChange::Unknown,
+        }
+    }
+
+    /// Apply the policy to installing `candidate` over `installed`, returning warnings for the job
+    ///
+    /// Fails with `VersionNotAllowed` if the policy forbids the installation and it is not
+    /// forced. Forced installations are recorded with the requester in the warnings, which
+    /// the install history keeps.
+    pub fn check(
+        &self,
+        installed: Option<&str>,
+        candidate: &str,
+        force: bool,
+        requester: &str,
+    ) -> Result<Vec<String>, RaucError> {
+        let description = match self.change(installed, candidate) {
+            VersionChange::Upgrade | VersionChange::Unknown => return Ok(Vec::new()),
+            VersionChange::Reinstall => format!("Reinstalling version {}", candidate),
+            VersionChange::Downgrade => {
+                format!(
+                    "Downgrading from version {} to {}",
+                    installed.unwrap_or("unknown"),
+                    candidate
+                )
+            }
+        };
+
+        match self.policy {
+            VersionPolicy::Allow => Ok(Vec::new()),
+            VersionPolicy::Warn => Ok(vec![description]),
+            VersionPolicy::Forbid if force => {
+                Ok(vec![format!("{} (forced by {})", description, requester)])
+            }
+            VersionPolicy::Forbid => Err(RaucError::VersionNotAllowed(format!(
+                "{} is forbidden by the version policy, force the installation to override",
+                description
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Ordering::{Equal, Greater, Less};
+
+    #[test]
+    fn compares_versions() {
+        use FallbackScheme::{Lexical, None as Incomparable, Numeric};
+
+        // (installed, candidate, fallback, candidate compared to installed)
+        let cases = [
+            ("1.0.0", "1.0.1", Numeric, Some(Greater)),
+            ("1.2.0", "1.10.0", Lexical, Some(Greater)),
+            ("v1.0.0", "1.0.0", Incomparable, Some(Equal)),
+            ("2.0.0", "1.9.9", Numeric, Some(Less)),
+            // Pre-releases precede the release
+            ("1.0.0-rc.1", "1.0.0", Numeric, Some(Greater)),
+            ("1.0.0", "1.0.0-rc.1", Numeric, Some(Less)),
+            ("1.0.0-alpha", "1.0.0-beta", Numeric, Some(Greater)),
+            // Build metadata does not change precedence
+            ("1.0.0+build.1", "1.0.0+build.2", Numeric, Some(Equal)),
+            ("1.0.0", "1.0.0+20240501", Incomparable, Some(Equal)),
+            // Numeric fallback compares each number, lexical compares characters
+            ("2024.05.9", "2024.05.10", Numeric, Some(Greater)),
+            ("2024.05.9", "2024.05.10", Lexical, Some(Less)),
+            ("v20200703", "v20210101", Numeric, Some(Greater)),
+            ("2024.05", "2024.05.0", Numeric, Some(Greater)),
+            ("release", "2024.05", Numeric, None),
+            ("2024.05", "2024.06", Incomparable, None),
+        ];
+
+        for (installed, candidate, fallback, expected) in cases {
+            assert_eq!(
+                compare(installed, candidate, fallback),
+                expected,
+                "{} -> {} ({:?})",
+                installed,
+                candidate,
+                fallback
+            );
+        }
+    }
+
+    #[test]
+    fn applies_version_policy() {
+        use VersionPolicy::{Allow, Forbid, Warn};
+
+        let downgrade = "Downgrading from version 2.0.0 to 1.0.0";
+        // (policy, installed, candidate, force, warnings or None if refused)
+        type Case = (
+            VersionPolicy,
+            Option<&'static str>,
+            &'static str,
+            bool,
+            Option<Vec<String>>,
+        );
+        let cases: [Case; 10] = [
+            (Allow, Some("2.0.0"), "1.0.0", false, Some(vec![])),
+            (
+                Warn,
+                Some("2.0.0"),
+                "1.0.0",
+                false,
+                Some(vec![downgrade.into()]),
+            ),
+            (
+                Warn,
+                Some("1.0.0"),
+                "1.0.0+rebuild",
+                false,
+                Some(vec!["Reinstalling version 1.0.0+rebuild".into()]),
+            ),
+            (Warn, Some("1.0.0"), "2.0.0", false, Some(vec![])),
+            (Forbid, Some("2.0.0"), "1.0.0", false, None),
+            (Forbid, Some("1.0.0"), "1.0.0", false, None),
+            (
+                Forbid,
+                Some("2.0.0"),
+                "1.0.0",
+                true,
+                Some(vec![format!("{} (forced by admin)", downgrade)]),
+            ),
+            (Forbid, Some("1.0.0"), "2.0.0", false, Some(vec![])),
+            // Versions that cannot be compared are not held back
+            (Forbid, None, "1.0.0", false, Some(vec![])),
+            (Forbid, Some("release"), "1.0.0", false, Some(vec![])),
+        ];
+
+        for (policy, installed, candidate, force, expected) in cases {
+            let config = VersionConfig {
+                policy,
+                fallback: FallbackScheme::Numeric,
+            };
+            let result = config.check(installed, candidate, force, "admin");
+            match expected {
+                Some(warnings) => assert_eq!(
+                    result.unwrap(),
+                    warnings,
+                    "{:?} {:?} -> {}",
+                    policy,
+                    installed,
+                    candidate
+                ),
+                None => assert!(
+                    matches!(result, Err(RaucError::VersionNotAllowed(_))),
+                    "{:?} {:?} -> {}",
+                    policy,
+                    installed,
+                    candidate
+                ),
+            }
+        }
+    }
+}
@@ -1,15 +1,180 @@
-import type { RaucStatus, RaucBundleInfo } from '$lib/types/rauc';
+import type {
+	ApiToken,
+	CreatedApiToken,
+	RaucStatus,
+	RaucBundleInfo,
+	ApiError,
+	HealthReport,
+	InstallJob,
+	JobEvent,
+	Permissions,
+	RemoteBundle,
+	Role,
+	RollbackPlan,
+	RollbackResult,
+	SessionInfo,
+	SlotMark,
+	SlotMarked,
+	StoredBundle,
+	TokenScope,
+	UploadSession,
+	UserInfo
+} from '$lib/types/rauc';
+
+/** Extract a readable message from an API error response */
+export function errorMessage(body: string): string {
+	try {
+		const error = JSON.parse(body) as ApiError;
+		return error.stderr ? `${error.message}: ${error.stderr}` : error.message;
+	} catch {
+		return body;
+	}
+}
+
+/** Failed API request, carrying the machine-readable error code */
+export class ApiRequestError extends Error {
+	constructor(
+		public code: string,
+		message: string
+	) {
+		super(message);
+	}
+}
+
+function requestError(body: string): Error {
+	try {
+		const error = JSON.parse(body) as ApiError;
+		return new ApiRequestError(error.code, errorMessage(body));
+	} catch {
+		return new Error(body);
+	}
+}
+
+/** Current session, fails with `unauthorized` if the user needs to log in */
+export async function fetchSession(): Promise<SessionInfo> {
+	const response = await fetch('/api/auth/session');
+	if (!response.ok) {
+		throw requestError(await response.text());
+	}
+	return response.json();
+}
+
+export async function login(username: string, password: string): Promise<SessionInfo> {
+	const response = await fetch('/api/auth/login', {
+		method: 'POST',
+		headers: { 'Content-Type': 'application/json' },
+		body: JSON.stringify({ username, password })
+	});
+	if (!response.ok) {
+		throw requestError(await response.text());
+	}
+	return response.json();
+}
+
+export async function logout(): Promise<void> {
+	await fetch('/api/auth/logout', { method: 'POST' });
+}
+
+export async function fetchPermissions(): Promise<Permissions> {
+	const response = await fetch('/api/auth/permissions');
+	if (!response.ok) {
+		throw requestError(await response.text());
+	}
+	return response.json();
+}
+
+export async function fetchUsers(): Promise<UserInfo[]> {
+	const response = await fetch('/api/users');
+	if (!response.ok) {
+		throw new Error(errorMessage(await response.text()));
+	}
+	return response.json();
+}
+
+export async function createUser(
+	username: string,
+	password: string,
+	role: Role
+): Promise<UserInfo> {
+	const response = await fetch('/api/users', {
+		method: 'POST',
+		headers: { 'Content-Type': 'application/json' },
+		body: JSON.stringify({ username, password, role })
+	});
+	if (!response.ok) {
+		throw new Error(errorMessage(await response.text()));
+	}
+	return response.json();
+}
+
+/** Change the role or password of a user, fields that are not given are kept */
+export async function updateUser(
+	username: string,
+	update: { role?: Role; password?: string }
+): Promise<UserInfo> {
+	const response = await fetch(`/api/users/${encodeURIComponent(username)}`, {
+		method: 'PUT',
+		headers: { 'Content-Type': 'application/json' },
+		body: JSON.stringify(update)
+	});
+	if (!response.ok) {
+		throw new Error(errorMessage(await response.text()));
+	}
+	return response.json();
+}
+
+export async function deleteUser(username: string): Promise<UserInfo> {
+	const response = await fetch(`/api/users/${encodeURIComponent(username)}`, {
+		method: 'DELETE'
+	});
+	if (!response.ok) {
+		throw new Error(errorMessage(await response.text()));
+	}
+	return response.json();
+}
+
+export async function fetchTokens(): Promise<ApiToken[]> {
+	const response = await fetch('/api/tokens');
+	if (!response.ok) {
+		throw new Error(errorMessage(await response.text()));
+	}
+	return response.json();
+}
+
+/** Create an API token, `expiresInDays` of `null` creates one that never expires */
+export async function createToken(
+	name: string,
+	scopes: TokenScope[],
+	expiresInDays: number | null
+): Promise<CreatedApiToken> {
+	const response = await fetch('/api/tokens', {
+		method: 'POST',
+		headers: { 'Content-Type': 'application/json' },
+		body: JSON.stringify({ name, scopes, expires_in_days: expiresInDays })
+	});
+	if (!response.ok) {
+		throw new Error(errorMessage(await response.text()));
+	}
+	return response.json();
+}
+
+export async function revokeToken(id: string): Promise<ApiToken> {
+	const response = await fetch(`/api/tokens/${id}`, { method: 'DELETE' });
+	if (!response.ok) {
+		throw new Error(errorMessage(await response.text()));
+	}
+	return response.json();
+}
 
 export async function fetchStatus(): Promise<RaucStatus> {
 	const response = await fetch('/api/status');
 	if (!response.ok) {
-		const error = await response.text();
-		throw new Error(error);
+		throw new Error(errorMessage(await response.text()));
 	}
 	return response.json();
 }
 
-export async function uploadBundle(file: File): Promise<string> {
+export async function uploadBundle(file: File): Promise<StoredBundle> {
 	const formData = new FormData();
 	formData.append('file', file);
 
@@ -18,41 +183,260 @@ export async function uploadBundle(file: File): Promise<string> {
 		body: formData
 	});
 
+	if (!response.ok) {
+		throw new Error(errorMessage(await response.text()));
+	}
+	return response.json();
+}
+
+export async function fetchBundles(): Promise<StoredBundle[]> {
+	const response = await fetch('/api/bundles');
+	if (!response.ok) {
+		throw new Error(errorMessage(await response.text()));
+	}
+	return response.json();
+}
+
+export async function fetchBundle(id: string): Promise<StoredBundle> {
+	const response = await fetch(`/api/bundles/${id}`);
+	if (!response.ok) {
+		throw new Error(errorMessage(await response.text()));
+	}
+	return response.json();
+}
+
+export async function deleteBundle(id: string): Promise<StoredBundle> {
+	const response = await fetch(`/api/bundles/${id}`, { method: 'DELETE' });
+	if (!response.ok) {
+		throw new Error(errorMessage(await response.text()));
+	}
+	return response.json();
+}
+
+/** Install a stored bundle, `force` overrides a version policy forbidding it */
+export async function installBundle(id: string, force = false): Promise<InstallJob> {
+	const response = await fetch(`/api/bundles/${id}/install?force=${force}`, { method: 'POST' });
+	if (!response.ok) {
+		throw requestError(await response.text());
+	}
+	return response.json();
+}
+
+/** Inspect the latest uploaded bundle, or the bundle at a URL if one is given */
+const CHUNK_SIZE = 8 * 1024 * 1024;
+/** Consecutive failed chunks before a chunked upload gives up */
+const CHUNK_RETRIES = 10;
+const CHUNK_RETRY_DELAY_MS = 2000;
+
+/** Whether the browser can hash files, which it only allows on HTTPS or localhost */
+export function canUploadChunked(): boolean {
+	return typeof crypto !== 'undefined' && crypto.subtle !== undefined;
+}
+
+async function sha256Hex(file: File): Promise<string> {
+	const digest = await crypto.subtle.digest('SHA-256', await file.arrayBuffer());
+	return Array.from(new Uint8Array(digest), (byte) => byte.toString(16).padStart(2, '0')).join(
+		''
+	);
+}
+
+export async function fetchUploadSession(id: string): Promise<UploadSession> {
+	const response = await fetch(`/api/uploads/${id}`);
+	if (!response.ok) {
+		throw new Error(errorMessage(await response.text()));
+	}
+	return response.json();
+}
+
+/**
+ * Upload a bundle in chunks, resuming where the server left off when a chunk fails.
+ * `onProgress` is called with the number of bytes the server has received.
+ */
+export async function uploadBundleChunked(
+	file: File,
+	onProgress: (received: number) => void
+): Promise<StoredBundle> {
+	const sha256 = await sha256Hex(file);
+	const created = await fetch('/api/uploads', {
+		method: 'POST',
+		headers: { 'Content-Type': 'application/json' },
+		body: JSON.stringify({ filename: file.name, size: file.size, sha256 })
+	});
+	if (!created.ok) {
+		throw new Error(errorMessage(await created.text()));
+	}
+	const { id } = (await created.json()) as UploadSession;
+
+	let received = 0;
+	let failures = 0;
+	onProgress(received);
+	while (received < file.size) {
+		try {
+			const response = await fetch(`/api/uploads/${id}?offset=${received}`, {
+				method: 'PUT',
+				body: file.slice(received, received + CHUNK_SIZE)
+			});
+			if (!response.ok) {
+				throw new Error(errorMessage(await response.text()));
+			}
+			received = ((await response.json()) as UploadSession).received;
+			failures = 0;
+		} catch (err) {
+			failures += 1;
+			if (failures > CHUNK_RETRIES) {
+				throw err;
+			}
+			await new Promise((resolve) => setTimeout(resolve, CHUNK_RETRY_DELAY_MS));
+			// Part of the chunk may have arrived before the connection dropped
+			received = await fetchUploadSession(id).then(
+				(session) => session.received,
+				() => received
+			);
+		}
+		onProgress(received);
+	}
+
+	const response = await fetch(`/api/uploads/${id}/finish`, { method: 'POST' });
+	if (!response.ok) {
+		throw new Error(errorMessage(await response.text()));
+	}
+	return response.json();
+}
+
+export async function fetchBundleInfo(remote?: RemoteBundle): Promise<RaucBundleInfo> {
+	const response = remote
+		? await fetch('/api/bundle-info-url', {
+				method: 'POST',
+				headers: { 'Content-Type': 'application/json' },
+				body: JSON.stringify(remote)
+			})
+		: await fetch('/api/bundle-info');
+	if (!response.ok) {
+		throw new Error(errorMessage(await response.text()));
+	}
+	return response.json();
+}
+
+/** Install the latest uploaded bundle, or stream the bundle at a URL if one is given */
+export async function startInstall(remote?: RemoteBundle, force = false): Promise<InstallJob> {
+	const response = remote
+		? await fetch('/api/install-url', {
+				method: 'POST',
+				headers: { 'Content-Type': 'application/json' },
+				body: JSON.stringify({ ...remote, force })
+			})
+		: await fetch(`/api/install?force=${force}`, { method: 'POST' });
+	if (!response.ok) {
+		throw requestError(await response.text());
+	}
+	return response.json();
+}
+
+export async function fetchJobs(): Promise<InstallJob[]> {
+	const response = await fetch('/api/jobs');
+	if (!response.ok) {
+		throw new Error(errorMessage(await response.text()));
+	}
+	return response.json();
+}
+
+/** Finished installations, most recent first */
+export async function fetchHistory(): Promise<InstallJob[]> {
+	const response = await fetch('/api/history');
+	if (!response.ok) {
+		throw new Error(errorMessage(await response.text()));
+	}
+	return response.json();
+}
+
+/** Full output of a finished installation */
+export async function fetchHistoryLog(id: string): Promise<string> {
+	const response = await fetch(`/api/history/${id}/log`);
 	const result = await response.text();
 	if (!response.ok) {
-		throw new Error(result);
+		throw new Error(errorMessage(result));
 	}
 	return result;
 }
 
-export async function fetchBundleInfo(): Promise<RaucBundleInfo> {
-	const response = await fetch('/api/bundle-info');
+/**
+ * Watch the events of an install job, replaying everything emitted so far.
+ * Resolves once the job has completed or failed.
+ */
+export function watchJob(id: string, onEvent: (event: JobEvent) => void): Promise<void> {
+	return new Promise((resolve, reject) => {
+		const source = new EventSource(`/api/jobs/${id}/events`);
+		let finished = false;
+
+		for (const name of ['log', 'progress', 'step', 'completed', 'failed']) {
+			source.addEventListener(name, (message) => {
+				const event = JSON.parse((message as MessageEvent).data) as JobEvent;
+				onEvent(event);
+				if (event.type === 'completed' || event.type === 'failed') {
+					finished = true;
+					source.close();
+					resolve();
+				}
+			});
+		}
+
+		source.addEventListener('error', () => {
+			// EventSource reconnects on its own while the job is still running
+			if (!finished && source.readyState === EventSource.CLOSED) {
+				reject(new Error('Lost connection to installation job'));
+			}
+		});
+	});
+}
+
+/** Mark a slot name, `booted` or `other` as good, bad or active */
+export async function markSlot(slot: string, mark: SlotMark): Promise<SlotMarked> {
+	const response = await fetch(`/api/slots/${encodeURIComponent(slot)}/mark`, {
+		method: 'POST',
+		headers: { 'Content-Type': 'application/json' },
+		body: JSON.stringify({ mark })
+	});
 	if (!response.ok) {
-		const error = await response.text();
-		throw new Error(error);
+		throw new Error(errorMessage(await response.text()));
 	}
 	return response.json();
 }
 
-export async function* installBundle(): AsyncGenerator<string, void, unknown> {
-	const response = await fetch('/api/install');
-	if (!response.body) {
-		throw new Error('No response body');
+export async function fetchHealth(): Promise<HealthReport> {
+	const response = await fetch('/api/health');
+	if (!response.ok) {
+		throw new Error(errorMessage(await response.text()));
 	}
+	return response.json();
+}
 
-	const reader = response.body.getReader();
-	const decoder = new TextDecoder();
-
-	while (true) {
-		const { done, value } = await reader.read();
-		if (done) break;
+/** Slot a rollback would boot, fails if there is nothing to roll back to */
+export async function fetchRollbackPlan(): Promise<RollbackPlan> {
+	const response = await fetch('/api/rollback');
+	if (!response.ok) {
+		throw new Error(errorMessage(await response.text()));
+	}
+	return response.json();
+}
 
-		const text = decoder.decode(value, { stream: true });
-		yield text;
+/** Boot the other slot, optionally marking the booted one bad, and reboot */
+export async function rollback(markBad: boolean): Promise<RollbackResult> {
+	const response = await fetch('/api/rollback', {
+		method: 'POST',
+		headers: { 'Content-Type': 'application/json' },
+		body: JSON.stringify({ mark_bad: markBad })
+	});
+	if (!response.ok) {
+		throw new Error(errorMessage(await response.text()));
 	}
+	return response.json();
 }
 
 export async function rebootSystem(): Promise<string> {
 	const response = await fetch('/api/reboot', { method: 'POST' });
-	return response.text();
+	const result = await response.text();
+	if (!response.ok) {
+		throw new Error(errorMessage(result));
+	}
+	return result;
 }
@@ -2,3 +2,10 @@ export { default as SystemStatus } from './SystemStatus.svelte';
 export { default as BundleUpload } from './BundleUpload.svelte';
 export { default as BundleInfo } from './BundleInfo.svelte';
 export { default as InstallProgress } from './InstallProgress.svelte';
+export { default as BundleList } from './BundleList.svelte';
+export { default as Rollback } from './Rollback.svelte';
+export { default as HealthChecks } from './HealthChecks.svelte';
+export { default as InstallHistory } from './InstallHistory.svelte';
+export { default as Login } from './Login.svelte';
+export { default as ApiTokens } from './ApiTokens.svelte';
+export { default as Users } from './Users.svelte';
@@ -1,38 +1,282 @@
+export interface RaucSlotEvent {
+	timestamp?: string | null;
+	count?: number | null;
+}
+
+export interface RaucSlotStatus {
+	bundle?: {
+		compatible?: string | null;
+		version?: string | null;
+		description?: string | null;
+		build?: string | null;
+		hash?: string | null;
+	} | null;
+	checksum?: { sha256?: string | null; size?: number | null } | null;
+	installed?: RaucSlotEvent | null;
+	activated?: RaucSlotEvent | null;
+	status?: string | null;
+}
+
 export interface RaucSlot {
-	state: string;
-	boot_status?: string;
+	state: 'booted' | 'active' | 'inactive' | 'unknown';
+	boot_status?: 'good' | 'bad' | 'unknown' | null;
 	device: string;
 	type: string;
-	bootname: string;
+	bootname?: string | null;
 	class: string;
-	mountpoint?: string;
+	parent?: string | null;
+	mountpoint?: string | null;
+	slot_status?: RaucSlotStatus | null;
+}
+
+export interface RaucArtifactRepository {
+	name: string;
+	description?: string | null;
+	path?: string | null;
+	type?: string | null;
 }
 
 export interface RaucStatus {
 	compatible: string;
 	variant?: string;
 	booted: string;
-	boot_primary: string;
+	boot_primary?: string | null;
 	slots: Record<string, RaucSlot>[];
-	'artifact-repositories': unknown[];
+	'artifact-repositories': RaucArtifactRepository[];
+	reboot: RebootState;
+}
+
+export interface PendingReboot {
+	slot: string;
+	version?: string | null;
+	job_id?: string | null;
+	installed_at: number;
+	boot_id?: string | null;
+}
+
+export interface BootVerification {
+	expected_slot: string;
+	booted_slot?: string | null;
+	success: boolean;
+	version?: string | null;
+	job_id?: string | null;
+	verified_at: number;
+}
+
+export interface RebootState {
+	pending?: PendingReboot | null;
+	last_boot?: BootVerification | null;
 }
 
 export interface RaucBundleImage {
-	filename?: string;
+	variant?: string | null;
+	filename?: string | null;
 	type?: string;
-	size?: number;
-	checksum?: string;
+	size?: number | null;
+	checksum?: string | null;
+	hooks?: string[];
+	adaptive?: string[];
 }
 
 export interface RaucBundleInfo {
 	compatible: string;
 	version: string;
-	description?: string;
-	build: string;
-	format: string;
-	hooks?: unknown[];
-	hash?: string;
+	description?: string | null;
+	build?: string | null;
+	format?: string | null;
+	hooks?: string[];
+	hash?: string | null;
 	images?: Record<string, RaucBundleImage>[];
+	meta?: Record<string, Record<string, string>>;
+}
+
+export type StepStatus = 'running' | 'done' | 'failed' | 'skipped';
+
+export interface InstallStep {
+	name: string;
+	status: StepStatus;
+	started_at: number;
+	duration_ms?: number | null;
+	steps: InstallStep[];
+}
+
+export interface InstallProgress {
+	percentage: number;
+	message?: string | null;
+	steps: InstallStep[];
+}
+
+/** Bundle kept in the server's repository */
+export interface StoredBundle {
+	id: string;
+	sha256: string;
+	filename: string;
+	size: number;
+	uploaded_at: number;
+	uploaded_by: string;
+	/** Output of `rauc info` at upload time */
+	info: RaucBundleInfo;
+	validation: BundleValidation;
+}
+
+export type CheckStatus = 'passed' | 'failed' | 'skipped';
+
+export interface ValidationCheck {
+	name: 'format' | 'signature' | 'compatible';
+	status: CheckStatus;
+	message: string;
+	stderr?: string | null;
+}
+
+/** Verdict on an uploaded bundle */
+export interface BundleValidation {
+	valid: boolean;
+	checks: ValidationCheck[];
+}
+
+/** State of a chunked upload */
+export interface UploadSession {
+	id: string;
+	filename: string;
+	size: number;
+	sha256: string;
+	/** Bytes received from the start of the file, the offset of the next chunk */
+	received: number;
+	created_at: number;
+	updated_at: number;
+}
+
+/** Bundle streamed by rauc from an HTTP(S) server */
+export interface RemoteBundle {
+	url: string;
+	/** Extra request headers, e.g. `Authorization: Bearer <token>` */
+	headers?: string[];
+	/** Install even if the version policy forbids it */
+	force?: boolean;
+}
+
+export interface InstallJob {
+	id: string;
+	state: 'queued' | 'running' | 'succeeded' | 'failed';
+	/** Path or URL of the installed bundle */
+	bundle: string;
+	bundle_info?: RaucBundleInfo | null;
+	/** Client that started the installation */
+	started_by: string;
+	/** Address the installation was requested from, if known */
+	client_ip?: string | null;
+	created_at: number;
+	started_at?: number | null;
+	finished_at?: number | null;
+	error?: string | null;
+	exit_status?: number | null;
+	/** Slots written by the installation */
+	slots: string[];
+	/** Version policy warnings, e.g. about a downgrade */
+	warnings: string[];
+	progress: InstallProgress;
+}
+
+export type JobEvent =
+	| { type: 'log'; stream: 'stdout' | 'stderr'; line: string }
+	| { type: 'progress'; percentage: number; message: string }
+	| { type: 'step'; name: string; status: StepStatus; depth: number }
+	| { type: 'completed' }
+	| { type: 'failed'; error: string };
+
+export type SlotMark = 'good' | 'bad' | 'active';
+
+export interface SlotMarked {
+	slot: string;
+	mark: SlotMark;
+	/** Message reported by RAUC */
+	message: string;
+}
+
+export interface RollbackPlan {
+	booted_slot: string;
+	booted_version?: string | null;
+	/** Slot that will be booted after the rollback */
+	target_slot: string;
+	target_version: string;
+	target_bootname?: string | null;
+}
+
+export interface RollbackResult {
+	plan: RollbackPlan;
+	messages: string[];
+}
+
+export interface HealthCheckResult {
+	name: string;
+	passed: boolean;
+	message: string;
+	duration_ms: number;
+}
+
+export interface HealthReport {
+	state: 'disabled' | 'pending' | 'running' | 'passed' | 'failed';
+	started_at?: number | null;
+	finished_at?: number | null;
+	checks: HealthCheckResult[];
+	/** Messages of the slot mark and reboot done in response to the checks */
+	actions: string[];
+	error?: string | null;
+}
+
+export interface ApiError {
+	code: string;
+	message: string;
+	stderr?: string | null;
+	/** Set for `bundle_rejected` errors */
+	validation?: BundleValidation;
+}
+
+export interface SessionInfo {
+	/** Not set if authentication is disabled */
+	username?: string | null;
+	expires_at?: number | null;
+	/** Subject of the client certificate identifying the user, who cannot log out */
+	client_certificate?: string | null;
+}
+
+export type TokenScope = 'read' | 'upload' | 'install' | 'reboot' | 'slots';
+
+/** Managing users and API tokens is never granted to API tokens */
+export type Permission = TokenScope | 'users' | 'tokens';
+
+export type Role = 'viewer' | 'operator' | 'admin';
+
+/** What the current user or API token may do */
+export interface Permissions {
+	name: string;
+	/** Not set for API tokens and if authentication is disabled */
+	role?: Role | null;
+	permissions: Permission[];
+}
+
+export interface UserInfo {
+	username: string;
+	role: Role;
+	created_by?: string | null;
+	created_at?: number | null;
+	/** The administrator configured on the server, which cannot be changed here */
+	configured: boolean;
+}
+
+export interface ApiToken {
+	id: string;
+	name: string;
+	scopes: TokenScope[];
+	created_by: string;
+	created_at: number;
+	expires_at?: number | null;
+	last_used_at?: number | null;
+}
+
+/** Newly created token, the secret is only returned once */
+export interface CreatedApiToken extends ApiToken {
+	token: string;
 }
 
 export interface AppConfig {